@@ -0,0 +1,490 @@
+use actix_web::{HttpResponse, delete, get, post, put, web};
+use log::error;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::{
+        jwt::{AuthUser, MaybeAuthUser},
+        signed_url::{sign_memory, unsigned_url},
+    },
+    conf::config::Config,
+    solana::solana::{TitleContent, solana_http_query},
+    store::{
+        models::{Album, Memory, Role, Visibility},
+        store::{Store, read_store, sort_by_block_time, write_store},
+    },
+    utils::{
+        page::{Page, PageQuery},
+        result::{CqResult, Nothing},
+    },
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AlbumRequest {
+    title: String,
+    cover: Option<String>,
+    visibility: Option<Visibility>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AlbumUpdate {
+    title: Option<String>,
+    cover: Option<String>,
+    visibility: Option<Visibility>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct OwnerQuery {
+    owner: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AuthorQuery {
+    author: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MemoryRequest {
+    image: String,
+    title: String,
+    content: String,
+}
+
+#[derive(Debug, Serialize)]
+struct MemoryResponse {
+    #[serde(flatten)]
+    memory: Memory,
+    /// The CHRO memo for the author to sign and send; the indexer confirms
+    /// this memory when it finds it.
+    memo: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MemoryUpdate {
+    album_id: Option<String>,
+    image: Option<String>,
+    title: Option<String>,
+    content: Option<String>,
+}
+
+pub(crate) fn not_found(what: &str) -> HttpResponse {
+    HttpResponse::NotFound().json(CqResult::<Nothing>::error(
+        500,
+        &format!("{} not found", what),
+    ))
+}
+
+pub(crate) fn forbidden() -> HttpResponse {
+    HttpResponse::Forbidden().json(CqResult::<Nothing>::error(
+        403,
+        "not allowed for this album",
+    ))
+}
+
+/// Why a store operation was refused.
+pub(crate) enum Denied {
+    NotFound(&'static str),
+    Forbidden,
+}
+
+impl Denied {
+    pub(crate) fn response(&self) -> HttpResponse {
+        match self {
+            Denied::NotFound(what) => not_found(what),
+            Denied::Forbidden => forbidden(),
+        }
+    }
+}
+
+#[post("/albums")]
+async fn create_album(user: AuthUser, req: web::Json<AlbumRequest>) -> HttpResponse {
+    if req.title.trim().is_empty() {
+        return HttpResponse::BadRequest()
+            .json(CqResult::<Nothing>::error(500, "title can not be empty"));
+    }
+    let now = chrono::Utc::now().timestamp();
+    let album = Album {
+        id: uuid::Uuid::new_v4().to_string(),
+        owner: user.pubkey,
+        title: req.title.clone(),
+        cover: req.cover.clone(),
+        visibility: req.visibility.unwrap_or_default(),
+        members: Vec::new(),
+        member_log: Vec::new(),
+        created_at: now,
+        updated_at: now,
+    };
+    write_store(|store| store.albums.insert(album.id.clone(), album.clone())).await;
+    HttpResponse::Ok().json(CqResult::success(album))
+}
+
+/// Lists public albums and those the caller is a member of.
+#[get("/albums")]
+async fn list_albums(
+    user: MaybeAuthUser,
+    owner: web::Query<OwnerQuery>,
+    page: web::Query<PageQuery>,
+) -> HttpResponse {
+    let viewer = user.pubkey.as_deref();
+    let mut albums = read_store(|store| {
+        store
+            .albums
+            .values()
+            .filter(|a| owner.owner.as_ref().is_none_or(|owner| &a.owner == owner))
+            .filter(|a| a.visibility == Visibility::Public || a.has_role(viewer, Role::Viewer))
+            .cloned()
+            .collect::<Vec<_>>()
+    })
+    .await;
+    albums.sort_by(|a, b| b.created_at.cmp(&a.created_at).then(a.id.cmp(&b.id)));
+    if page.ascending() {
+        albums.reverse();
+    }
+    HttpResponse::Ok().json(CqResult::success(Page::paginate(albums, &page)))
+}
+
+#[get("/albums/{album_id}")]
+async fn get_album(user: MaybeAuthUser, path: web::Path<String>) -> HttpResponse {
+    let album_id = path.into_inner();
+    match read_store(|store| store.albums.get(&album_id).cloned()).await {
+        Some(album) if album.visible_to(user.pubkey.as_deref()) => {
+            HttpResponse::Ok().json(CqResult::success(album))
+        }
+        Some(_) => forbidden(),
+        None => not_found("album"),
+    }
+}
+
+/// Editors may change title and cover; only the owner changes visibility.
+#[put("/albums/{album_id}")]
+async fn update_album(
+    user: AuthUser,
+    path: web::Path<String>,
+    req: web::Json<AlbumUpdate>,
+) -> HttpResponse {
+    let album_id = path.into_inner();
+    let updated = write_store(|store| {
+        let album = store
+            .albums
+            .get_mut(&album_id)
+            .ok_or(Denied::NotFound("album"))?;
+        let required = if req.visibility.is_some() {
+            Role::Owner
+        } else {
+            Role::Editor
+        };
+        if !album.has_role(Some(&user.pubkey), required) {
+            return Err(Denied::Forbidden);
+        }
+        if let Some(title) = req.title.as_ref().filter(|t| !t.trim().is_empty()) {
+            album.title = title.clone();
+        }
+        if req.cover.is_some() {
+            album.cover = req.cover.clone();
+        }
+        if let Some(visibility) = req.visibility {
+            album.visibility = visibility;
+        }
+        album.updated_at = chrono::Utc::now().timestamp();
+        Ok(album.clone())
+    })
+    .await;
+    match updated {
+        Ok(album) => HttpResponse::Ok().json(CqResult::success(album)),
+        Err(denied) => denied.response(),
+    }
+}
+
+/// Deletes an album together with its memories, so a private album's
+/// memories never become public.
+#[delete("/albums/{album_id}")]
+async fn delete_album(user: AuthUser, path: web::Path<String>) -> HttpResponse {
+    let album_id = path.into_inner();
+    let removed = write_store(|store| {
+        let album = store
+            .albums
+            .get(&album_id)
+            .ok_or(Denied::NotFound("album"))?;
+        if album.owner != user.pubkey {
+            return Err(Denied::Forbidden);
+        }
+        let removed = store
+            .albums
+            .remove(&album_id)
+            .ok_or(Denied::NotFound("album"))?;
+        store.invitations.retain(|_, i| i.album_id != album_id);
+        let memory_ids = store
+            .memories
+            .values()
+            .filter(|m| m.album_id.as_deref() == Some(album_id.as_str()))
+            .map(|m| m.id.clone())
+            .collect::<Vec<_>>();
+        for memory_id in memory_ids {
+            store.remove_memory(&memory_id);
+        }
+        Ok(removed)
+    })
+    .await;
+    match removed {
+        Ok(album) => HttpResponse::Ok().json(CqResult::success(album)),
+        Err(denied) => denied.response(),
+    }
+}
+
+/// Signs the file URLs of a memory the public can't see for `viewer`.
+pub(crate) fn signed_memory(
+    config: &Config,
+    store: &Store,
+    mut memory: Memory,
+    viewer: Option<&str>,
+) -> Memory {
+    let public = store.memory_visible_to(&memory, None);
+    sign_memory(config, &mut memory, public, viewer);
+    memory
+}
+
+#[post("/albums/{album_id}/memories")]
+async fn add_memory(
+    config: web::Data<Config>,
+    user: AuthUser,
+    path: web::Path<String>,
+    req: web::Json<MemoryRequest>,
+) -> HttpResponse {
+    let album_id = path.into_inner();
+    if req.image.trim().is_empty() || req.title.trim().is_empty() || req.content.trim().is_empty() {
+        return HttpResponse::BadRequest().json(CqResult::<Nothing>::error(
+            500,
+            "image title content can not be empty",
+        ));
+    }
+    let memory = Memory {
+        id: uuid::Uuid::new_v4().to_string(),
+        album_id: Some(album_id.clone()),
+        author: user.pubkey.clone(),
+        image: unsigned_url(&config, &req.image),
+        image_sha256: None,
+        title: req.title.clone(),
+        content: req.content.clone(),
+        // set by the indexer once the memo carrying the memory id is on chain
+        tx_signature: None,
+        slot: None,
+        block_time: None,
+        created_at: chrono::Utc::now().timestamp(),
+        like_count: 0,
+        comment_count: 0,
+        video: None,
+    };
+    let added = write_store(|store| {
+        let album = store
+            .albums
+            .get(&album_id)
+            .ok_or(Denied::NotFound("album"))?;
+        if !album.has_role(Some(&user.pubkey), Role::Editor) {
+            return Err(Denied::Forbidden);
+        }
+        let mut memory = memory;
+        memory.video = store.video_for(&memory.image);
+        store.memories.insert(memory.id.clone(), memory.clone());
+        let memo = TitleContent {
+            p: "CHRO".to_string(),
+            uri: memory.image.clone(),
+            title: memory.title.clone(),
+            content: memory.content.clone(),
+            album: memory.album_id.clone(),
+            id: Some(memory.id.clone()),
+        };
+        Ok(MemoryResponse {
+            memo: serde_json::to_string(&memo).unwrap_or_default(),
+            memory: signed_memory(&config, store, memory, Some(&user.pubkey)),
+        })
+    })
+    .await;
+    match added {
+        Ok(memory) => HttpResponse::Ok().json(CqResult::success(memory)),
+        Err(denied) => denied.response(),
+    }
+}
+
+#[get("/albums/{album_id}/memories")]
+async fn list_memories(
+    config: web::Data<Config>,
+    user: MaybeAuthUser,
+    path: web::Path<String>,
+    page: web::Query<PageQuery>,
+) -> HttpResponse {
+    let album_id = path.into_inner();
+    let memories = read_store(|store| {
+        let album = store
+            .albums
+            .get(&album_id)
+            .ok_or(Denied::NotFound("album"))?;
+        if !album.visible_to(user.pubkey.as_deref()) {
+            return Err(Denied::Forbidden);
+        }
+        Ok(store
+            .album_memories(&album_id, page.ascending())
+            .into_iter()
+            .map(|m| signed_memory(&config, store, m, user.pubkey.as_deref()))
+            .collect::<Vec<_>>())
+    })
+    .await;
+    match memories {
+        Ok(memories) => HttpResponse::Ok().json(CqResult::success(Page::paginate(memories, &page))),
+        Err(denied) => denied.response(),
+    }
+}
+
+#[get("/memories")]
+async fn list_author_memories(
+    config: web::Data<Config>,
+    user: MaybeAuthUser,
+    author: web::Query<AuthorQuery>,
+    page: web::Query<PageQuery>,
+) -> HttpResponse {
+    let mut memories = read_store(|store| {
+        store
+            .memories
+            .values()
+            .filter(|m| m.author == author.author)
+            .filter(|m| store.memory_visible_to(m, user.pubkey.as_deref()))
+            .map(|m| signed_memory(&config, store, m.clone(), user.pubkey.as_deref()))
+            .collect::<Vec<_>>()
+    })
+    .await;
+    sort_by_block_time(&mut memories, page.ascending());
+    HttpResponse::Ok().json(CqResult::success(Page::paginate(memories, &page)))
+}
+
+#[get("/memories/{memory_id}")]
+async fn get_memory(
+    config: web::Data<Config>,
+    user: MaybeAuthUser,
+    path: web::Path<String>,
+) -> HttpResponse {
+    let memory_id = path.into_inner();
+    let memory = read_store(|store| {
+        let memory = store
+            .memories
+            .get(&memory_id)
+            .ok_or(Denied::NotFound("memory"))?;
+        if !store.memory_visible_to(memory, user.pubkey.as_deref()) {
+            return Err(Denied::Forbidden);
+        }
+        Ok(signed_memory(
+            &config,
+            store,
+            memory.clone(),
+            user.pubkey.as_deref(),
+        ))
+    })
+    .await;
+    match memory {
+        Ok(memory) => HttpResponse::Ok().json(CqResult::success(memory)),
+        Err(denied) => denied.response(),
+    }
+}
+
+/// The author or an editor of the memory's album may change it. Moving it into
+/// an album needs editor rights there too.
+#[put("/memories/{memory_id}")]
+async fn update_memory(
+    config: web::Data<Config>,
+    user: AuthUser,
+    path: web::Path<String>,
+    req: web::Json<MemoryUpdate>,
+) -> HttpResponse {
+    let memory_id = path.into_inner();
+    let updated = write_store(|store| {
+        let memory = store
+            .memories
+            .get(&memory_id)
+            .ok_or(Denied::NotFound("memory"))?;
+        if !store.can_edit_memory(memory, &user.pubkey) {
+            return Err(Denied::Forbidden);
+        }
+        if let Some(album_id) = &req.album_id {
+            let album = store
+                .albums
+                .get(album_id)
+                .ok_or(Denied::NotFound("album"))?;
+            if !album.has_role(Some(&user.pubkey), Role::Editor) {
+                return Err(Denied::Forbidden);
+            }
+        }
+        let image = req
+            .image
+            .as_ref()
+            .filter(|v| !v.trim().is_empty())
+            .map(|image| unsigned_url(&config, image));
+        let video = image.as_ref().and_then(|image| store.video_for(image));
+        let memory = store
+            .memories
+            .get_mut(&memory_id)
+            .ok_or(Denied::NotFound("memory"))?;
+        if req.album_id.is_some() {
+            memory.album_id = req.album_id.clone();
+        }
+        if let Some(image) = image {
+            memory.image = image;
+            memory.video = video;
+        }
+        if let Some(title) = req.title.as_ref().filter(|v| !v.trim().is_empty()) {
+            memory.title = title.clone();
+        }
+        if let Some(content) = req.content.as_ref().filter(|v| !v.trim().is_empty()) {
+            memory.content = content.clone();
+        }
+        let memory = memory.clone();
+        Ok(signed_memory(&config, store, memory, Some(&user.pubkey)))
+    })
+    .await;
+    match updated {
+        Ok(memory) => HttpResponse::Ok().json(CqResult::success(memory)),
+        Err(denied) => denied.response(),
+    }
+}
+
+#[delete("/memories/{memory_id}")]
+async fn delete_memory(user: AuthUser, path: web::Path<String>) -> HttpResponse {
+    let memory_id = path.into_inner();
+    let removed = write_store(|store| {
+        let memory = store
+            .memories
+            .get(&memory_id)
+            .ok_or(Denied::NotFound("memory"))?;
+        if !store.can_edit_memory(memory, &user.pubkey) {
+            return Err(Denied::Forbidden);
+        }
+        store
+            .remove_memory(&memory_id)
+            .ok_or(Denied::NotFound("memory"))
+    })
+    .await;
+    match removed {
+        Ok(memory) => HttpResponse::Ok().json(CqResult::success(memory)),
+        Err(denied) => denied.response(),
+    }
+}
+
+/// Indexes a CHRO memo transaction into a memory, membership change or comment.
+#[post("/index/{signature}")]
+async fn index_memory(
+    config: web::Data<crate::conf::config::Config>,
+    path: web::Path<String>,
+) -> HttpResponse {
+    let signature = path.into_inner();
+    match solana_http_query(&signature, &config).await {
+        Ok(Some(item)) => HttpResponse::Ok().json(CqResult::success(item)),
+        Ok(None) => HttpResponse::BadRequest().json(CqResult::<Nothing>::error(
+            500,
+            "transaction carries no CHRO memory",
+        )),
+        Err(e) => {
+            error!("{} ERROR!!!", e);
+            HttpResponse::BadRequest().json(CqResult::<Nothing>::error(
+                500,
+                &format!("index transaction failed: {}", e),
+            ))
+        }
+    }
+}
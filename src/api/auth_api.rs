@@ -0,0 +1,78 @@
+use std::str::FromStr;
+
+use actix_web::{HttpResponse, post, web};
+use log::error;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+
+use crate::{
+    auth::jwt::{issue_challenge, issue_token, take_challenge},
+    utils::result::{CqResult, Nothing},
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ChallengeRequest {
+    pubkey: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct LoginRequest {
+    pubkey: String,
+    /// The nonce the challenge was issued with.
+    nonce: String,
+    /// Base58 ed25519 signature of the challenge message.
+    signature: String,
+}
+
+#[post("/auth/challenge")]
+async fn challenge(req: web::Json<ChallengeRequest>) -> HttpResponse {
+    if Pubkey::from_str(req.pubkey.trim()).is_err() {
+        return HttpResponse::BadRequest()
+            .json(CqResult::<Nothing>::error(500, "pubkey is not valid"));
+    }
+    match issue_challenge(req.pubkey.trim()).await {
+        Some((nonce, message)) => HttpResponse::Ok().json(CqResult::success(json!({
+            "nonce": nonce,
+            "message": message,
+        }))),
+        None => HttpResponse::TooManyRequests().json(CqResult::<Nothing>::error(
+            429,
+            "too many pending sign-ins, try again later",
+        )),
+    }
+}
+
+/// Exchanges a wallet signature of the challenge for a bearer token.
+#[post("/auth/login")]
+async fn login(
+    config: web::Data<crate::conf::config::Config>,
+    req: web::Json<LoginRequest>,
+) -> HttpResponse {
+    let pubkey = req.pubkey.trim();
+    let (Ok(key), Ok(signature)) = (
+        Pubkey::from_str(pubkey),
+        Signature::from_str(req.signature.trim()),
+    ) else {
+        return HttpResponse::BadRequest().json(CqResult::<Nothing>::error(
+            500,
+            "pubkey or signature is not valid",
+        ));
+    };
+    let Some(message) = take_challenge(req.nonce.trim(), pubkey).await else {
+        return HttpResponse::Unauthorized()
+            .json(CqResult::<Nothing>::error(401, "no pending challenge"));
+    };
+    if !signature.verify(key.as_ref(), message.as_bytes()) {
+        return HttpResponse::Unauthorized()
+            .json(CqResult::<Nothing>::error(401, "signature does not match"));
+    }
+    match issue_token(&config, pubkey) {
+        Ok(token) => HttpResponse::Ok().json(CqResult::success(json!({ "token": token }))),
+        Err(e) => {
+            error!("{} ERROR!!!", e);
+            HttpResponse::InternalServerError()
+                .json(CqResult::<Nothing>::error(500, "issue token failed"))
+        }
+    }
+}
@@ -0,0 +1,306 @@
+use std::collections::HashMap;
+
+use actix_web::{HttpResponse, delete, get, post, put, web};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    api::album_api::Denied,
+    auth::jwt::{AuthUser, MaybeAuthUser},
+    moderation::moderation::{Verdict, moderate},
+    store::{
+        models::Comment,
+        store::{read_store, write_store},
+    },
+    utils::{
+        page::{Page, PageQuery},
+        result::{CqResult, Nothing},
+    },
+};
+
+const MAX_COMMENT_CHARS: usize = 1000;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CommentRequest {
+    body: String,
+    parent_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ModerationRequest {
+    hidden: bool,
+}
+
+/// A comment with its replies. Hidden and deleted comments keep their place
+/// in the thread with an empty body while they have replies.
+#[derive(Debug, Serialize)]
+struct CommentThread {
+    #[serde(flatten)]
+    comment: Comment,
+    replies: Vec<CommentThread>,
+}
+
+#[derive(Debug, Serialize)]
+struct CommentResponse {
+    comment: Comment,
+    /// The memo anchoring the comment on chain, when comment memo ops are enabled.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    memo: Option<String>,
+}
+
+async fn set_like(user: &AuthUser, memory_id: String, liked: bool) -> HttpResponse {
+    let updated = write_store(|store| {
+        let memory = store
+            .memories
+            .get(&memory_id)
+            .ok_or(Denied::NotFound("memory"))?;
+        if !store.memory_visible_to(memory, Some(&user.pubkey)) {
+            return Err(Denied::Forbidden);
+        }
+        store
+            .set_like(&memory_id, &user.pubkey, liked)
+            .ok_or(Denied::NotFound("memory"))
+    })
+    .await;
+    match updated {
+        Ok(like_count) => HttpResponse::Ok().json(CqResult::success(json!({
+            "liked": liked,
+            "like_count": like_count,
+        }))),
+        Err(denied) => denied.response(),
+    }
+}
+
+#[post("/memories/{memory_id}/like")]
+async fn like(user: AuthUser, path: web::Path<String>) -> HttpResponse {
+    set_like(&user, path.into_inner(), true).await
+}
+
+#[delete("/memories/{memory_id}/like")]
+async fn unlike(user: AuthUser, path: web::Path<String>) -> HttpResponse {
+    set_like(&user, path.into_inner(), false).await
+}
+
+#[get("/memories/{memory_id}/likes")]
+async fn fetch_likes(user: MaybeAuthUser, path: web::Path<String>) -> HttpResponse {
+    let memory_id = path.into_inner();
+    let likes = read_store(|store| {
+        let memory = store
+            .memories
+            .get(&memory_id)
+            .ok_or(Denied::NotFound("memory"))?;
+        if !store.memory_visible_to(memory, user.pubkey.as_deref()) {
+            return Err(Denied::Forbidden);
+        }
+        Ok(json!({
+            "like_count": memory.like_count,
+            "liked": user
+                .pubkey
+                .as_deref()
+                .is_some_and(|pubkey| store.liked_by(&memory_id, pubkey)),
+        }))
+    })
+    .await;
+    match likes {
+        Ok(likes) => HttpResponse::Ok().json(CqResult::success(likes)),
+        Err(denied) => denied.response(),
+    }
+}
+
+/// Posts a comment or a reply. Moderation hooks may hide or reject it.
+#[post("/memories/{memory_id}/comments")]
+async fn add_comment(
+    config: web::Data<crate::conf::config::Config>,
+    user: AuthUser,
+    path: web::Path<String>,
+    req: web::Json<CommentRequest>,
+) -> HttpResponse {
+    let memory_id = path.into_inner();
+    let body = req.body.trim();
+    if body.is_empty() || body.chars().count() > MAX_COMMENT_CHARS {
+        return HttpResponse::BadRequest().json(CqResult::<Nothing>::error(
+            500,
+            &format!("comment must have 1 to {} characters", MAX_COMMENT_CHARS),
+        ));
+    }
+    let mut comment = Comment {
+        id: uuid::Uuid::new_v4().to_string(),
+        memory_id: memory_id.clone(),
+        parent_id: req.parent_id.clone(),
+        author: user.pubkey.clone(),
+        body: body.to_string(),
+        hidden: false,
+        deleted: false,
+        tx_signature: None,
+        slot: None,
+        block_time: None,
+        created_at: chrono::Utc::now().timestamp(),
+    };
+    match moderate(&config, &comment).await {
+        Verdict::Allow => {}
+        Verdict::Hide => comment.hidden = true,
+        Verdict::Reject(reason) => {
+            return HttpResponse::BadRequest().json(CqResult::<Nothing>::error(
+                500,
+                &format!("comment rejected: {}", reason),
+            ));
+        }
+    }
+    let added = write_store(|store| {
+        let memory = store
+            .memories
+            .get(&memory_id)
+            .ok_or(Denied::NotFound("memory"))?;
+        if !store.memory_visible_to(memory, Some(&user.pubkey)) {
+            return Err(Denied::Forbidden);
+        }
+        let memory_ref = memory.tx_signature.clone().unwrap_or(memory.id.clone());
+        if let Some(parent_id) = &comment.parent_id
+            && store
+                .comments
+                .get(parent_id)
+                .is_none_or(|parent| parent.memory_id != memory_id)
+        {
+            return Err(Denied::NotFound("parent comment"));
+        }
+        store.insert_comment(comment.clone());
+        Ok(memory_ref)
+    })
+    .await;
+    match added {
+        Ok(memory_ref) => {
+            let memo = config.comment_memo_ops.then(|| {
+                json!({
+                    "p": "CHRO",
+                    "op": "comment",
+                    "memory": memory_ref,
+                    "id": comment.id,
+                    "parent": comment.parent_id,
+                    "body": comment.body,
+                })
+                .to_string()
+            });
+            HttpResponse::Ok().json(CqResult::success(CommentResponse { comment, memo }))
+        }
+        Err(denied) => denied.response(),
+    }
+}
+
+/// Top-level comments, newest first, each with its full reply tree oldest first.
+#[get("/memories/{memory_id}/comments")]
+async fn list_comments(
+    user: MaybeAuthUser,
+    path: web::Path<String>,
+    page: web::Query<PageQuery>,
+) -> HttpResponse {
+    let memory_id = path.into_inner();
+    let comments = read_store(|store| {
+        let memory = store
+            .memories
+            .get(&memory_id)
+            .ok_or(Denied::NotFound("memory"))?;
+        if !store.memory_visible_to(memory, user.pubkey.as_deref()) {
+            return Err(Denied::Forbidden);
+        }
+        Ok(store
+            .comments
+            .values()
+            .filter(|c| c.memory_id == memory_id)
+            .cloned()
+            .collect::<Vec<_>>())
+    })
+    .await;
+    let comments = match comments {
+        Ok(comments) => comments,
+        Err(denied) => return denied.response(),
+    };
+
+    let mut children: HashMap<Option<String>, Vec<Comment>> = HashMap::new();
+    for comment in comments {
+        children
+            .entry(comment.parent_id.clone())
+            .or_default()
+            .push(comment);
+    }
+    let mut threads = build_threads(&mut children, None);
+    if !page.ascending() {
+        threads.reverse();
+    }
+    HttpResponse::Ok().json(CqResult::success(Page::paginate(threads, &page)))
+}
+
+fn build_threads(
+    children: &mut HashMap<Option<String>, Vec<Comment>>,
+    parent_id: Option<String>,
+) -> Vec<CommentThread> {
+    let mut comments = children.remove(&parent_id).unwrap_or_default();
+    comments.sort_by(|a, b| a.sort_time().cmp(&b.sort_time()).then(a.id.cmp(&b.id)));
+    comments
+        .into_iter()
+        .filter_map(|mut comment| {
+            let replies = build_threads(children, Some(comment.id.clone()));
+            if !comment.is_shown() {
+                if replies.is_empty() {
+                    return None;
+                }
+                comment.body.clear();
+            }
+            Some(CommentThread { comment, replies })
+        })
+        .collect()
+}
+
+/// Deletes a comment of the caller. Replies stay in place.
+#[delete("/comments/{comment_id}")]
+async fn delete_comment(user: AuthUser, path: web::Path<String>) -> HttpResponse {
+    let comment_id = path.into_inner();
+    let deleted = write_store(|store| {
+        let comment = store
+            .comments
+            .get(&comment_id)
+            .ok_or(Denied::NotFound("comment"))?;
+        if comment.author != user.pubkey {
+            return Err(Denied::Forbidden);
+        }
+        store
+            .update_comment(&comment_id, |c| c.deleted = true)
+            .ok_or(Denied::NotFound("comment"))
+    })
+    .await;
+    match deleted {
+        Ok(comment) => HttpResponse::Ok().json(CqResult::success(comment)),
+        Err(denied) => denied.response(),
+    }
+}
+
+/// Hides or restores a comment. Allowed to the memory's author and the editors
+/// of its album.
+#[put("/comments/{comment_id}/moderation")]
+async fn moderate_comment(
+    user: AuthUser,
+    path: web::Path<String>,
+    req: web::Json<ModerationRequest>,
+) -> HttpResponse {
+    let comment_id = path.into_inner();
+    let moderated = write_store(|store| {
+        let comment = store
+            .comments
+            .get(&comment_id)
+            .ok_or(Denied::NotFound("comment"))?;
+        let memory = store
+            .memories
+            .get(&comment.memory_id)
+            .ok_or(Denied::NotFound("memory"))?;
+        if !store.can_edit_memory(memory, &user.pubkey) {
+            return Err(Denied::Forbidden);
+        }
+        store
+            .update_comment(&comment_id, |c| c.hidden = req.hidden)
+            .ok_or(Denied::NotFound("comment"))
+    })
+    .await;
+    match moderated {
+        Ok(comment) => HttpResponse::Ok().json(CqResult::success(comment)),
+        Err(denied) => denied.response(),
+    }
+}
@@ -0,0 +1,68 @@
+use actix_web::{HttpResponse, get, post, web};
+use serde_json::json;
+
+use crate::{
+    api::album_api::{forbidden, not_found},
+    auth::{jwt::AuthUser, signed_url::file_url},
+    conf::config::Config,
+    export::export::{ExportSource, get_export, start_export},
+    store::store::read_store,
+    utils::result::CqResult,
+};
+
+/// Bundles the memories of an album the caller can see. Progress and the
+/// download link arrive on `/task_events`.
+#[post("/albums/{album_id}/export")]
+async fn export_album(
+    config: web::Data<Config>,
+    user: AuthUser,
+    path: web::Path<String>,
+) -> HttpResponse {
+    let album_id = path.into_inner();
+    match read_store(|store| store.albums.get(&album_id).cloned()).await {
+        Some(album) if album.visible_to(Some(&user.pubkey)) => {
+            let job = start_export(&config, &user.pubkey, ExportSource::Album { album_id }).await;
+            HttpResponse::Ok().json(CqResult::success(job))
+        }
+        Some(_) => forbidden(),
+        None => not_found("album"),
+    }
+}
+
+/// Bundles everything the caller published or generated.
+#[post("/authors/{author}/export")]
+async fn export_author(
+    config: web::Data<Config>,
+    user: AuthUser,
+    path: web::Path<String>,
+) -> HttpResponse {
+    let author = path.into_inner();
+    if author != user.pubkey {
+        return forbidden();
+    }
+    let job = start_export(&config, &user.pubkey, ExportSource::Author { author }).await;
+    HttpResponse::Ok().json(CqResult::success(job))
+}
+
+/// Polls an export; a finished one comes with a fresh download link.
+#[get("/exports/{export_id}")]
+async fn fetch_export(
+    config: web::Data<Config>,
+    user: AuthUser,
+    path: web::Path<String>,
+) -> HttpResponse {
+    match get_export(&path.into_inner()).await {
+        Some(job) if job.owner == user.pubkey => {
+            let url = job
+                .file_name
+                .as_ref()
+                .map(|file_name| file_url(&config, file_name, Some(&job.owner)));
+            HttpResponse::Ok().json(CqResult::success(json!({
+                "export": job,
+                "url": url,
+            })))
+        }
+        Some(_) => forbidden(),
+        None => not_found("export"),
+    }
+}
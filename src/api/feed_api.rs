@@ -0,0 +1,85 @@
+use std::str::FromStr;
+
+use actix_web::{HttpResponse, delete, get, post, web};
+use serde_json::json;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::{
+    api::album_api::signed_memory,
+    auth::jwt::AuthUser,
+    conf::config::Config,
+    store::store::{read_store, write_store},
+    utils::{
+        page::{CursorPage, CursorQuery},
+        result::{CqResult, Nothing},
+    },
+};
+
+async fn set_follow(user: &AuthUser, followee: String, following: bool) -> HttpResponse {
+    if Pubkey::from_str(&followee).is_err() || followee == user.pubkey {
+        return HttpResponse::BadRequest().json(CqResult::<Nothing>::error(
+            500,
+            "can only follow another valid pubkey",
+        ));
+    }
+    let changed = write_store(|store| store.set_follow(&user.pubkey, &followee, following)).await;
+    HttpResponse::Ok().json(CqResult::success(json!({
+        "pubkey": followee,
+        "following": following,
+        "changed": changed,
+    })))
+}
+
+#[post("/follows/{pubkey}")]
+async fn follow(user: AuthUser, path: web::Path<String>) -> HttpResponse {
+    set_follow(&user, path.into_inner(), true).await
+}
+
+#[delete("/follows/{pubkey}")]
+async fn unfollow(user: AuthUser, path: web::Path<String>) -> HttpResponse {
+    set_follow(&user, path.into_inner(), false).await
+}
+
+#[get("/users/{pubkey}/following")]
+async fn list_following(path: web::Path<String>) -> HttpResponse {
+    let pubkey = path.into_inner();
+    let mut following = read_store(|store| {
+        store
+            .follows
+            .get(&pubkey)
+            .map(|f| f.iter().cloned().collect::<Vec<_>>())
+            .unwrap_or_default()
+    })
+    .await;
+    following.sort();
+    HttpResponse::Ok().json(CqResult::success(following))
+}
+
+#[get("/users/{pubkey}/followers")]
+async fn list_followers(path: web::Path<String>) -> HttpResponse {
+    let pubkey = path.into_inner();
+    let followers = read_store(|store| store.followers_of(&pubkey)).await;
+    HttpResponse::Ok().json(CqResult::success(followers))
+}
+
+/// Memories of followed authors and shared albums, newest block time first.
+#[get("/feed")]
+async fn feed(
+    config: web::Data<Config>,
+    user: AuthUser,
+    query: web::Query<CursorQuery>,
+) -> HttpResponse {
+    let memories = read_store(|store| {
+        store
+            .feed(&user.pubkey)
+            .into_iter()
+            .map(|m| signed_memory(&config, store, m, Some(&user.pubkey)))
+            .collect()
+    })
+    .await;
+    HttpResponse::Ok().json(CqResult::success(CursorPage::paginate(
+        memories,
+        &query,
+        |m| (m.sort_time(), m.id.clone()),
+    )))
+}
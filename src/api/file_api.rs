@@ -1,17 +1,251 @@
 use std::{
+    collections::{BTreeMap, HashMap},
     fs,
+    io::BufReader,
     path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime},
 };
 
 use actix_files::NamedFile;
-use actix_web::{HttpRequest, HttpResponse, get, http::header, web};
+use actix_web::{HttpMessage, HttpRequest, HttpResponse, get, http::header, web};
+use log::error;
 
-use crate::utils::result::{CqResult, Nothing};
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    auth::{
+        jwt::MaybeAuthUser,
+        signed_url::{self, SignedQuery, UrlDenied},
+    },
+    render::{
+        png_metadata,
+        variant::{VariantFormat, VariantSpec, ensure_variant},
+    },
+    storage::{
+        janitor,
+        storage::{blob_store_from_config, local_output, output_storage_from_config},
+    },
+    store::store::{Store, read_store},
+    upload::video::hls_key,
+    utils::result::{CqResult, Nothing},
+};
+
+/// Outputs, uploads and their variants are never rewritten under the same
+/// name, so clients may keep them for good.
+const FILE_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+/// Signed files may be bound to a user, so shared caches must not keep them.
+const SIGNED_FILE_CACHE_CONTROL: &str = "private, max-age=31536000, immutable";
+
+/// Playlists are rewritten when a video is transcoded again.
+const HLS_CACHE_CONTROL: &str = "public, max-age=86400";
+
+/// Lifetime of the storage URLs originals are redirected to.
+const PRESIGN_TTL: Duration = Duration::from_secs(300);
+
+/// Asks for a resized or re-encoded variant, e.g. `?w=256&format=webp&q=80`.
+#[derive(Debug, Deserialize)]
+struct VariantQuery {
+    w: Option<u32>,
+    format: Option<String>,
+    q: Option<u8>,
+}
+
+impl VariantQuery {
+    fn is_original(&self) -> bool {
+        self.w.is_none() && self.format.is_none() && self.q.is_none()
+    }
+
+    /// Whether the format is picked from the `Accept` header: only resized
+    /// or re-compressed PNG and JPEG images without an explicit format are.
+    /// The original is always served as stored, so its metadata survives.
+    fn negotiates(&self, original: &Path) -> bool {
+        !self.is_original()
+            && self.format.is_none()
+            && matches!(
+                original_format(original),
+                Some(VariantFormat::Png | VariantFormat::Jpeg)
+            )
+    }
+
+    /// `None` when the original is wanted. Without a format the best one the
+    /// client accepts is used, falling back to the original's.
+    fn spec(&self, original: &Path, accept: Option<&str>) -> Result<Option<VariantSpec>, String> {
+        if self.is_original() {
+            return Ok(None);
+        }
+        let negotiated = accept
+            .filter(|_| self.negotiates(original))
+            .and_then(VariantFormat::negotiate);
+        let format = match &self.format {
+            Some(format) => VariantFormat::parse(format)
+                .ok_or_else(|| format!("unsupported format: {}", format))?,
+            None => negotiated
+                .or_else(|| original_format(original))
+                .unwrap_or(VariantFormat::Png),
+        };
+        Ok(Some(VariantSpec::new(self.w, format, self.q)))
+    }
+}
+
+fn original_format(original: &Path) -> Option<VariantFormat> {
+    original
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(VariantFormat::parse)
+}
+
+/// Content hash of a served file, valid while its size and modification time
+/// stay the same.
+struct CachedEtag {
+    modified: SystemTime,
+    size: u64,
+    tag: String,
+}
+
+/// Most file hashes kept in memory; one is dropped for each new one past it.
+const MAX_CACHED_ETAGS: usize = 4096;
+
+static ETAGS: Lazy<Mutex<HashMap<PathBuf, CachedEtag>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// A strong ETag from the SHA-256 of the file, so every instance serving a
+/// copy of an output agrees on it.
+async fn content_etag(path: &Path, metadata: &fs::Metadata) -> std::io::Result<header::EntityTag> {
+    let modified = metadata.modified()?;
+    let size = metadata.len();
+    if let Some(cached) = ETAGS.lock().unwrap().get(path)
+        && cached.modified == modified
+        && cached.size == size
+    {
+        return Ok(header::EntityTag::new_strong(cached.tag.clone()));
+    }
+    let owned = path.to_path_buf();
+    let tag = tokio::task::spawn_blocking(move || {
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut BufReader::new(fs::File::open(&owned)?), &mut hasher)?;
+        std::io::Result::Ok(hex::encode(hasher.finalize()))
+    })
+    .await
+    .map_err(std::io::Error::other)??;
+    let cached = CachedEtag {
+        modified,
+        size,
+        tag: tag.clone(),
+    };
+    let mut etags = ETAGS.lock().unwrap();
+    if etags.len() >= MAX_CACHED_ETAGS
+        && !etags.contains_key(path)
+        && let Some(evicted) = etags.keys().next().cloned()
+    {
+        etags.remove(&evicted);
+    }
+    etags.insert(path.to_path_buf(), cached);
+    Ok(header::EntityTag::new_strong(tag))
+}
+
+/// Whether the client's `If-None-Match` already names this content.
+fn not_modified(req: &HttpRequest, etag: &header::EntityTag) -> bool {
+    match req.get_header::<header::IfNoneMatch>() {
+        Some(header::IfNoneMatch::Any) => true,
+        Some(header::IfNoneMatch::Items(items)) => items.iter().any(|item| item.weak_eq(etag)),
+        None => false,
+    }
+}
+
+/// Validators and caching hints shared by full and 304 responses.
+fn insert_cache_headers(
+    headers: &mut header::HeaderMap,
+    etag: &header::EntityTag,
+    cache_control: &'static str,
+    vary: bool,
+) {
+    if let Ok(value) = header::HeaderValue::from_str(&etag.to_string()) {
+        headers.insert(header::ETAG, value);
+    }
+    headers.insert(
+        header::CACHE_CONTROL,
+        header::HeaderValue::from_static(cache_control),
+    );
+    if vary {
+        headers.insert(header::VARY, header::HeaderValue::from_static("Accept"));
+    }
+}
+
+/// Where a client can fetch an output straight from the storage backend, when
+/// `OUTPUT_PRESIGN` is on and this instance has no copy of it.
+async fn presigned_url(config: &crate::conf::config::Config, file_name: &str) -> Option<String> {
+    if !config.output_presign || Path::new(&config.img_tmp_path).join(file_name).exists() {
+        return None;
+    }
+    output_storage_from_config(config)
+        .ok()?
+        .presign(file_name, PRESIGN_TTL)
+        .await
+        .inspect_err(|e| error!("presign {} failed: {}", file_name, e))
+        .ok()
+        .flatten()
+}
+
+/// Copies an output produced by another instance into `img_tmp_path`.
+async fn fetch_output(
+    config: &crate::conf::config::Config,
+    file_name: &str,
+) -> Result<(), HttpResponse> {
+    match local_output(config, file_name).await {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            let result = CqResult::<Nothing>::error(500, &format!("Failed to fetch file: {}", e));
+            Err(HttpResponse::BadRequest().json(result))
+        }
+    }
+}
+
+/// With signed URLs on, a file needs a valid signature unless a public
+/// memory shows it.
+async fn check_access(
+    config: &crate::conf::config::Config,
+    file_name: &str,
+    signed: &SignedQuery,
+    user: &MaybeAuthUser,
+) -> Result<(), HttpResponse> {
+    check_signed(config, file_name, signed, user, |store| {
+        store.is_public_file(config, file_name)
+    })
+    .await
+}
+
+/// Lets through a valid signature for `signed_name`, or a request without
+/// one when `visible` holds.
+async fn check_signed(
+    config: &crate::conf::config::Config,
+    signed_name: &str,
+    signed: &SignedQuery,
+    user: &MaybeAuthUser,
+    visible: impl FnOnce(&Store) -> bool,
+) -> Result<(), HttpResponse> {
+    if !config.signed_urls {
+        return Ok(());
+    }
+    match signed_url::verify(config, signed_name, signed, user.pubkey.as_deref()) {
+        Ok(()) => Ok(()),
+        Err(UrlDenied::Missing) if read_store(visible).await => Ok(()),
+        Err(e) => {
+            Err(HttpResponse::Forbidden().json(CqResult::<Nothing>::error(403, &e.to_string())))
+        }
+    }
+}
 
 #[get("/file/{file_name}")]
 async fn file(
     config: web::Data<crate::conf::config::Config>,
     path: web::Path<String>,
+    variant: web::Query<VariantQuery>,
+    signed: web::Query<SignedQuery>,
+    user: MaybeAuthUser,
     req: HttpRequest,
 ) -> HttpResponse {
     let file_name = path.into_inner();
@@ -19,6 +253,24 @@ async fn file(
         let result = CqResult::<Nothing>::error(500, "file_name is empty");
         return HttpResponse::BadRequest().json(result);
     }
+    if let Err(denied) = check_access(&config, &file_name, &signed, &user).await {
+        return denied;
+    }
+    let accept = req
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok());
+    if matches!(variant.spec(Path::new(&file_name), accept), Ok(None))
+        && let Some(url) = presigned_url(&config, &file_name).await
+    {
+        return HttpResponse::TemporaryRedirect()
+            .insert_header((header::LOCATION, url))
+            .insert_header((header::CACHE_CONTROL, "no-store"))
+            .finish();
+    }
+    if let Err(response) = fetch_output(&config, &file_name).await {
+        return response;
+    }
     let root_dir = PathBuf::from(config.img_tmp_path.clone())
         .canonicalize()
         .map_err(|e| {
@@ -33,6 +285,22 @@ async fn file(
             return HttpResponse::BadRequest().json(result);
         }
     };
+    let vary = variant.negotiates(&full_path);
+    let (full_path, content_type) = match variant.spec(&full_path, accept) {
+        Ok(None) => (full_path, None),
+        Ok(Some(spec)) => match ensure_variant(&full_path, &spec).await {
+            Ok(variant_path) => (variant_path, Some(spec.format.content_type())),
+            Err(e) => {
+                let result =
+                    CqResult::<Nothing>::error(500, &format!("Failed to create variant: {}", e));
+                return HttpResponse::InternalServerError().json(result);
+            }
+        },
+        Err(e) => {
+            let result = CqResult::<Nothing>::error(500, &e);
+            return HttpResponse::BadRequest().json(result);
+        }
+    };
     let metadata = match fs::metadata(&full_path) {
         Ok(meta) => meta,
         Err(e) => {
@@ -47,9 +315,39 @@ async fn file(
         .to_string_lossy()
         .to_string();
     let file_size = metadata.len();
+    let etag = match content_etag(&full_path, &metadata).await {
+        Ok(etag) => etag,
+        Err(e) => {
+            let result = CqResult::<Nothing>::error(500, &format!("Failed to hash file: {}", e));
+            return HttpResponse::InternalServerError().json(result);
+        }
+    };
+    let cache_control = if config.signed_urls {
+        SIGNED_FILE_CACHE_CONTROL
+    } else {
+        FILE_CACHE_CONTROL
+    };
+    janitor::touch(&file_name);
+    if file_name_only != file_name {
+        janitor::touch(&file_name_only);
+    }
+    if not_modified(&req, &etag) {
+        let mut response = HttpResponse::NotModified().finish();
+        insert_cache_headers(response.headers_mut(), &etag, cache_control, vary);
+        return response;
+    }
     match NamedFile::open(&full_path) {
         Ok(named_file) => {
-            let mut response = named_file.into_response(&req);
+            // our content hash replaces the inode based ETag
+            let mut response = named_file.use_etag(false).into_response(&req);
+            let headers = response.headers_mut();
+            insert_cache_headers(headers, &etag, cache_control, vary);
+            if let Some(content_type) = content_type {
+                headers.insert(
+                    header::CONTENT_TYPE,
+                    header::HeaderValue::from_static(content_type),
+                );
+            }
             if let Ok(name_value) = header::HeaderValue::from_str(&file_name_only) {
                 response
                     .headers_mut()
@@ -60,12 +358,235 @@ async fn file(
                     .headers_mut()
                     .insert(header::HeaderName::from_static("x-file-size"), size_value);
             }
-            return response;
+            response
         }
         Err(e) => {
             let result = CqResult::<Nothing>::error(500, &format!("Failed to open file: {}", e));
+            HttpResponse::NotFound().json(result)
+        }
+    }
+}
+
+/// Reads back the metadata we embedded in a generated PNG.
+#[get("/file/{file_name}/metadata")]
+async fn file_metadata(
+    config: web::Data<crate::conf::config::Config>,
+    path: web::Path<String>,
+    signed: web::Query<SignedQuery>,
+    user: MaybeAuthUser,
+) -> HttpResponse {
+    let file_name = path.into_inner();
+    if let Err(denied) = check_access(&config, &file_name, &signed, &user).await {
+        return denied;
+    }
+    if let Err(response) = fetch_output(&config, &file_name).await {
+        return response;
+    }
+    let full_path = match PathBuf::from(&config.img_tmp_path)
+        .canonicalize()
+        .map_err(|e| format!("Invalid FILE_ROOT: {}", e))
+        .and_then(|root_dir| validate_path(&file_name, &root_dir))
+    {
+        Ok(path) => path,
+        Err(e) => {
+            let result = CqResult::<Nothing>::error(500, &e);
+            return HttpResponse::BadRequest().json(result);
+        }
+    };
+    let data = match tokio::fs::read(&full_path).await {
+        Ok(data) => data,
+        Err(e) => {
+            let result = CqResult::<Nothing>::error(500, &format!("Failed to read file: {}", e));
             return HttpResponse::NotFound().json(result);
         }
+    };
+    match png_metadata::read_text_chunks(&data) {
+        Ok(chunks) => {
+            let metadata = chunks
+                .iter()
+                .filter_map(|c| {
+                    let key = c.keyword.strip_prefix(png_metadata::KEYWORD_PREFIX)?;
+                    Some((key.to_string(), c.text.clone()))
+                })
+                .collect::<BTreeMap<_, _>>();
+            let workflow = chunks
+                .iter()
+                .any(|c| png_metadata::is_comfyui_keyword(&c.keyword));
+            HttpResponse::Ok().json(CqResult::success(json!({
+                "metadata": metadata,
+                "comfyui_workflow": workflow,
+            })))
+        }
+        Err(e) => {
+            let result = CqResult::<Nothing>::error(500, &format!("Failed to read PNG: {}", e));
+            HttpResponse::BadRequest().json(result)
+        }
+    }
+}
+
+/// Playlists and segments of a transcoded video, served to whoever may see
+/// the video itself. Segments support range requests like any other file.
+#[get("/hls/{file_id}/{name}")]
+async fn hls(
+    config: web::Data<crate::conf::config::Config>,
+    path: web::Path<(String, String)>,
+    signed: web::Query<SignedQuery>,
+    user: MaybeAuthUser,
+    req: HttpRequest,
+) -> HttpResponse {
+    let (file_id, name) = path.into_inner();
+    let content_type = match name.rsplit_once('.') {
+        Some((_, "m3u8")) => "application/vnd.apple.mpegurl",
+        Some((_, "ts")) => "video/mp2t",
+        _ => "",
+    };
+    let valid = !content_type.is_empty()
+        && file_id.chars().all(|c| c.is_ascii_hexdigit())
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+    if !valid {
+        let result = CqResult::<Nothing>::error(500, "invalid playlist or segment name");
+        return HttpResponse::BadRequest().json(result);
+    }
+    let Some(video_name) = read_store(|store| {
+        store
+            .uploads
+            .get(&file_id)
+            .map(|upload| upload.file_name.clone())
+    })
+    .await
+    else {
+        return HttpResponse::NotFound().json(CqResult::<Nothing>::error(404, "upload not found"));
+    };
+    if let Err(denied) = check_access(&config, &video_name, &signed, &user).await {
+        return denied;
+    }
+    let path = match local_output(&config, &hls_key(&file_id, &name)).await {
+        Ok(Some(path)) => path,
+        Ok(None) => {
+            let result = CqResult::<Nothing>::error(404, "playlist or segment not found");
+            return HttpResponse::NotFound().json(result);
+        }
+        Err(e) => {
+            let result = CqResult::<Nothing>::error(500, &format!("Failed to fetch file: {}", e));
+            return HttpResponse::BadRequest().json(result);
+        }
+    };
+    if name.ends_with(".m3u8") && config.signed_urls && signed.sig.is_some() {
+        return signed_playlist(&path, req.query_string()).await;
+    }
+    match NamedFile::open(path) {
+        Ok(named_file) => {
+            janitor::touch(&file_id);
+            let mut response = named_file.into_response(&req);
+            let headers = response.headers_mut();
+            headers.insert(
+                header::CACHE_CONTROL,
+                header::HeaderValue::from_static(HLS_CACHE_CONTROL),
+            );
+            headers.insert(
+                header::CONTENT_TYPE,
+                header::HeaderValue::from_static(content_type),
+            );
+            response
+        }
+        Err(e) => {
+            let result = CqResult::<Nothing>::error(500, &format!("Failed to open file: {}", e));
+            HttpResponse::NotFound().json(result)
+        }
+    }
+}
+
+/// A playlist whose entries carry the signature it was requested with, since
+/// players drop the query when resolving relative URLs.
+async fn signed_playlist(path: &Path, query: &str) -> HttpResponse {
+    match tokio::fs::read_to_string(path).await {
+        Ok(playlist) => {
+            let body = playlist
+                .lines()
+                .map(|line| {
+                    if line.is_empty() || line.starts_with('#') {
+                        line.to_string()
+                    } else {
+                        signed_url::with_query(line, query)
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            HttpResponse::Ok()
+                .content_type("application/vnd.apple.mpegurl")
+                .insert_header((header::CACHE_CONTROL, "private, no-store"))
+                .body(body + "\n")
+        }
+        Err(e) => {
+            let result = CqResult::<Nothing>::error(500, &format!("Failed to open file: {}", e));
+            HttpResponse::NotFound().json(result)
+        }
+    }
+}
+
+/// A mirrored image, under the same rules as `/file`: without a signature
+/// only when a memory the requester may see shows it.
+#[get("/blob/{sha256}")]
+async fn blob(
+    config: web::Data<crate::conf::config::Config>,
+    path: web::Path<String>,
+    signed: web::Query<SignedQuery>,
+    user: MaybeAuthUser,
+) -> HttpResponse {
+    let sha256 = path.into_inner();
+    if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+        let result = CqResult::<Nothing>::error(500, "invalid sha256");
+        return HttpResponse::BadRequest().json(result);
+    }
+    let signed_name = signed_url::blob_name(&sha256);
+    if let Err(denied) = check_signed(&config, &signed_name, &signed, &user, |store| {
+        store.blob_visible_to(&sha256, user.pubkey.as_deref())
+    })
+    .await
+    {
+        return denied;
+    }
+    let cache_control = if config.signed_urls {
+        SIGNED_FILE_CACHE_CONTROL
+    } else {
+        FILE_CACHE_CONTROL
+    };
+    let store = match blob_store_from_config(&config) {
+        Ok(store) => store,
+        Err(e) => {
+            let result = CqResult::<Nothing>::error(500, &format!("blob store unavailable: {}", e));
+            return HttpResponse::InternalServerError().json(result);
+        }
+    };
+    match store.get(&sha256).await {
+        Ok(Some(data)) => HttpResponse::Ok()
+            .content_type(sniff_image_type(&data))
+            .insert_header((header::CACHE_CONTROL, cache_control))
+            .body(data),
+        Ok(None) => {
+            HttpResponse::NotFound().json(CqResult::<Nothing>::error(500, "blob not found"))
+        }
+        Err(e) => {
+            let result = CqResult::<Nothing>::error(500, &format!("Failed to read blob: {}", e));
+            HttpResponse::InternalServerError().json(result)
+        }
+    }
+}
+
+fn sniff_image_type(data: &[u8]) -> &'static str {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        "image/png"
+    } else if data.starts_with(&[0xff, 0xd8, 0xff]) {
+        "image/jpeg"
+    } else if data.starts_with(b"GIF8") {
+        "image/gif"
+    } else if data.len() > 12 && &data[0..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        "image/webp"
+    } else {
+        "application/octet-stream"
     }
 }
 
@@ -0,0 +1,271 @@
+use actix_web::{HttpResponse, delete, get, post, put, web};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    api::album_api::{Denied, not_found},
+    auth::jwt::{AuthUser, MaybeAuthUser},
+    store::{
+        models::{Invitation, MemberChange, Role},
+        store::{read_store, write_store},
+    },
+    utils::result::{CqResult, Nothing},
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+struct InvitationRequest {
+    role: Role,
+    /// Seconds until the invitation expires.
+    expires_in: Option<i64>,
+    max_uses: Option<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RoleRequest {
+    role: Role,
+}
+
+/// A membership change as returned to the client, with the CHRO memo that
+/// records it on chain when memo ops are enabled.
+#[derive(Debug, Serialize, Deserialize)]
+struct MemberChangeResponse {
+    change: MemberChange,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    memo: Option<String>,
+}
+
+/// `invitation` is the token hash of the invitation a member joined with.
+fn change_response(
+    config: &crate::conf::config::Config,
+    change: MemberChange,
+    invitation: Option<String>,
+) -> HttpResponse {
+    let memo = config.membership_memo_ops.then(|| {
+        let mut memo = json!({
+            "p": "CHRO",
+            "op": "member",
+            "album": change.album_id,
+            "member": change.member,
+            "role": change.role,
+        });
+        if let Some(invitation) = invitation {
+            memo["invitation"] = json!(invitation);
+        }
+        memo.to_string()
+    });
+    HttpResponse::Ok().json(CqResult::success(MemberChangeResponse { change, memo }))
+}
+
+/// Owners may invite any role below owner; editors may invite viewers.
+#[post("/albums/{album_id}/invitations")]
+async fn create_invitation(
+    config: web::Data<crate::conf::config::Config>,
+    user: AuthUser,
+    path: web::Path<String>,
+    req: web::Json<InvitationRequest>,
+) -> HttpResponse {
+    let album_id = path.into_inner();
+    if req.role == Role::Owner {
+        return HttpResponse::BadRequest().json(CqResult::<Nothing>::error(
+            500,
+            "ownership can not be shared",
+        ));
+    }
+    if req.expires_in.is_some_and(|secs| secs <= 0) {
+        return HttpResponse::BadRequest().json(CqResult::<Nothing>::error(
+            500,
+            "expires_in must be positive",
+        ));
+    }
+    let now = chrono::Utc::now().timestamp();
+    let invitation = Invitation {
+        token: uuid::Uuid::new_v4().simple().to_string(),
+        album_id: album_id.clone(),
+        role: req.role,
+        created_by: user.pubkey.clone(),
+        expires_at: req.expires_in.map(|secs| now + secs),
+        max_uses: req.max_uses,
+        uses: 0,
+    };
+    let created = write_store(|store| {
+        let album = store
+            .albums
+            .get(&album_id)
+            .ok_or(Denied::NotFound("album"))?;
+        if !album.has_role(Some(&user.pubkey), invitation.inviter_role()) {
+            return Err(Denied::Forbidden);
+        }
+        store
+            .invitations
+            .insert(invitation.token.clone(), invitation.clone());
+        Ok(())
+    })
+    .await;
+    match created {
+        Ok(()) => HttpResponse::Ok().json(CqResult::success(json!({
+            "invitation": invitation,
+            "link": format!("{}/invitations/{}", config.public_base_url, invitation.token),
+        }))),
+        Err(denied) => denied.response(),
+    }
+}
+
+#[get("/invitations/{token}")]
+async fn get_invitation(path: web::Path<String>) -> HttpResponse {
+    let token = path.into_inner();
+    let now = chrono::Utc::now().timestamp();
+    let found = read_store(|store| {
+        let invitation = store.invitations.get(&token).filter(|i| i.is_usable(now))?;
+        let album = store.albums.get(&invitation.album_id)?;
+        Some(json!({
+            "album_id": album.id,
+            "album_title": album.title,
+            "role": invitation.role,
+            "expires_at": invitation.expires_at,
+        }))
+    })
+    .await;
+    match found {
+        Some(found) => HttpResponse::Ok().json(CqResult::success(found)),
+        None => not_found("invitation"),
+    }
+}
+
+/// Joins the album of an invitation while its creator may still invite.
+/// Accepting never lowers an existing role. The change is the member's own,
+/// and its memo names the invitation for the indexer to check.
+#[post("/invitations/{token}/accept")]
+async fn accept_invitation(
+    config: web::Data<crate::conf::config::Config>,
+    user: AuthUser,
+    path: web::Path<String>,
+) -> HttpResponse {
+    let token = path.into_inner();
+    let now = chrono::Utc::now().timestamp();
+    let accepted = write_store(|store| {
+        let invitation = store
+            .invitations
+            .get_mut(&token)
+            .filter(|i| i.is_usable(now))
+            .ok_or(Denied::NotFound("invitation"))?;
+        let album = store
+            .albums
+            .get_mut(&invitation.album_id)
+            .ok_or(Denied::NotFound("album"))?;
+        if !album.has_role(Some(&invitation.created_by), invitation.inviter_role()) {
+            return Err(Denied::NotFound("invitation"));
+        }
+        if album.has_role(Some(&user.pubkey), invitation.role) {
+            return Err(Denied::Forbidden);
+        }
+        invitation.uses += 1;
+        let change = MemberChange {
+            album_id: album.id.clone(),
+            member: user.pubkey.clone(),
+            role: Some(invitation.role),
+            by: user.pubkey.clone(),
+            at: now,
+            tx_signature: None,
+        };
+        album.apply_member_change(change.clone());
+        Ok((change, invitation.token_hash()))
+    })
+    .await;
+    match accepted {
+        Ok((change, invitation)) => change_response(&config, change, Some(invitation)),
+        Err(Denied::Forbidden) => HttpResponse::BadRequest().json(CqResult::<Nothing>::error(
+            500,
+            "already a member with this role",
+        )),
+        Err(denied) => denied.response(),
+    }
+}
+
+#[get("/albums/{album_id}/members")]
+async fn list_members(user: MaybeAuthUser, path: web::Path<String>) -> HttpResponse {
+    let album_id = path.into_inner();
+    let members = read_store(|store| {
+        let album = store
+            .albums
+            .get(&album_id)
+            .ok_or(Denied::NotFound("album"))?;
+        if !album.visible_to(user.pubkey.as_deref()) {
+            return Err(Denied::Forbidden);
+        }
+        Ok(json!({
+            "owner": album.owner,
+            "members": album.members,
+            "log": album.member_log,
+        }))
+    })
+    .await;
+    match members {
+        Ok(members) => HttpResponse::Ok().json(CqResult::success(members)),
+        Err(denied) => denied.response(),
+    }
+}
+
+#[put("/albums/{album_id}/members/{pubkey}")]
+async fn set_member_role(
+    config: web::Data<crate::conf::config::Config>,
+    user: AuthUser,
+    path: web::Path<(String, String)>,
+    req: web::Json<RoleRequest>,
+) -> HttpResponse {
+    let (album_id, member) = path.into_inner();
+    if req.role == Role::Owner {
+        return HttpResponse::BadRequest().json(CqResult::<Nothing>::error(
+            500,
+            "ownership can not be shared",
+        ));
+    }
+    update_membership(&config, &user, album_id, member, Some(req.role)).await
+}
+
+/// Removes a member. Members may also remove themselves.
+#[delete("/albums/{album_id}/members/{pubkey}")]
+async fn remove_member(
+    config: web::Data<crate::conf::config::Config>,
+    user: AuthUser,
+    path: web::Path<(String, String)>,
+) -> HttpResponse {
+    let (album_id, member) = path.into_inner();
+    update_membership(&config, &user, album_id, member, None).await
+}
+
+async fn update_membership(
+    config: &crate::conf::config::Config,
+    user: &AuthUser,
+    album_id: String,
+    member: String,
+    role: Option<Role>,
+) -> HttpResponse {
+    let updated = write_store(|store| {
+        let album = store
+            .albums
+            .get_mut(&album_id)
+            .ok_or(Denied::NotFound("album"))?;
+        let leaving = role.is_none() && member == user.pubkey;
+        if member == album.owner || !(leaving || album.owner == user.pubkey) {
+            return Err(Denied::Forbidden);
+        }
+        if album.role_of(&member).is_none() && role.is_none() {
+            return Err(Denied::NotFound("member"));
+        }
+        let change = MemberChange {
+            album_id: album.id.clone(),
+            member,
+            role,
+            by: user.pubkey.clone(),
+            at: chrono::Utc::now().timestamp(),
+            tx_signature: None,
+        };
+        album.apply_member_change(change.clone());
+        Ok(change)
+    })
+    .await;
+    match updated {
+        Ok(change) => change_response(config, change, None),
+        Err(denied) => denied.response(),
+    }
+}
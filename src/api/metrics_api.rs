@@ -0,0 +1,11 @@
+use actix_web::{HttpResponse, get};
+
+use crate::utils::metrics;
+
+/// Counters and gauges for Prometheus to scrape.
+#[get("/metrics")]
+async fn prometheus_metrics() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4; charset=utf-8")
+        .body(metrics::render())
+}
@@ -1,2 +1,16 @@
 pub mod task_api;
-pub mod file_api;
\ No newline at end of file
+pub mod file_api;
+pub mod publish_api;
+pub mod nft_api;
+pub mod album_api;
+pub mod auth_api;
+pub mod member_api;
+pub mod comment_api;
+pub mod feed_api;
+pub mod share_api;
+pub mod render_api;
+pub mod provenance_api;
+pub mod upload_api;
+pub mod resumable_api;
+pub mod metrics_api;
+pub mod export_api;
\ No newline at end of file
@@ -0,0 +1,97 @@
+use std::str::FromStr;
+
+use actix_web::{HttpResponse, get, post, web};
+use log::error;
+use rand::seq::IndexedRandom;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::{
+    api::publish_api::get_published,
+    auth::jwt::AuthUser,
+    solana::nft::{self, NftMetadata},
+    store::store::read_store,
+    utils::result::{CqResult, Nothing},
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MintRequest {
+    owner: String,
+    #[serde(default)]
+    compressed: bool,
+}
+
+/// Prepares an NFT mint for a published task of the caller. The returned
+/// transaction is partially signed and must be signed and sent by the owner's
+/// wallet.
+#[post("/mint/{prompt_id}")]
+async fn mint(
+    config: web::Data<crate::conf::config::Config>,
+    user: AuthUser,
+    path: web::Path<String>,
+    req: web::Json<MintRequest>,
+) -> HttpResponse {
+    let prompt_id = path.into_inner();
+    let author = read_store(|store| store.file_owner(&prompt_id).map(str::to_string)).await;
+    if author.as_deref() != Some(user.pubkey.as_str()) {
+        return HttpResponse::Forbidden().json(CqResult::<Nothing>::error(
+            403,
+            "only the author can mint this image",
+        ));
+    }
+    let owner = match Pubkey::from_str(req.owner.trim()) {
+        Ok(owner) => owner,
+        Err(_) => {
+            return HttpResponse::BadRequest().json(CqResult::<Nothing>::error(
+                500,
+                "owner is not a valid pubkey",
+            ));
+        }
+    };
+    let Some(published) = get_published(&prompt_id).await else {
+        return HttpResponse::BadRequest()
+            .json(CqResult::<Nothing>::error(500, "task is not published yet"));
+    };
+    let metadata = NftMetadata {
+        name: published.title.clone(),
+        symbol: config.nft_symbol.clone(),
+        uri: published
+            .metadata_uri
+            .clone()
+            .unwrap_or_else(|| format!("{}/metadata/{}", config.public_base_url, prompt_id)),
+        seller_fee_basis_points: config.nft_seller_fee_bps,
+    };
+    let Some(rpc_url) = config.solana_points.choose(&mut rand::rng()) else {
+        return HttpResponse::InternalServerError()
+            .json(CqResult::<Nothing>::error(500, "no solana rpc configured"));
+    };
+    let result = if req.compressed {
+        nft::build_compressed_mint_transaction(&config, rpc_url, &prompt_id, &owner, &metadata)
+            .await
+    } else {
+        nft::build_mint_transaction(rpc_url, &prompt_id, &owner, &metadata).await
+    };
+    match result {
+        Ok(record) => {
+            nft::record_mint(record.clone()).await;
+            HttpResponse::Ok().json(CqResult::success(record))
+        }
+        Err(e) => {
+            error!("{} ERROR!!!", e);
+            HttpResponse::InternalServerError().json(CqResult::<Nothing>::error(
+                500,
+                "build mint transaction failed",
+            ))
+        }
+    }
+}
+
+#[get("/mint/{prompt_id}")]
+async fn fetch_mint(path: web::Path<String>) -> HttpResponse {
+    match nft::get_mint_record(&path.into_inner()).await {
+        Some(record) => HttpResponse::Ok().json(CqResult::success(record)),
+        None => {
+            HttpResponse::NotFound().json(CqResult::<Nothing>::error(500, "task is not minted"))
+        }
+    }
+}
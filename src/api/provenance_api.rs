@@ -0,0 +1,143 @@
+use actix_web::{HttpResponse, get, post, web};
+use futures_util::StreamExt;
+use serde::Serialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use solana_sdk::signature::Signer;
+
+use crate::{
+    conf::config::Config,
+    provenance::{
+        manifest::{self, Manifest},
+        watermark::{self, DetectedWatermark},
+    },
+    render::png_metadata,
+    upload::upload::decode_image,
+    utils::result::{CqResult, Nothing},
+};
+
+/// Largest image accepted by `/verify`.
+const MAX_VERIFY_BYTES: usize = 32 * 1024 * 1024;
+
+#[derive(Debug, Serialize)]
+struct Verification {
+    /// `chroniq` when the watermark or an exact match with a signed manifest
+    /// ties the image to us.
+    origin: Option<&'static str>,
+    task_id: Option<String>,
+    /// A task id only named by the PNG text chunks, which anyone can write.
+    claimed_task_id: Option<String>,
+    watermark: Option<DetectedWatermark>,
+    manifest: Option<Manifest>,
+    signature_valid: bool,
+    /// The upload is byte for byte the file we served.
+    exact_match: bool,
+}
+
+/// The signed manifest of a generated image.
+#[get("/manifest/{prompt_id}")]
+async fn get_manifest(config: web::Data<Config>, path: web::Path<String>) -> HttpResponse {
+    let prompt_id = path.into_inner();
+    match manifest::read_manifest(&config, &prompt_id).await {
+        Ok(Some(manifest)) => {
+            let signature_valid = manifest::verify(&manifest);
+            HttpResponse::Ok().json(CqResult::success(json!({
+                "manifest": manifest,
+                "signature_valid": signature_valid,
+            })))
+        }
+        Ok(None) => {
+            HttpResponse::NotFound().json(CqResult::<Nothing>::error(404, "manifest not found"))
+        }
+        Err(e) => HttpResponse::BadRequest().json(CqResult::<Nothing>::error(500, &e.to_string())),
+    }
+}
+
+/// The public key manifests are signed with.
+#[get("/provenance/key")]
+async fn provenance_key() -> HttpResponse {
+    let signer = manifest::signing_key().pubkey().to_string();
+    HttpResponse::Ok().json(CqResult::success(json!({ "signer": signer })))
+}
+
+/// Checks whether an uploaded image came from this service, by its watermark
+/// or by its hash matching the signed manifest of the task its PNG text chunks
+/// name. A task id in the text chunks alone is only reported as claimed.
+#[post("/verify")]
+async fn verify(config: web::Data<Config>, mut payload: web::Payload) -> HttpResponse {
+    let mut data = Vec::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                return HttpResponse::BadRequest()
+                    .json(CqResult::<Nothing>::error(500, &e.to_string()));
+            }
+        };
+        if data.len() + chunk.len() > MAX_VERIFY_BYTES {
+            return HttpResponse::PayloadTooLarge().json(CqResult::<Nothing>::error(
+                413,
+                &format!("image exceeds {} bytes", MAX_VERIFY_BYTES),
+            ));
+        }
+        data.extend_from_slice(&chunk);
+    }
+
+    let max_dimension = config.upload_max_dimension;
+    let (data, detected) = match web::block(move || {
+        let image = decode_image(&data, max_dimension)?.to_rgb8();
+        anyhow::Ok((data, watermark::detect(&image)))
+    })
+    .await
+    {
+        Ok(Ok(result)) => result,
+        Ok(Err(e)) => {
+            return HttpResponse::BadRequest().json(CqResult::<Nothing>::error(
+                500,
+                &format!("not a readable image: {}", e),
+            ));
+        }
+        Err(e) => {
+            return HttpResponse::InternalServerError()
+                .json(CqResult::<Nothing>::error(500, &e.to_string()));
+        }
+    };
+
+    let embedded_id = png_metadata::read_text_chunks(&data)
+        .ok()
+        .and_then(|chunks| {
+            chunks
+                .into_iter()
+                .find(|c| c.keyword == format!("{}task_id", png_metadata::KEYWORD_PREFIX))
+                .map(|c| c.text)
+        });
+    let candidate = detected.as_ref().map(|w| w.task_id.clone()).or(embedded_id);
+    let manifest = match &candidate {
+        Some(task_id) => manifest::read_manifest(&config, task_id)
+            .await
+            .ok()
+            .flatten(),
+        None => None,
+    };
+    let signature_valid = manifest.as_ref().is_some_and(manifest::verify);
+    let exact_match = signature_valid
+        && manifest
+            .as_ref()
+            .is_some_and(|m| m.claim.content_sha256 == hex::encode(Sha256::digest(&data)));
+    let confirmed = detected.is_some() || exact_match;
+    let (task_id, claimed_task_id, manifest, signature_valid) = if confirmed {
+        (candidate, None, manifest, signature_valid)
+    } else {
+        (None, candidate, None, false)
+    };
+
+    HttpResponse::Ok().json(CqResult::success(Verification {
+        origin: confirmed.then_some("chroniq"),
+        task_id,
+        claimed_task_id,
+        watermark: detected,
+        manifest,
+        signature_valid,
+        exact_match,
+    }))
+}
@@ -0,0 +1,189 @@
+use actix_web::{HttpResponse, get, post, web};
+use log::error;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    auth::jwt::AuthUser,
+    solana::solana::TitleContent,
+    storage::storage::local_output,
+    store::{
+        models::PublishedMemory,
+        store::{read_store, write_store},
+    },
+    upload::upload::image_file,
+    uploader::uploader::uploader_from_config,
+    utils::result::{CqResult, Nothing},
+    ws::task_ws::get_task_params,
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PublishRequest {
+    title: String,
+    content: String,
+    album: Option<String>,
+}
+
+pub async fn get_published(prompt_id: &str) -> Option<PublishedMemory> {
+    read_store(|store| store.published.get(prompt_id).cloned()).await
+}
+
+/// Tasks and uploads published so far; their files must be kept.
+pub async fn published_ids() -> Vec<String> {
+    read_store(|store| store.published.keys().cloned().collect()).await
+}
+
+fn image_not_found() -> HttpResponse {
+    HttpResponse::NotFound().json(CqResult::<Nothing>::error(
+        500,
+        "image not found, please fetch the task or upload the file first",
+    ))
+}
+
+/// Uploads a finished image or a user upload and its metadata document to
+/// permanent storage and returns the CHRO memo for the client to sign and send.
+#[post("/publish/{prompt_id}")]
+async fn publish(
+    config: web::Data<crate::conf::config::Config>,
+    user: AuthUser,
+    path: web::Path<String>,
+    req: web::Json<PublishRequest>,
+) -> HttpResponse {
+    let prompt_id = path.into_inner();
+    if req.title.trim().is_empty() || req.content.trim().is_empty() {
+        return HttpResponse::BadRequest().json(CqResult::<Nothing>::error(
+            500,
+            "title content can not be empty",
+        ));
+    }
+    match read_store(|store| store.file_owner(&prompt_id).map(str::to_string)).await {
+        Some(owner) if owner != user.pubkey => {
+            return HttpResponse::Forbidden().json(CqResult::<Nothing>::error(
+                403,
+                "only the author can publish this image",
+            ));
+        }
+        Some(_) => {}
+        None => return image_not_found(),
+    }
+    let image = match image_file(&config, &prompt_id).await {
+        Some((file_name, content_type)) => match local_output(&config, &file_name).await {
+            Ok(Some(file_path)) => tokio::fs::read(&file_path)
+                .await
+                .ok()
+                .map(|image| (file_name, content_type, image)),
+            _ => None,
+        },
+        None => None,
+    };
+    let Some((file_name, content_type, image)) = image else {
+        return image_not_found();
+    };
+
+    let (image_uri, metadata_uri) =
+        match upload_permanent(&config, &prompt_id, image, &content_type, &req).await {
+            Ok(Some(uris)) => (uris.0, Some(uris.1)),
+            Ok(None) => (format!("{}/{}", config.img_tmp_point, file_name), None),
+            Err(e) => {
+                error!("{} ERROR!!!", e);
+                return HttpResponse::InternalServerError().json(CqResult::<Nothing>::error(
+                    500,
+                    "upload to permanent storage failed",
+                ));
+            }
+        };
+    let memo = TitleContent {
+        p: "CHRO".to_string(),
+        uri: image_uri.clone(),
+        title: req.title.clone(),
+        content: req.content.clone(),
+        album: req.album.clone(),
+        id: None,
+    };
+    let published = PublishedMemory {
+        prompt_id: prompt_id.clone(),
+        image_uri,
+        content_type,
+        metadata_uri,
+        title: req.title.clone(),
+        content: req.content.clone(),
+        memo: serde_json::to_string(&memo).unwrap_or_default(),
+    };
+    write_store(|store| store.published.insert(prompt_id, published.clone())).await;
+    HttpResponse::Ok().json(CqResult::success(published))
+}
+
+/// Returns the image and metadata URIs, or `None` when no uploader is configured.
+async fn upload_permanent(
+    config: &crate::conf::config::Config,
+    prompt_id: &str,
+    image: Vec<u8>,
+    content_type: &str,
+    req: &PublishRequest,
+) -> anyhow::Result<Option<(String, String)>> {
+    let Some(uploader) = uploader_from_config(config)? else {
+        return Ok(None);
+    };
+    let image_uri = uploader.upload(image, content_type).await?;
+    let metadata = metadata_document(
+        prompt_id,
+        &req.title,
+        &req.content,
+        &image_uri,
+        content_type,
+    )
+    .await;
+    let metadata_uri = uploader
+        .upload(serde_json::to_vec(&metadata)?, "application/json")
+        .await?;
+    Ok(Some((image_uri, metadata_uri)))
+}
+
+/// Metaplex-style JSON metadata for a generated image or upload, with the
+/// generation parameters as attributes when the task is known.
+pub async fn metadata_document(
+    prompt_id: &str,
+    title: &str,
+    content: &str,
+    image_uri: &str,
+    content_type: &str,
+) -> serde_json::Value {
+    let mut attributes = Vec::new();
+    if let Some(params) = get_task_params(prompt_id).await {
+        attributes.push(json!({ "trait_type": "style", "value": params.style }));
+        attributes.push(json!({ "trait_type": "seed", "value": params.seed }));
+        attributes.push(json!({ "trait_type": "steps", "value": params.steps }));
+        attributes.push(json!({ "trait_type": "prompt", "value": params.prompt }));
+    }
+    json!({
+        "name": title,
+        "description": content,
+        "image": image_uri,
+        "attributes": attributes,
+        "properties": {
+            "files": [{ "uri": image_uri, "type": content_type }],
+            "category": if content_type.starts_with("video/") { "video" } else { "image" },
+            "prompt_id": prompt_id,
+        },
+    })
+}
+
+/// Serves the metadata document of a published task that has no permanent copy.
+#[get("/metadata/{prompt_id}")]
+async fn fetch_metadata(path: web::Path<String>) -> HttpResponse {
+    let prompt_id = path.into_inner();
+    match get_published(&prompt_id).await {
+        Some(published) => HttpResponse::Ok().json(
+            metadata_document(
+                &prompt_id,
+                &published.title,
+                &published.content,
+                &published.image_uri,
+                &published.content_type,
+            )
+            .await,
+        ),
+        None => HttpResponse::NotFound()
+            .json(CqResult::<Nothing>::error(500, "task is not published yet")),
+    }
+}
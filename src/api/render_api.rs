@@ -0,0 +1,387 @@
+use std::io::Cursor;
+
+use ab_glyph::FontArc;
+use actix_web::{HttpResponse, get, post, web};
+use image::{DynamicImage, ImageFormat, RgbaImage};
+use log::error;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    auth::{jwt::MaybeAuthUser, signed_url::file_url},
+    render::{
+        compositor::{self, CardText},
+        filter::Filter,
+        layout::{self, Layout, LayoutOptions, Tile},
+        template::load_templates,
+    },
+    storage::{
+        mirror::download_image,
+        storage::{blob_store_from_config, output_storage_from_config},
+    },
+    store::{models::Memory, store::read_store},
+    upload::upload::decode_image,
+    utils::result::{CqResult, Nothing},
+};
+
+/// Most images a single card may combine.
+const MAX_RENDER_IMAGES: usize = 16;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RenderRequest {
+    template: String,
+    #[serde(default)]
+    title: String,
+    #[serde(default)]
+    content: String,
+    /// Defaults to today.
+    date: Option<String>,
+    /// Overrides the template's filter.
+    filter: Option<Filter>,
+    /// More tasks whose images fill the remaining slots of a collage.
+    #[serde(default)]
+    extra: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum CollageItem {
+    Task(String),
+    Memory(String),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CollageRequest {
+    items: Vec<CollageItem>,
+    #[serde(default)]
+    layout: Layout,
+    /// Poster width for grid and masonry, row height for the timeline; at
+    /// most `MAX_POSTER_SIDE`.
+    size: Option<u32>,
+    columns: Option<u32>,
+    /// At most `MAX_GAP`.
+    gap: Option<u32>,
+    background: Option<String>,
+    color: Option<String>,
+    title: Option<String>,
+    #[serde(default = "default_captions")]
+    captions: bool,
+}
+
+fn default_captions() -> bool {
+    true
+}
+
+#[get("/templates")]
+async fn list_templates(config: web::Data<crate::conf::config::Config>) -> HttpResponse {
+    let templates = load_templates(&config).await;
+    HttpResponse::Ok().json(CqResult::success(
+        templates.into_values().collect::<Vec<_>>(),
+    ))
+}
+
+/// Renders a generated image into a memory card template and stores the
+/// result next to the task images.
+#[post("/render/{prompt_id}")]
+async fn render(
+    config: web::Data<crate::conf::config::Config>,
+    path: web::Path<String>,
+    user: MaybeAuthUser,
+    req: web::Json<RenderRequest>,
+) -> HttpResponse {
+    let prompt_id = path.into_inner();
+    let mut templates = load_templates(&config).await;
+    let Some(template) = templates.remove(&req.template) else {
+        return HttpResponse::NotFound()
+            .json(CqResult::<Nothing>::error(500, "template not found"));
+    };
+    let prompt_ids = std::iter::once(&prompt_id)
+        .chain(req.extra.iter())
+        .take(MAX_RENDER_IMAGES)
+        .collect::<Vec<_>>();
+    let mut images = Vec::with_capacity(prompt_ids.len());
+    for id in prompt_ids {
+        if !may_use_task(&config, &user, id).await {
+            return HttpResponse::Forbidden().json(CqResult::<Nothing>::error(
+                403,
+                &format!("only the author can use the image of task {}", id),
+            ));
+        }
+        match load_task_image(&config, id).await {
+            Ok(image) => images.push(image),
+            Err(e) => {
+                error!("load image {} failed: {}", id, e);
+                return HttpResponse::NotFound().json(CqResult::<Nothing>::error(
+                    500,
+                    &format!("image of task {} not found", id),
+                ));
+            }
+        }
+    }
+    let text = CardText {
+        title: req.title.clone(),
+        content: req.content.clone(),
+        date: req
+            .date
+            .clone()
+            .unwrap_or_else(|| chrono::Utc::now().format("%Y-%m-%d").to_string()),
+    };
+    let font = load_font(&config).await;
+    let filter = req.filter;
+    let rendered =
+        web::block(move || compositor::render(&template, &images, &text, filter, font.as_ref()))
+            .await;
+    let card = match rendered {
+        Ok(Ok(card)) => card,
+        Ok(Err(e)) => {
+            return HttpResponse::BadRequest().json(CqResult::<Nothing>::error(
+                500,
+                &format!("render failed: {}", e),
+            ));
+        }
+        Err(e) => {
+            error!("{} ERROR!!!", e);
+            return HttpResponse::InternalServerError()
+                .json(CqResult::<Nothing>::error(500, "render failed"));
+        }
+    };
+
+    let file_name = format!(
+        "{}_{}_{}.png",
+        prompt_id,
+        req.template,
+        &uuid::Uuid::new_v4().simple().to_string()[..8]
+    );
+    if let Err(e) = save_output(&config, &file_name, card).await {
+        error!("{} ERROR!!!", e);
+        return HttpResponse::InternalServerError().json(CqResult::<Nothing>::error(
+            500,
+            "save rendered image failed",
+        ));
+    }
+    HttpResponse::Ok().json(CqResult::success(json!({
+        "file_name": file_name,
+        "url": file_url(&config, &file_name, user.pubkey.as_deref()),
+    })))
+}
+
+/// Combines the images of tasks and memories into one poster. Memories are
+/// captioned with their title, or their date on a timeline.
+#[post("/collage")]
+async fn collage(
+    config: web::Data<crate::conf::config::Config>,
+    user: MaybeAuthUser,
+    req: web::Json<CollageRequest>,
+) -> HttpResponse {
+    if req.items.is_empty() || req.items.len() > MAX_RENDER_IMAGES {
+        return HttpResponse::BadRequest().json(CqResult::<Nothing>::error(
+            500,
+            &format!("a collage takes 1 to {} items", MAX_RENDER_IMAGES),
+        ));
+    }
+    let mut tiles = Vec::with_capacity(req.items.len());
+    for item in &req.items {
+        match load_collage_tile(&config, &user, item, req.layout).await {
+            Ok(tile) => tiles.push(tile),
+            Err(e) => {
+                error!("load collage item failed: {}", e);
+                return HttpResponse::NotFound()
+                    .json(CqResult::<Nothing>::error(500, &e.to_string()));
+            }
+        }
+    }
+    if req.layout == Layout::Timeline {
+        tiles.sort_by_key(|(time, _)| *time);
+    }
+    let tiles = tiles
+        .into_iter()
+        .map(|(_, mut tile)| {
+            if !req.captions {
+                tile.caption = None;
+            }
+            tile
+        })
+        .collect::<Vec<_>>();
+    let options = LayoutOptions {
+        size: req
+            .size
+            .unwrap_or(match req.layout {
+                Layout::Timeline => 480,
+                _ => 1600,
+            })
+            .clamp(1, layout::MAX_POSTER_SIDE),
+        columns: req
+            .columns
+            .map(|columns| columns.clamp(1, MAX_RENDER_IMAGES as u32)),
+        gap: req.gap.unwrap_or(16).min(layout::MAX_GAP),
+        background: req.background.clone().unwrap_or("#ffffff".to_string()),
+        color: req.color.clone().unwrap_or("#222222".to_string()),
+        title: req.title.clone(),
+    };
+    let font = load_font(&config).await;
+    let layout = req.layout;
+    let composed =
+        web::block(move || layout::compose(layout, &tiles, &options, font.as_ref())).await;
+    let poster = match composed {
+        Ok(Ok(poster)) => poster,
+        Ok(Err(e)) => {
+            return HttpResponse::BadRequest().json(CqResult::<Nothing>::error(
+                500,
+                &format!("collage failed: {}", e),
+            ));
+        }
+        Err(e) => {
+            error!("{} ERROR!!!", e);
+            return HttpResponse::InternalServerError()
+                .json(CqResult::<Nothing>::error(500, "collage failed"));
+        }
+    };
+
+    let file_name = format!("collage_{}.png", uuid::Uuid::new_v4().simple());
+    let (width, height) = (poster.width(), poster.height());
+    if let Err(e) = save_output(&config, &file_name, poster).await {
+        error!("{} ERROR!!!", e);
+        return HttpResponse::InternalServerError()
+            .json(CqResult::<Nothing>::error(500, "save collage failed"));
+    }
+    HttpResponse::Ok().json(CqResult::success(json!({
+        "file_name": file_name,
+        "url": file_url(&config, &file_name, user.pubkey.as_deref()),
+        "width": width,
+        "height": height,
+    })))
+}
+
+/// Loads one collage image with the time it sorts by on a timeline.
+async fn load_collage_tile(
+    config: &crate::conf::config::Config,
+    user: &MaybeAuthUser,
+    item: &CollageItem,
+    layout: Layout,
+) -> anyhow::Result<(i64, Tile)> {
+    let date = |time: i64| {
+        chrono::DateTime::from_timestamp(time, 0)
+            .map(|t| t.format("%Y-%m-%d").to_string())
+            .unwrap_or_default()
+    };
+    match item {
+        CollageItem::Task(prompt_id) => {
+            if !may_use_task(config, user, prompt_id).await {
+                anyhow::bail!("only the author can use the image of task {}", prompt_id);
+            }
+            let image = load_task_image(config, prompt_id).await?;
+            let time = output_storage_from_config(config)?
+                .stat(&format!("{}.png", prompt_id))
+                .await?
+                .map_or(0, |meta| meta.modified);
+            let caption = (layout == Layout::Timeline).then(|| date(time));
+            Ok((time, Tile { image, caption }))
+        }
+        CollageItem::Memory(memory_id) => {
+            let memory = read_store(|store| {
+                store
+                    .memories
+                    .get(memory_id)
+                    .filter(|m| store.memory_visible_to(m, user.pubkey.as_deref()))
+                    .cloned()
+            })
+            .await
+            .ok_or_else(|| anyhow::anyhow!("memory {} not found", memory_id))?;
+            let image = load_memory_image(config, &memory).await?;
+            let caption = if layout == Layout::Timeline {
+                date(memory.sort_time())
+            } else {
+                memory.title.clone()
+            };
+            Ok((
+                memory.sort_time(),
+                Tile {
+                    image,
+                    caption: Some(caption),
+                },
+            ))
+        }
+    }
+}
+
+/// Whether `user` may use the image of a task: its author may, anyone else
+/// once a public memory shows it.
+async fn may_use_task(
+    config: &crate::conf::config::Config,
+    user: &MaybeAuthUser,
+    prompt_id: &str,
+) -> bool {
+    let file_name = format!("{}.png", prompt_id);
+    read_store(|store| store.may_view_file(config, prompt_id, &file_name, user.pubkey.as_deref()))
+        .await
+}
+
+/// Prefers the mirrored copy, then a task image served by `file_api`, and only
+/// then downloads the original.
+pub(crate) async fn load_memory_image(
+    config: &crate::conf::config::Config,
+    memory: &Memory,
+) -> anyhow::Result<DynamicImage> {
+    if let Some(sha256) = &memory.image_sha256
+        && let Some(data) = blob_store_from_config(config)?.get(sha256).await?
+    {
+        return decode_image(&data, config.upload_max_dimension);
+    }
+    let local_prefix = format!("{}/", config.img_tmp_point.trim_end_matches('/'));
+    if let Some(prompt_id) = memory
+        .image
+        .strip_prefix(&local_prefix)
+        .and_then(|file_name| file_name.strip_suffix(".png"))
+    {
+        return load_task_image(config, prompt_id).await;
+    }
+    let (data, _) = download_image(&memory.image, config).await?;
+    decode_image(&data, config.upload_max_dimension)
+}
+
+/// Encodes a rendered image as PNG into the output storage.
+async fn save_output(
+    config: &crate::conf::config::Config,
+    file_name: &str,
+    image: RgbaImage,
+) -> anyhow::Result<()> {
+    let data = web::block(move || {
+        let mut data = Vec::new();
+        image.write_to(&mut Cursor::new(&mut data), ImageFormat::Png)?;
+        anyhow::Ok(data)
+    })
+    .await??;
+    output_storage_from_config(config)?
+        .put(file_name, data, "image/png")
+        .await
+}
+
+/// Reads the image a finished task left in the output storage.
+pub(crate) async fn load_task_image(
+    config: &crate::conf::config::Config,
+    prompt_id: &str,
+) -> anyhow::Result<DynamicImage> {
+    if prompt_id.is_empty()
+        || !prompt_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        anyhow::bail!("invalid task id: {}", prompt_id);
+    }
+    let data = output_storage_from_config(config)?
+        .get(&format!("{}.png", prompt_id))
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("task image {} not found", prompt_id))?;
+    decode_image(&data, config.upload_max_dimension)
+}
+
+/// Captions are skipped when no font can be loaded.
+pub(crate) async fn load_font(config: &crate::conf::config::Config) -> Option<FontArc> {
+    let data = tokio::fs::read(&config.font_path)
+        .await
+        .map_err(|e| error!("read font {} failed: {}", config.font_path, e))
+        .ok()?;
+    FontArc::try_from_vec(data)
+        .map_err(|e| error!("load font {} failed: {}", config.font_path, e))
+        .ok()
+}
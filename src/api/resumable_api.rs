@@ -0,0 +1,196 @@
+use actix_web::{
+    HttpRequest, HttpResponse, HttpResponseBuilder, delete, get, head, http::StatusCode, options,
+    patch, post, web,
+};
+use serde_json::json;
+
+use crate::{
+    auth::jwt::AuthUser,
+    conf::config::Config,
+    upload::resumable::{
+        self, ResumableError, TUS_CHECKSUM_ALGORITHMS, TUS_EXTENSIONS, TUS_VERSION, WriteLock,
+    },
+    utils::result::{CqResult, Nothing},
+};
+
+use super::upload_api::UploadResponse;
+
+/// Content type of PATCH bodies in the tus protocol.
+const OFFSET_OCTET_STREAM: &str = "application/offset+octet-stream";
+
+fn tus(status: StatusCode) -> HttpResponseBuilder {
+    let mut builder = HttpResponse::build(status);
+    builder.insert_header(("Tus-Resumable", TUS_VERSION));
+    builder
+}
+
+fn error_response(e: ResumableError) -> HttpResponse {
+    let status = match &e {
+        ResumableError::NotFound => StatusCode::NOT_FOUND,
+        ResumableError::Forbidden => StatusCode::FORBIDDEN,
+        ResumableError::OffsetMismatch { .. } => StatusCode::CONFLICT,
+        ResumableError::Busy => StatusCode::LOCKED,
+        ResumableError::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+        // tus "Checksum Mismatch"
+        ResumableError::ChecksumMismatch => StatusCode::from_u16(460).unwrap(),
+        ResumableError::Invalid(_) => StatusCode::BAD_REQUEST,
+        ResumableError::Io(_) | ResumableError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    tus(status).json(CqResult::<Nothing>::error(
+        status.as_u16() as i32,
+        &e.to_string(),
+    ))
+}
+
+fn header<'a>(req: &'a HttpRequest, name: &str) -> Option<&'a str> {
+    req.headers().get(name).and_then(|v| v.to_str().ok())
+}
+
+fn u64_header(req: &HttpRequest, name: &str) -> Result<u64, ResumableError> {
+    header(req, name)
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(|| ResumableError::Invalid(format!("missing or invalid {}", name)))
+}
+
+/// Protocol discovery.
+#[options("/resumable")]
+async fn resumable_options(config: web::Data<Config>) -> HttpResponse {
+    tus(StatusCode::NO_CONTENT)
+        .insert_header(("Tus-Version", TUS_VERSION))
+        .insert_header(("Tus-Extension", TUS_EXTENSIONS))
+        .insert_header(("Tus-Max-Size", config.resumable_max_bytes.to_string()))
+        .insert_header(("Tus-Checksum-Algorithm", TUS_CHECKSUM_ALGORITHMS))
+        .finish()
+}
+
+/// Starts an upload of `Upload-Length` bytes. `Upload-Metadata` may carry a
+/// `checksum` (hex SHA-256) the finished file must match.
+#[post("/resumable")]
+async fn create_resumable(
+    config: web::Data<Config>,
+    user: AuthUser,
+    req: HttpRequest,
+) -> HttpResponse {
+    let created = async {
+        let length = u64_header(&req, "Upload-Length")?;
+        let metadata = resumable::parse_metadata(header(&req, "Upload-Metadata").unwrap_or(""))?;
+        resumable::create(&config, &user.pubkey, length, metadata).await
+    }
+    .await;
+    match created {
+        Ok(upload) => tus(StatusCode::CREATED)
+            .insert_header(("Location", format!("/resumable/{}", upload.id)))
+            .insert_header(("Upload-Offset", "0"))
+            .json(CqResult::success(upload)),
+        Err(e) => error_response(e),
+    }
+}
+
+/// Where to resume: the number of bytes received so far.
+#[head("/resumable/{id}")]
+async fn resumable_offset(
+    config: web::Data<Config>,
+    user: AuthUser,
+    path: web::Path<String>,
+) -> HttpResponse {
+    let upload = match resumable::find(&path.into_inner(), &user.pubkey).await {
+        Ok(upload) => upload,
+        Err(e) => return error_response(e),
+    };
+    match resumable::offset(&config, &upload).await {
+        Ok(offset) => tus(StatusCode::OK)
+            .insert_header(("Upload-Offset", offset.to_string()))
+            .insert_header(("Upload-Length", upload.length.to_string()))
+            .insert_header(("Cache-Control", "no-store"))
+            .finish(),
+        Err(e) => error_response(e),
+    }
+}
+
+/// The upload with its offset, and the file it became once finished.
+#[get("/resumable/{id}")]
+async fn get_resumable(
+    config: web::Data<Config>,
+    user: AuthUser,
+    path: web::Path<String>,
+) -> HttpResponse {
+    let upload = match resumable::find(&path.into_inner(), &user.pubkey).await {
+        Ok(upload) => upload,
+        Err(e) => return error_response(e),
+    };
+    match resumable::offset(&config, &upload).await {
+        Ok(offset) => tus(StatusCode::OK).json(CqResult::success(json!({
+            "upload": upload,
+            "offset": offset,
+        }))),
+        Err(e) => error_response(e),
+    }
+}
+
+/// Appends a chunk at `Upload-Offset`, optionally checked against
+/// `Upload-Checksum`. The chunk completing the upload stores the file and
+/// answers with it; earlier chunks answer 204 with the new offset.
+#[patch("/resumable/{id}")]
+async fn patch_resumable(
+    config: web::Data<Config>,
+    user: AuthUser,
+    path: web::Path<String>,
+    req: HttpRequest,
+    payload: web::Payload,
+) -> HttpResponse {
+    if header(&req, "Content-Type") != Some(OFFSET_OCTET_STREAM) {
+        return tus(StatusCode::UNSUPPORTED_MEDIA_TYPE).json(CqResult::<Nothing>::error(
+            415,
+            &format!("Content-Type must be {}", OFFSET_OCTET_STREAM),
+        ));
+    }
+    let result = async {
+        let upload = resumable::find(&path.into_inner(), &user.pubkey).await?;
+        let at = u64_header(&req, "Upload-Offset")?;
+        let checksum = header(&req, "Upload-Checksum")
+            .map(resumable::parse_checksum)
+            .transpose()?;
+        let _lock = WriteLock::acquire(&upload.id)?;
+        let offset = resumable::append(&config, &upload, at, payload, checksum).await?;
+        let finished = if offset == upload.length {
+            Some(resumable::finish(&config, &upload).await?)
+        } else {
+            None
+        };
+        Ok::<_, ResumableError>((offset, finished))
+    }
+    .await;
+    match result {
+        Ok((offset, Some((upload, duplicate)))) => tus(StatusCode::OK)
+            .insert_header(("Upload-Offset", offset.to_string()))
+            .json(CqResult::success(UploadResponse::new(
+                &config,
+                upload,
+                duplicate,
+                Some(&user.pubkey),
+            ))),
+        Ok((offset, None)) => tus(StatusCode::NO_CONTENT)
+            .insert_header(("Upload-Offset", offset.to_string()))
+            .finish(),
+        Err(e) => error_response(e),
+    }
+}
+
+/// Abandons an upload and deletes what was received.
+#[delete("/resumable/{id}")]
+async fn delete_resumable(
+    config: web::Data<Config>,
+    user: AuthUser,
+    path: web::Path<String>,
+) -> HttpResponse {
+    let result = async {
+        let upload = resumable::find(&path.into_inner(), &user.pubkey).await?;
+        let _lock = WriteLock::acquire(&upload.id)?;
+        resumable::discard(&config, &upload.id).await
+    }
+    .await;
+    match result {
+        Ok(()) => tus(StatusCode::NO_CONTENT).finish(),
+        Err(e) => error_response(e),
+    }
+}
@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use actix_web::{HttpResponse, delete, get, http::StatusCode, post, web};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    api::album_api::Denied,
+    auth::{
+        jwt::AuthUser,
+        signed_url::{blob_url, sign_url},
+    },
+    store::{
+        models::{Memory, ShareLink, Visibility},
+        store::{Store, read_store, write_store},
+    },
+    uploader::uploader::resolve_content_uri,
+    utils::result::CqResult,
+};
+
+/// How often view counts are added to the store.
+const VIEW_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Views of each link since the last flush, so anonymous visits do not each
+/// persist the store.
+static PENDING_VIEWS: Lazy<Mutex<HashMap<String, u64>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Adds the views counted since the last flush to their links.
+pub async fn flush_views() {
+    let pending = std::mem::take(&mut *PENDING_VIEWS.lock().unwrap());
+    if pending.is_empty() {
+        return;
+    }
+    write_store(|store| {
+        for (token, views) in pending {
+            if let Some(link) = store.share_links.get_mut(&token) {
+                link.views += views;
+            }
+        }
+    })
+    .await;
+}
+
+pub async fn run_view_flusher() {
+    let mut interval = tokio::time::interval(VIEW_FLUSH_INTERVAL);
+    loop {
+        interval.tick().await;
+        flush_views().await;
+    }
+}
+
+/// Whether `pubkey` may share the memory: it has to see it, and memories of
+/// private albums need someone who may edit them.
+fn may_share(store: &Store, memory: &Memory, pubkey: &str) -> bool {
+    let private = memory
+        .album_id
+        .as_ref()
+        .and_then(|album_id| store.albums.get(album_id))
+        .is_some_and(|album| album.visibility == Visibility::Private);
+    store.memory_visible_to(memory, Some(pubkey))
+        && (!private || store.can_edit_memory(memory, pubkey))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ShareRequest {
+    /// Seconds until the link expires.
+    expires_in: Option<i64>,
+}
+
+/// Creates a public link to a memory. Memories of private albums can only be
+/// shared by those who may edit them.
+#[post("/memories/{memory_id}/share")]
+async fn create_share(
+    config: web::Data<crate::conf::config::Config>,
+    user: AuthUser,
+    path: web::Path<String>,
+    req: web::Json<ShareRequest>,
+) -> HttpResponse {
+    let memory_id = path.into_inner();
+    let now = chrono::Utc::now().timestamp();
+    let link = ShareLink {
+        token: bs58::encode(rand::random::<[u8; 12]>()).into_string(),
+        memory_id: memory_id.clone(),
+        created_by: user.pubkey.clone(),
+        created_at: now,
+        expires_at: req.expires_in.map(|secs| now + secs),
+        views: 0,
+    };
+    let created = write_store(|store| {
+        let memory = store
+            .memories
+            .get(&memory_id)
+            .ok_or(Denied::NotFound("memory"))?;
+        if !may_share(store, memory, &user.pubkey) {
+            return Err(Denied::Forbidden);
+        }
+        store.share_links.insert(link.token.clone(), link.clone());
+        Ok(())
+    })
+    .await;
+    match created {
+        Ok(()) => HttpResponse::Ok().json(CqResult::success(json!({
+            "link": link,
+            "url": format!("{}/s/{}", config.public_base_url, link.token),
+        }))),
+        Err(denied) => denied.response(),
+    }
+}
+
+/// Revokes a link. Only its creator may do so.
+#[delete("/shares/{token}")]
+async fn revoke_share(user: AuthUser, path: web::Path<String>) -> HttpResponse {
+    let token = path.into_inner();
+    let revoked = write_store(|store| {
+        let link = store
+            .share_links
+            .get(&token)
+            .ok_or(Denied::NotFound("share link"))?;
+        if link.created_by != user.pubkey {
+            return Err(Denied::Forbidden);
+        }
+        store
+            .share_links
+            .remove(&token)
+            .ok_or(Denied::NotFound("share link"))
+    })
+    .await
+    .map(|mut link| {
+        link.views += PENDING_VIEWS.lock().unwrap().remove(&token).unwrap_or(0);
+        link
+    });
+    match revoked {
+        Ok(link) => HttpResponse::Ok().json(CqResult::success(link)),
+        Err(denied) => denied.response(),
+    }
+}
+
+/// Serves the preview page social platforms unfurl, counting each view. The
+/// link stops working once its creator could no longer share the memory.
+#[get("/s/{token}")]
+async fn view_share(
+    config: web::Data<crate::conf::config::Config>,
+    path: web::Path<String>,
+) -> HttpResponse {
+    let token = path.into_inner();
+    let now = chrono::Utc::now().timestamp();
+    let viewed = read_store(|store| {
+        let link = store.share_links.get(&token)?;
+        if link.is_expired(now) {
+            return Some(Err("This link has expired."));
+        }
+        let memory = store.memories.get(&link.memory_id)?;
+        if !may_share(store, memory, &link.created_by) {
+            return Some(Err("This memory is no longer shared."));
+        }
+        let public = store.memory_visible_to(memory, None);
+        Some(Ok((memory.clone(), public)))
+    })
+    .await;
+    match viewed {
+        Some(Ok((memory, public))) => {
+            *PENDING_VIEWS
+                .lock()
+                .unwrap()
+                .entry(token.clone())
+                .or_default() += 1;
+            HttpResponse::Ok()
+                .content_type("text/html; charset=utf-8")
+                .body(share_page(&config, &token, &memory, public))
+        }
+        Some(Err(message)) => message_page(StatusCode::GONE, message),
+        None => message_page(StatusCode::NOT_FOUND, "This link does not exist."),
+    }
+}
+
+/// Prefers the mirrored copy, which outlives the original URL. A private
+/// memory's image URL is signed without binding it to a user, as the link
+/// stands in for album membership.
+fn share_image_url(config: &crate::conf::config::Config, memory: &Memory, public: bool) -> String {
+    match &memory.image_sha256 {
+        Some(sha256) if public => format!("{}/blob/{}", config.public_base_url, sha256),
+        Some(sha256) => blob_url(config, sha256, None),
+        None if public => resolve_content_uri(&memory.image, config),
+        None => sign_url(config, &resolve_content_uri(&memory.image, config), None),
+    }
+}
+
+fn share_page(
+    config: &crate::conf::config::Config,
+    token: &str,
+    memory: &Memory,
+    public: bool,
+) -> String {
+    let title = escape_html(&memory.title);
+    let content = escape_html(&memory.content);
+    let image = escape_html(&share_image_url(config, memory, public));
+    let url = escape_html(&format!("{}/s/{}", config.public_base_url, token));
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{title}</title>
+<meta name="description" content="{content}">
+<meta property="og:type" content="article">
+<meta property="og:site_name" content="Chroniq">
+<meta property="og:title" content="{title}">
+<meta property="og:description" content="{content}">
+<meta property="og:image" content="{image}">
+<meta property="og:url" content="{url}">
+<meta name="twitter:card" content="summary_large_image">
+<meta name="twitter:title" content="{title}">
+<meta name="twitter:description" content="{content}">
+<meta name="twitter:image" content="{image}">
+</head>
+<body>
+<h1>{title}</h1>
+<img src="{image}" alt="{title}" style="max-width:100%">
+<p>{content}</p>
+</body>
+</html>
+"#
+    )
+}
+
+fn message_page(status: StatusCode, message: &str) -> HttpResponse {
+    HttpResponse::build(status)
+        .content_type("text/html; charset=utf-8")
+        .body(format!(
+            "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Chroniq</title></head>\n<body><p>{}</p></body>\n</html>\n",
+            escape_html(message)
+        ))
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
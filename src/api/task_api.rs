@@ -1,22 +1,38 @@
-use std::{fs::File, io::BufReader};
+use std::{fs::File, io::BufReader, time::Duration};
 
-use actix_web::{HttpResponse, get, post, web};
+use actix_web::{HttpResponse, get, http::header, post, web};
 use log::error;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use tokio::sync::broadcast::error::RecvError;
 
 use crate::{
-    conf,
+    auth::{
+        jwt::{AuthUser, MaybeAuthUser},
+        signed_url::{file_url, with_query},
+    },
+    conf::{self, config::Config},
+    render::variant::pregenerate_variants,
     sd3::{self, ImagineRequest},
-    utils::result::{CqResult, Nothing},
-    ws,
+    storage::storage::local_output,
+    store::{
+        models::{Task, TaskLineage, VariationKind},
+        store::read_store,
+    },
+    utils::{
+        page::{CursorPage, CursorQuery},
+        result::{CqResult, Nothing},
+    },
+    ws::{
+        self,
+        task_ws::{TaskParams, TaskStatus},
+    },
 };
 #[derive(Debug, Serialize, Deserialize)]
 struct ImaRequest {
     #[serde(skip_serializing_if = "Option::is_none")]
     base64_array: Option<Vec<String>>,
     prompt: String,
-    author: String,
     style: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default = "default_steps")]
@@ -38,18 +54,90 @@ fn default_steps() -> Option<i32> {
     Some(4)
 }
 
+/// Narrows an author's task history. Times are unix seconds, inclusive.
+#[derive(Debug, Deserialize)]
+struct TaskFilter {
+    status: Option<TaskStatus>,
+    style: Option<String>,
+    from: Option<i64>,
+    to: Option<i64>,
+    /// Words that must each start a word of the prompt.
+    q: Option<String>,
+}
+
+impl TaskFilter {
+    fn matches(&self, task: &Task) -> bool {
+        let terms = self.q.as_deref().map(words).unwrap_or_default();
+        let prompt = words(&task.params.prompt);
+        self.status.as_ref().is_none_or(|s| *s == task.status)
+            && self
+                .style
+                .as_ref()
+                .is_none_or(|s| s.eq_ignore_ascii_case(&task.params.style))
+            && self.from.is_none_or(|from| task.created_at >= from)
+            && self.to.is_none_or(|to| task.created_at <= to)
+            && terms
+                .iter()
+                .all(|term| prompt.iter().any(|word| word.starts_with(term.as_str())))
+    }
+}
+
+/// Lowercase words of a prompt or search query.
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+#[derive(Debug, Serialize)]
+struct TaskView {
+    #[serde(flatten)]
+    task: Task,
+    /// Set once the image has been fetched.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    img_url: Option<String>,
+}
+
+/// Denoise of img2img variations when none is asked for.
+const DEFAULT_DENOISE: f64 = 0.6;
+
+#[derive(Debug, Deserialize)]
+struct TweakRequest {
+    prompt: String,
+    style: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Img2imgRequest {
+    /// How far the result may move away from the parent's image, 0 to 1.
+    denoise: Option<f64>,
+    prompt: Option<String>,
+}
+
+/// What a variation changes about its parent.
+struct Variation {
+    kind: VariationKind,
+    prompt: String,
+    style: String,
+    seed: Option<u32>,
+    input_image: Option<String>,
+    denoise: Option<f64>,
+}
+
+/// How often an idle event stream gets a comment, so proxies keep it open.
+const EVENT_KEEPALIVE: Duration = Duration::from_secs(15);
+
 #[post("/submit_imageine")]
 async fn submit_imageine(
     config: web::Data<crate::conf::config::Config>,
+    user: AuthUser,
     req: web::Json<ImaRequest>,
 ) -> HttpResponse {
-    if req.author.clone().trim().is_empty()
-        || req.prompt.clone().trim().is_empty()
-        || req.style.clone().trim().is_empty()
-    {
+    if req.prompt.clone().trim().is_empty() || req.style.clone().trim().is_empty() {
         return HttpResponse::BadRequest().json(CqResult::<Nothing>::error(
             500,
-            "author prompt style can not be empty",
+            "prompt style can not be empty",
         ));
     }
     let prompt = format!("{},{}", req.prompt.clone(), req.style.clone());
@@ -61,19 +149,36 @@ async fn submit_imageine(
         sd3_clip_name1: config.sd3_clip_name1.clone(),
         sd3_clip_name2: config.sd3_clip_name2.clone(),
         sd3_clip_name3: config.sd3_clip_name3.clone(),
+        seed: None,
+        input_image: None,
+        denoise: None,
     };
     let sd3_client = sd3::SD3Client::new(&config.sd3_base_server);
     match sd3_client.submit_imagine(imagine_request).await {
-        Ok(res) => {
+        Ok((res, seed)) => {
+            // params first, so the submitted event reaches the author
+            ws::task_ws::record_task(
+                &res,
+                ws::task_ws::TaskParams {
+                    prompt: req.prompt.clone(),
+                    style: req.style.clone(),
+                    steps: req.steps.unwrap(),
+                    seed,
+                    author: user.pubkey.clone(),
+                    model: config.sd3_model_file_name.clone(),
+                },
+                None,
+            )
+            .await;
             ws::task_ws::update_task_status(&res, ws::task_ws::TaskStatus::Submited).await;
-            return HttpResponse::Ok().json(CqResult::<String>::success(res));
+            HttpResponse::Ok().json(CqResult::<String>::success(res))
         }
         Err(e) => {
             error!("{} ERROR!!!", e);
-            return HttpResponse::BadRequest().json(CqResult::<Nothing>::error(
+            HttpResponse::BadRequest().json(CqResult::<Nothing>::error(
                 500,
                 "submit task failed , please check your prompt",
-            ));
+            ))
         }
     }
 }
@@ -83,29 +188,288 @@ async fn submit_imageine(
 pub async fn fetch_task(
     config: web::Data<crate::conf::config::Config>,
     path: web::Path<String>,
+    user: MaybeAuthUser,
 ) -> HttpResponse {
     let prompt_id = path.into_inner();
+    let viewer = user.pubkey.as_deref();
+    let file_name = format!("{}.png", prompt_id);
+    if !read_store(|store| store.may_view_file(&config, &prompt_id, &file_name, viewer)).await {
+        return HttpResponse::Forbidden().json(CqResult::<Nothing>::error(
+            403,
+            "only the author can fetch this task",
+        ));
+    }
     let sd3client = sd3::SD3Client::new(&config.sd3_base_server);
 
-    match sd3client
-        .fetch_sd3_image(&config.img_tmp_path, &prompt_id)
-        .await
-    {
+    match sd3client.fetch_sd3_image(&config, &prompt_id).await {
         Ok((state, img_name)) => {
             if img_name.is_empty() {
                 return HttpResponse::Ok()
                     .json(CqResult::success("task is not finish yet".to_string()));
             }
-            return HttpResponse::Ok().json(CqResult::<serde_json::Value>::success(
-                json!({"task_state":state,"img_url":format!("{}/{}",config.img_tmp_point,img_name)}),
+            tokio::spawn(pregenerate_variants(
+                config.get_ref().clone(),
+                img_name.clone(),
             ));
+            let img_url = file_url(&config, &img_name, viewer);
+            HttpResponse::Ok().json(CqResult::<serde_json::Value>::success(json!({
+                "task_state": state,
+                "thumbnail_url": with_query(&img_url, "w=256&format=webp"),
+                "img_url": img_url,
+            })))
         }
         Err(e) => {
             error!("{} ERROR!!!", e);
-            return HttpResponse::BadRequest().json(CqResult::<Nothing>::error(
+            HttpResponse::BadRequest().json(CqResult::<Nothing>::error(
                 500,
                 "fetch task failed , please check your prompt_id",
-            ));
+            ))
         }
+    }
+}
+
+/// Server-sent events for the caller's generation and export tasks.
+#[get("/task_events")]
+async fn task_events(user: AuthUser) -> HttpResponse {
+    let events = ws::task_ws::subscribe_task_events();
+    let stream =
+        futures_util::stream::unfold((events, user.pubkey), |(mut events, pubkey)| async move {
+            loop {
+                let chunk = match tokio::time::timeout(EVENT_KEEPALIVE, events.recv()).await {
+                    Err(_) => ": keepalive\n\n".to_string(),
+                    Ok(Ok(event)) if event.author.as_deref() == Some(pubkey.as_str()) => {
+                        let data = serde_json::to_string(&event).unwrap_or_default();
+                        format!("event: task\ndata: {}\n\n", data)
+                    }
+                    Ok(Ok(_)) | Ok(Err(RecvError::Lagged(_))) => continue,
+                    Ok(Err(RecvError::Closed)) => return None,
+                };
+                return Some((
+                    Ok::<_, actix_web::Error>(web::Bytes::from(chunk)),
+                    (events, pubkey),
+                ));
+            }
+        });
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .streaming(stream)
+}
+
+/// The caller's past generations, newest first.
+#[get("/authors/{author}/tasks")]
+async fn list_author_tasks(
+    config: web::Data<crate::conf::config::Config>,
+    user: AuthUser,
+    path: web::Path<String>,
+    filter: web::Query<TaskFilter>,
+    query: web::Query<CursorQuery>,
+) -> HttpResponse {
+    let author = path.into_inner();
+    if author != user.pubkey {
+        return HttpResponse::Forbidden().json(CqResult::<Nothing>::error(
+            403,
+            "tasks are only shown to their author",
+        ));
+    }
+    let mut tasks = read_store(|store| {
+        store
+            .tasks
+            .values()
+            .filter(|t| t.params.author == author && filter.matches(t))
+            .cloned()
+            .collect::<Vec<_>>()
+    })
+    .await;
+    tasks.sort_by(|a, b| (b.created_at, &b.id).cmp(&(a.created_at, &a.id)));
+    let page = CursorPage::paginate(tasks, &query, |t| (t.created_at, t.id.clone()));
+    HttpResponse::Ok().json(CqResult::success(page.map(|task| {
+        let img_url = (task.status == TaskStatus::ExecutionSuccess)
+            .then(|| file_url(&config, &format!("{}.png", task.id), Some(&author)));
+        TaskView { task, img_url }
+    })))
+}
+
+fn read_workflow(path: &str) -> anyhow::Result<serde_json::Value> {
+    Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+}
+
+/// The task to vary, when the caller submitted it.
+async fn own_task(task_id: &str, pubkey: &str) -> Result<Task, HttpResponse> {
+    match read_store(|store| store.tasks.get(task_id).cloned()).await {
+        Some(task) if task.params.author == pubkey => Ok(task),
+        Some(_) => Err(HttpResponse::Forbidden().json(CqResult::<Nothing>::error(
+            403,
+            "only the author can vary a task",
+        ))),
+        None => {
+            Err(HttpResponse::NotFound().json(CqResult::<Nothing>::error(500, "task not found")))
+        }
+    }
+}
+
+/// Queues a variation with the parent's steps and stores it with its lineage.
+async fn submit_variation(config: &Config, parent: &Task, variation: Variation) -> HttpResponse {
+    let workflow_path = match variation.input_image {
+        Some(_) => &config.img2img_json_path,
+        None => &config.wf_json_path,
+    };
+    let workflow = match read_workflow(workflow_path) {
+        Ok(workflow) => workflow,
+        Err(e) => {
+            error!("read workflow {} failed: {}", workflow_path, e);
+            return HttpResponse::InternalServerError()
+                .json(CqResult::<Nothing>::error(500, "workflow unavailable"));
+        }
+    };
+    let imagine_request = ImagineRequest {
+        prompt: format!("{},{}", variation.prompt, variation.style),
+        steps: parent.params.steps,
+        workflow,
+        sd3_model_file: config.sd3_model_file_name.clone(),
+        sd3_clip_name1: config.sd3_clip_name1.clone(),
+        sd3_clip_name2: config.sd3_clip_name2.clone(),
+        sd3_clip_name3: config.sd3_clip_name3.clone(),
+        seed: variation.seed,
+        input_image: variation.input_image,
+        denoise: variation.denoise,
+    };
+    let sd3_client = sd3::SD3Client::new(&config.sd3_base_server);
+    match sd3_client.submit_imagine(imagine_request).await {
+        Ok((task_id, seed)) => {
+            let params = TaskParams {
+                prompt: variation.prompt,
+                style: variation.style,
+                steps: parent.params.steps,
+                seed,
+                author: parent.params.author.clone(),
+                model: config.sd3_model_file_name.clone(),
+            };
+            let lineage = TaskLineage {
+                parent_id: parent.id.clone(),
+                kind: variation.kind,
+                denoise: variation.denoise,
+            };
+            ws::task_ws::record_task(&task_id, params, Some(lineage)).await;
+            ws::task_ws::update_task_status(&task_id, TaskStatus::Submited).await;
+            let task = read_store(|store| store.tasks.get(&task_id).cloned()).await;
+            HttpResponse::Ok().json(CqResult::success(task))
+        }
+        Err(e) => {
+            error!("{} ERROR!!!", e);
+            HttpResponse::BadRequest().json(CqResult::<Nothing>::error(
+                500,
+                "submit task failed , please check your prompt",
+            ))
+        }
+    }
+}
+
+/// Generates again with the same parameters and a new seed.
+#[post("/tasks/{task_id}/rerun")]
+async fn rerun_task(
+    config: web::Data<Config>,
+    user: AuthUser,
+    path: web::Path<String>,
+) -> HttpResponse {
+    let parent = match own_task(&path.into_inner(), &user.pubkey).await {
+        Ok(parent) => parent,
+        Err(response) => return response,
+    };
+    let variation = Variation {
+        kind: VariationKind::Rerun,
+        prompt: parent.params.prompt.clone(),
+        style: parent.params.style.clone(),
+        seed: None,
+        input_image: None,
+        denoise: None,
+    };
+    submit_variation(&config, &parent, variation).await
+}
+
+/// Keeps the seed, so the composition stays close, with an edited prompt.
+#[post("/tasks/{task_id}/tweak")]
+async fn tweak_task(
+    config: web::Data<Config>,
+    user: AuthUser,
+    path: web::Path<String>,
+    req: web::Json<TweakRequest>,
+) -> HttpResponse {
+    let parent = match own_task(&path.into_inner(), &user.pubkey).await {
+        Ok(parent) => parent,
+        Err(response) => return response,
+    };
+    let req = req.into_inner();
+    let style = req.style.unwrap_or_else(|| parent.params.style.clone());
+    if req.prompt.trim().is_empty() || style.trim().is_empty() {
+        return HttpResponse::BadRequest().json(CqResult::<Nothing>::error(
+            500,
+            "prompt style can not be empty",
+        ));
+    }
+    let variation = Variation {
+        kind: VariationKind::Tweak,
+        prompt: req.prompt,
+        style,
+        seed: Some(parent.params.seed),
+        input_image: None,
+        denoise: None,
+    };
+    submit_variation(&config, &parent, variation).await
+}
+
+/// Starts from the parent's image instead of noise.
+#[post("/tasks/{task_id}/img2img")]
+async fn img2img_task(
+    config: web::Data<Config>,
+    user: AuthUser,
+    path: web::Path<String>,
+    req: web::Json<Img2imgRequest>,
+) -> HttpResponse {
+    let parent = match own_task(&path.into_inner(), &user.pubkey).await {
+        Ok(parent) => parent,
+        Err(response) => return response,
+    };
+    let req = req.into_inner();
+    let denoise = req.denoise.unwrap_or(DEFAULT_DENOISE);
+    if !(denoise > 0.0 && denoise <= 1.0) {
+        return HttpResponse::BadRequest().json(CqResult::<Nothing>::error(
+            500,
+            "denoise must be above 0 and at most 1",
+        ));
+    }
+    let file_name = format!("{}.png", parent.id);
+    let image = match local_output(&config, &file_name).await {
+        Ok(Some(path)) => tokio::fs::read(path).await.ok(),
+        Ok(None) => None,
+        Err(e) => {
+            error!("fetch {} failed: {}", file_name, e);
+            None
+        }
+    };
+    let Some(image) = image else {
+        return HttpResponse::BadRequest()
+            .json(CqResult::<Nothing>::error(500, "task has no image yet"));
+    };
+    let sd3_client = sd3::SD3Client::new(&config.sd3_base_server);
+    let input_image = match sd3_client.upload_image(&file_name, image).await {
+        Ok(name) => name,
+        Err(e) => {
+            error!("{} ERROR!!!", e);
+            return HttpResponse::BadRequest()
+                .json(CqResult::<Nothing>::error(500, "upload input image failed"));
+        }
+    };
+    let variation = Variation {
+        kind: VariationKind::Img2img,
+        prompt: req
+            .prompt
+            .filter(|prompt| !prompt.trim().is_empty())
+            .unwrap_or_else(|| parent.params.prompt.clone()),
+        style: parent.params.style.clone(),
+        seed: None,
+        input_image: Some(input_image),
+        denoise: Some(denoise),
     };
+    submit_variation(&config, &parent, variation).await
 }
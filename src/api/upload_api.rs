@@ -0,0 +1,142 @@
+use actix_multipart::Multipart;
+use actix_web::{HttpResponse, get, post, web};
+use futures_util::StreamExt;
+use serde::Serialize;
+
+use crate::{
+    auth::{
+        jwt::{AuthUser, MaybeAuthUser},
+        signed_url::{file_url, sign_video},
+    },
+    conf::config::Config,
+    store::{models::Upload, store::read_store},
+    upload::upload::{check_image, store_upload},
+    utils::result::{CqResult, Nothing},
+};
+
+#[derive(Debug, Serialize)]
+pub(crate) struct UploadResponse {
+    #[serde(flatten)]
+    upload: Upload,
+    url: String,
+    /// The same content was uploaded before; the earlier record is returned.
+    duplicate: bool,
+}
+
+impl UploadResponse {
+    /// The url is signed for `viewer` when signed URLs are on.
+    pub(crate) fn new(
+        config: &Config,
+        mut upload: Upload,
+        duplicate: bool,
+        viewer: Option<&str>,
+    ) -> Self {
+        if let Some(video) = upload.video.as_mut() {
+            sign_video(config, video, &upload.file_name, viewer);
+        }
+        UploadResponse {
+            url: file_url(config, &upload.file_name, viewer),
+            upload,
+            duplicate,
+        }
+    }
+}
+
+/// Takes an image in the `file` field of a multipart form. The returned id can
+/// be published like a task id.
+#[post("/uploads")]
+async fn upload_image(
+    config: web::Data<Config>,
+    user: AuthUser,
+    mut form: Multipart,
+) -> HttpResponse {
+    let mut data = None;
+    while let Some(field) = form.next().await {
+        let mut field = match field {
+            Ok(field) => field,
+            Err(e) => {
+                return HttpResponse::BadRequest()
+                    .json(CqResult::<Nothing>::error(500, &e.to_string()));
+            }
+        };
+        let is_file = field.name() == Some("file");
+        let mut bytes = Vec::new();
+        while let Some(chunk) = field.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    return HttpResponse::BadRequest()
+                        .json(CqResult::<Nothing>::error(500, &e.to_string()));
+                }
+            };
+            if bytes.len() + chunk.len() > config.upload_max_bytes {
+                return HttpResponse::PayloadTooLarge().json(CqResult::<Nothing>::error(
+                    413,
+                    &format!("file exceeds {} bytes", config.upload_max_bytes),
+                ));
+            }
+            if is_file {
+                bytes.extend_from_slice(&chunk);
+            }
+        }
+        if is_file {
+            data = Some(bytes);
+        }
+    }
+    let Some(data) = data else {
+        return HttpResponse::BadRequest()
+            .json(CqResult::<Nothing>::error(500, "missing file field"));
+    };
+
+    let max_dimension = config.upload_max_dimension;
+    let image = match web::block(move || check_image(data, max_dimension)).await {
+        Ok(Ok(image)) => image,
+        Ok(Err(e)) => {
+            return HttpResponse::BadRequest()
+                .json(CqResult::<Nothing>::error(500, &e.to_string()));
+        }
+        Err(e) => {
+            return HttpResponse::InternalServerError()
+                .json(CqResult::<Nothing>::error(500, &e.to_string()));
+        }
+    };
+    match store_upload(&config, image, &user.pubkey).await {
+        Ok((upload, duplicate)) => HttpResponse::Ok().json(CqResult::success(UploadResponse::new(
+            &config,
+            upload,
+            duplicate,
+            Some(&user.pubkey),
+        ))),
+        Err(e) => HttpResponse::InternalServerError().json(CqResult::<Nothing>::error(
+            500,
+            &format!("store upload failed: {}", e),
+        )),
+    }
+}
+
+#[get("/uploads/{file_id}")]
+async fn get_upload(
+    config: web::Data<Config>,
+    user: MaybeAuthUser,
+    path: web::Path<String>,
+) -> HttpResponse {
+    let file_id = path.into_inner();
+    let viewer = user.pubkey.as_deref();
+    let found = read_store(|store| {
+        store.uploads.get(&file_id).cloned().map(|upload| {
+            let allowed = store.may_view_file(&config, &file_id, &upload.file_name, viewer);
+            (upload, allowed)
+        })
+    })
+    .await;
+    match found {
+        Some((upload, true)) => HttpResponse::Ok().json(CqResult::success(UploadResponse::new(
+            &config, upload, false, viewer,
+        ))),
+        Some((_, false)) => HttpResponse::Forbidden().json(CqResult::<Nothing>::error(
+            403,
+            "only the uploader can see this upload",
+        )),
+        None => HttpResponse::NotFound().json(CqResult::<Nothing>::error(404, "upload not found")),
+    }
+}
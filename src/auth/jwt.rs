@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use std::future::{Ready, ready};
+use std::sync::Arc;
+
+use actix_web::{FromRequest, HttpRequest, HttpResponse, dev::Payload, error::InternalError, web};
+use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::{
+    conf::config::Config,
+    utils::result::{CqResult, Nothing},
+};
+
+/// How long a sign-in challenge stays valid, in seconds.
+const CHALLENGE_TTL_SECS: i64 = 300;
+
+/// Most sign-in challenges pending at once.
+const MAX_CHALLENGES: usize = 10_000;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Claims {
+    sub: String,
+    exp: i64,
+}
+
+struct Challenge {
+    pubkey: String,
+    message: String,
+    expires_at: i64,
+}
+
+/// Sign-in messages handed out, by nonce, so asking for a new one never
+/// replaces another client's.
+static GLOBAL_CHALLENGES: Lazy<Arc<Mutex<HashMap<String, Challenge>>>> =
+    Lazy::new(|| Arc::new(Mutex::new(HashMap::new())));
+
+/// Creates the message a wallet has to sign to log in as `pubkey`, with the
+/// nonce to log in with. Expired challenges are dropped first; `None` when
+/// too many are still pending.
+pub async fn issue_challenge(pubkey: &str) -> Option<(String, String)> {
+    let now = chrono::Utc::now().timestamp();
+    let mut map = GLOBAL_CHALLENGES.lock().await;
+    map.retain(|_, challenge| challenge.expires_at > now);
+    if map.len() >= MAX_CHALLENGES {
+        return None;
+    }
+    let nonce = uuid::Uuid::new_v4().simple().to_string();
+    let message = format!("Sign in to Chroniq as {}\nnonce: {}", pubkey, nonce);
+    map.insert(
+        nonce.clone(),
+        Challenge {
+            pubkey: pubkey.to_string(),
+            message: message.clone(),
+            expires_at: now + CHALLENGE_TTL_SECS,
+        },
+    );
+    Some((nonce, message))
+}
+
+/// Consumes the challenge issued under `nonce`, if it was issued to `pubkey`
+/// and has not expired.
+pub async fn take_challenge(nonce: &str, pubkey: &str) -> Option<String> {
+    let mut map = GLOBAL_CHALLENGES.lock().await;
+    let challenge = map.remove(nonce)?;
+    (challenge.pubkey == pubkey && challenge.expires_at > chrono::Utc::now().timestamp())
+        .then_some(challenge.message)
+}
+
+pub fn issue_token(config: &Config, pubkey: &str) -> anyhow::Result<String> {
+    let claims = Claims {
+        sub: pubkey.to_string(),
+        exp: chrono::Utc::now().timestamp() + config.jwt_ttl_secs,
+    };
+    Ok(jsonwebtoken::encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(config.jwt_secret.as_bytes()),
+    )?)
+}
+
+fn verify_token(config: &Config, token: &str) -> anyhow::Result<String> {
+    let data = jsonwebtoken::decode::<Claims>(
+        token,
+        &DecodingKey::from_secret(config.jwt_secret.as_bytes()),
+        &Validation::default(),
+    )?;
+    Ok(data.claims.sub)
+}
+
+fn bearer_pubkey(req: &HttpRequest) -> Option<Result<String, &'static str>> {
+    let header = req.headers().get(actix_web::http::header::AUTHORIZATION)?;
+    let Some(token) = header.to_str().ok().and_then(|v| v.strip_prefix("Bearer ")) else {
+        return Some(Err("malformed authorization header"));
+    };
+    let Some(config) = req.app_data::<web::Data<Config>>() else {
+        return Some(Err("server is not configured"));
+    };
+    Some(verify_token(config, token.trim()).map_err(|_| "invalid or expired token"))
+}
+
+fn unauthorized(message: &str) -> actix_web::Error {
+    InternalError::from_response(
+        message.to_string(),
+        HttpResponse::Unauthorized().json(CqResult::<Nothing>::error(401, message)),
+    )
+    .into()
+}
+
+/// The pubkey of a request carrying a valid `Authorization: Bearer` token.
+#[derive(Debug, Clone)]
+pub struct AuthUser {
+    pub pubkey: String,
+}
+
+impl FromRequest for AuthUser {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(match bearer_pubkey(req) {
+            Some(Ok(pubkey)) => Ok(AuthUser { pubkey }),
+            Some(Err(message)) => Err(unauthorized(message)),
+            None => Err(unauthorized("authorization required")),
+        })
+    }
+}
+
+/// Like [`AuthUser`], for endpoints that also serve anonymous readers.
+/// A present but invalid token is still rejected.
+#[derive(Debug, Clone)]
+pub struct MaybeAuthUser {
+    pub pubkey: Option<String>,
+}
+
+impl FromRequest for MaybeAuthUser {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(match bearer_pubkey(req) {
+            Some(Ok(pubkey)) => Ok(MaybeAuthUser {
+                pubkey: Some(pubkey),
+            }),
+            Some(Err(message)) => Err(unauthorized(message)),
+            None => Ok(MaybeAuthUser { pubkey: None }),
+        })
+    }
+}
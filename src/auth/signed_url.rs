@@ -0,0 +1,165 @@
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+
+use crate::{
+    conf::config::Config,
+    store::models::{Memory, VideoInfo},
+};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Signature parameters of a file URL.
+#[derive(Debug, Deserialize)]
+pub struct SignedQuery {
+    /// Unix time the URL stops working.
+    pub exp: Option<i64>,
+    pub sig: Option<String>,
+    /// Pubkey the URL is bound to.
+    pub u: Option<String>,
+}
+
+/// Why a file URL was refused.
+#[derive(Debug, thiserror::Error, PartialEq)]
+pub enum UrlDenied {
+    #[error("signed URL required")]
+    Missing,
+    #[error("invalid signature")]
+    Invalid,
+    #[error("URL expired")]
+    Expired,
+    #[error("URL is bound to another user")]
+    WrongUser,
+}
+
+fn mac(config: &Config, file_name: &str, exp: i64, user: Option<&str>) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(config.file_url_secret.as_bytes())
+        .expect("HMAC accepts keys of any size");
+    mac.update(format!("{}\n{}\n{}", file_name, exp, user.unwrap_or_default()).as_bytes());
+    mac
+}
+
+/// Appends query parameters to a URL that may already carry some.
+pub fn with_query(url: &str, query: &str) -> String {
+    let separator = if url.contains('?') { '&' } else { '?' };
+    format!("{}{}{}", url, separator, query)
+}
+
+/// The `img_tmp_point` URL of a file, signed for `FILE_URL_TTL_SECS` when
+/// signed URLs are on. `user` binds it to a pubkey if `FILE_URL_BIND_USER`
+/// is set.
+pub fn file_url(config: &Config, file_name: &str, user: Option<&str>) -> String {
+    let url = format!("{}/{}", config.img_tmp_point, file_name);
+    match signature(config, file_name, user) {
+        Some(query) => with_query(&url, &query),
+        None => url,
+    }
+}
+
+/// What the signature of a mirrored image covers, apart from file names.
+pub(crate) fn blob_name(sha256: &str) -> String {
+    format!("blob/{}", sha256)
+}
+
+/// The URL of a mirrored image, signed like `file_url`.
+pub fn blob_url(config: &Config, sha256: &str, user: Option<&str>) -> String {
+    let url = format!("{}/blob/{}", config.public_base_url, sha256);
+    match signature(config, &blob_name(sha256), user) {
+        Some(query) => with_query(&url, &query),
+        None => url,
+    }
+}
+
+/// The query granting access to `file_name`, or `None` when signed URLs are
+/// off.
+fn signature(config: &Config, file_name: &str, user: Option<&str>) -> Option<String> {
+    if !config.signed_urls {
+        return None;
+    }
+    let user = user.filter(|_| config.file_url_bind_user);
+    let exp = chrono::Utc::now().timestamp() + config.file_url_ttl_secs;
+    let sig = hex::encode(mac(config, file_name, exp, user).finalize().into_bytes());
+    let query = format!("exp={}&sig={}", exp, sig);
+    match user {
+        Some(user) => Some(format!("{}&u={}", query, user)),
+        None => Some(query),
+    }
+}
+
+/// The file a URL served by us points at, ignoring any query.
+pub(crate) fn local_file_name<'a>(config: &Config, url: &'a str) -> Option<&'a str> {
+    let prefix = format!("{}/", config.img_tmp_point.trim_end_matches('/'));
+    let file_name = url.strip_prefix(&prefix)?;
+    let file_name = file_name
+        .split_once('?')
+        .map_or(file_name, |(name, _)| name);
+    (!file_name.is_empty() && !file_name.contains('/')).then_some(file_name)
+}
+
+/// Signs a URL when it points at a file we serve; others are returned as is.
+pub fn sign_url(config: &Config, url: &str, user: Option<&str>) -> String {
+    match local_file_name(config, url) {
+        Some(file_name) => file_url(config, file_name, user),
+        None => url.to_string(),
+    }
+}
+
+/// Drops the signature from a URL of ours before it is stored, so stored
+/// URLs never expire and are signed afresh when handed out.
+pub fn unsigned_url(config: &Config, url: &str) -> String {
+    match local_file_name(config, url) {
+        Some(file_name) => format!("{}/{}", config.img_tmp_point, file_name),
+        None => url.to_string(),
+    }
+}
+
+/// Signs the file URLs of a memory hidden from the public.
+pub fn sign_memory(config: &Config, memory: &mut Memory, public: bool, viewer: Option<&str>) {
+    if public || !config.signed_urls {
+        return;
+    }
+    if let (Some(file_name), Some(video)) = (
+        local_file_name(config, &memory.image),
+        memory.video.as_mut(),
+    ) {
+        sign_video(config, video, file_name, viewer);
+    }
+    memory.image = sign_url(config, &memory.image, viewer);
+}
+
+/// Signs the poster and playlist URLs of the video stored as `file_name`.
+/// Playlists are signed for the video itself, and `file_api` passes the
+/// signature on to the playlists and segments they list.
+pub fn sign_video(config: &Config, video: &mut VideoInfo, file_name: &str, user: Option<&str>) {
+    if let Some(poster_url) = video.poster_url.as_mut() {
+        *poster_url = sign_url(config, poster_url, user);
+    }
+    if let (Some(hls_url), Some(query)) =
+        (video.hls_url.as_mut(), signature(config, file_name, user))
+    {
+        *hls_url = with_query(hls_url, &query);
+    }
+}
+
+/// Checks the signature of a request for `file_name` made by `requester`.
+pub fn verify(
+    config: &Config,
+    file_name: &str,
+    query: &SignedQuery,
+    requester: Option<&str>,
+) -> Result<(), UrlDenied> {
+    let (Some(exp), Some(sig)) = (query.exp, &query.sig) else {
+        return Err(UrlDenied::Missing);
+    };
+    let sig = hex::decode(sig).map_err(|_| UrlDenied::Invalid)?;
+    mac(config, file_name, exp, query.u.as_deref())
+        .verify_slice(&sig)
+        .map_err(|_| UrlDenied::Invalid)?;
+    if exp < chrono::Utc::now().timestamp() {
+        return Err(UrlDenied::Expired);
+    }
+    if query.u.is_some() && query.u.as_deref() != requester {
+        return Err(UrlDenied::WrongUser);
+    }
+    Ok(())
+}
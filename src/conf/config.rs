@@ -21,7 +21,68 @@ pub struct Config {
     pub img_tmp_point: String,
     pub img_tmp_path: String,
     pub wf_json_path: String,
+    pub img2img_json_path: String,
     pub solana_points: Vec<String>,
+    pub require_memo_signer: bool,
+    pub payment_treasury: Option<String>,
+    pub payment_mint: Option<String>,
+    pub payment_amount: u64,
+    pub blob_backend: String,
+    pub blob_local_path: String,
+    pub mirror_max_bytes: usize,
+    pub s3_endpoint: Option<String>,
+    pub s3_bucket: Option<String>,
+    pub s3_region: String,
+    pub s3_access_key: Option<String>,
+    pub s3_secret_key: Option<String>,
+    pub output_backend: String,
+    /// Key prefix of outputs in `S3_BUCKET`.
+    pub output_s3_prefix: String,
+    /// Redirect to presigned storage URLs instead of proxying originals.
+    pub output_presign: bool,
+    pub uploader: String,
+    pub ipfs_api: String,
+    pub ipfs_gateway: String,
+    pub arweave_node: String,
+    pub arweave_gateway: String,
+    pub arweave_wallet_path: Option<String>,
+    pub public_base_url: String,
+    pub nft_symbol: String,
+    pub nft_seller_fee_bps: u16,
+    pub bubblegum_tree: Option<String>,
+    pub tree_authority_keypair: Option<String>,
+    pub store_path: String,
+    pub jwt_secret: String,
+    pub jwt_ttl_secs: i64,
+    pub membership_memo_ops: bool,
+    pub comment_memo_ops: bool,
+    pub moderation_blocklist: Vec<String>,
+    pub moderation_webhook: Option<String>,
+    pub moderation_fail_closed: bool,
+    pub template_path: String,
+    pub font_path: String,
+    pub png_privacy_mode: bool,
+    pub watermark: bool,
+    pub manifest_keypair: String,
+    pub upload_max_bytes: usize,
+    pub upload_max_dimension: u32,
+    pub resumable_path: String,
+    pub resumable_max_bytes: u64,
+    /// Resumable uploads untouched for this long are dropped; 0 keeps them.
+    pub resumable_ttl_secs: u64,
+    pub ffmpeg_path: String,
+    pub ffprobe_path: String,
+    /// Heights of the HLS renditions; empty disables transcoding.
+    pub hls_renditions: Vec<u32>,
+    pub signed_urls: bool,
+    pub file_url_secret: String,
+    pub file_url_ttl_secs: i64,
+    pub file_url_bind_user: bool,
+    /// Unpinned temp files unused for this long are deleted; 0 keeps them.
+    pub temp_ttl_secs: u64,
+    /// Size the temp directory is trimmed to; 0 means no quota.
+    pub temp_quota_bytes: u64,
+    pub janitor_interval_secs: u64,
 }
 
 impl Config {
@@ -50,12 +111,141 @@ impl Config {
             env::var("SD3_CLIP_NAME3").map_err(|_| ConfigError::MissingEnvVar("SD3_CLIP_NAME3"))?;
         let wf_json_path =
             env::var("WF_JSON_PATH").map_err(|_| ConfigError::MissingEnvVar("WF_JSON_PATH"))?;
+        let img2img_json_path =
+            env::var("IMG2IMG_JSON_PATH").unwrap_or_else(|_| "sd3_json/img2img.json".to_string());
         let solana_points_str =
             env::var("SOLANA_POINTS").map_err(|_| ConfigError::MissingEnvVar("SOLANA_POINTS"))?;
         let solana_points: Vec<String> = solana_points_str
             .split(",")
             .map(|s| s.to_string())
             .collect();
+        let require_memo_signer = env::var("REQUIRE_MEMO_SIGNER")
+            .map(|v| v == "true")
+            .unwrap_or(false);
+        let payment_treasury = env::var("PAYMENT_TREASURY").ok();
+        let payment_mint = env::var("PAYMENT_MINT").ok();
+        let payment_amount = env::var("PAYMENT_AMOUNT")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(0);
+        let blob_backend = env::var("BLOB_BACKEND").unwrap_or_else(|_| "local".to_string());
+        let blob_local_path =
+            env::var("BLOB_LOCAL_PATH").unwrap_or_else(|_| "blob_store".to_string());
+        let mirror_max_bytes = env::var("MIRROR_MAX_BYTES")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .unwrap_or(20 * 1024 * 1024);
+        let s3_endpoint = env::var("S3_ENDPOINT").ok();
+        let s3_bucket = env::var("S3_BUCKET").ok();
+        let s3_region = env::var("S3_REGION").unwrap_or_else(|_| "us-east-1".to_string());
+        let s3_access_key = env::var("S3_ACCESS_KEY").ok();
+        let s3_secret_key = env::var("S3_SECRET_KEY").ok();
+        let output_backend = env::var("OUTPUT_BACKEND").unwrap_or_else(|_| "local".to_string());
+        let output_s3_prefix =
+            env::var("OUTPUT_S3_PREFIX").unwrap_or_else(|_| "outputs/".to_string());
+        let output_presign = env::var("OUTPUT_PRESIGN")
+            .map(|v| v == "true")
+            .unwrap_or(false);
+        let uploader = env::var("UPLOADER").unwrap_or_else(|_| "none".to_string());
+        let ipfs_api = env::var("IPFS_API").unwrap_or_else(|_| "http://127.0.0.1:5001".to_string());
+        let ipfs_gateway =
+            env::var("IPFS_GATEWAY").unwrap_or_else(|_| "http://127.0.0.1:8080".to_string());
+        let arweave_node =
+            env::var("ARWEAVE_NODE").unwrap_or_else(|_| "http://127.0.0.1:1984".to_string());
+        let arweave_gateway = env::var("ARWEAVE_GATEWAY").unwrap_or_else(|_| arweave_node.clone());
+        let arweave_wallet_path = env::var("ARWEAVE_WALLET_PATH").ok();
+        let public_base_url =
+            env::var("PUBLIC_BASE_URL").unwrap_or_else(|_| format!("http://{}", server_addr));
+        let nft_symbol = env::var("NFT_SYMBOL").unwrap_or_else(|_| "CHRO".to_string());
+        let nft_seller_fee_bps = env::var("NFT_SELLER_FEE_BPS")
+            .ok()
+            .and_then(|v| v.parse::<u16>().ok())
+            .unwrap_or(0);
+        let bubblegum_tree = env::var("BUBBLEGUM_TREE").ok();
+        let tree_authority_keypair = env::var("TREE_AUTHORITY_KEYPAIR").ok();
+        let store_path = env::var("STORE_PATH").unwrap_or_else(|_| "data/store.json".to_string());
+        // without a configured secret tokens do not survive a restart
+        let jwt_secret = env::var("JWT_SECRET").unwrap_or_else(|_| Uuid::new_v4().to_string());
+        let jwt_ttl_secs = env::var("JWT_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(86400);
+        let membership_memo_ops = env::var("MEMBERSHIP_MEMO_OPS")
+            .map(|v| v == "true")
+            .unwrap_or(false);
+        let comment_memo_ops = env::var("COMMENT_MEMO_OPS")
+            .map(|v| v == "true")
+            .unwrap_or(false);
+        let moderation_blocklist = env::var("MODERATION_BLOCKLIST")
+            .map(|v| {
+                v.split(',')
+                    .map(|w| w.trim().to_string())
+                    .filter(|w| !w.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let moderation_webhook = env::var("MODERATION_WEBHOOK").ok();
+        let moderation_fail_closed = env::var("MODERATION_FAIL_CLOSED")
+            .map(|v| v == "true")
+            .unwrap_or(false);
+        let template_path = env::var("TEMPLATE_PATH").unwrap_or_else(|_| "templates".to_string());
+        let font_path = env::var("FONT_PATH")
+            .unwrap_or_else(|_| "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf".to_string());
+        let png_privacy_mode = env::var("PNG_PRIVACY_MODE")
+            .map(|v| v == "true")
+            .unwrap_or(false);
+        let watermark = env::var("WATERMARK").map(|v| v != "false").unwrap_or(true);
+        let manifest_keypair = env::var("MANIFEST_KEYPAIR")
+            .unwrap_or_else(|_| "data/manifest_keypair.json".to_string());
+        let upload_max_bytes = env::var("UPLOAD_MAX_BYTES")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .unwrap_or(20 * 1024 * 1024);
+        let upload_max_dimension = env::var("UPLOAD_MAX_DIMENSION")
+            .ok()
+            .and_then(|v| v.parse::<u32>().ok())
+            .unwrap_or(8192);
+        let resumable_path = env::var("RESUMABLE_PATH").unwrap_or_else(|_| "resumable".to_string());
+        let resumable_max_bytes = env::var("RESUMABLE_MAX_BYTES")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(4 * 1024 * 1024 * 1024);
+        let resumable_ttl_secs = env::var("RESUMABLE_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(86400);
+        let ffmpeg_path = env::var("FFMPEG_PATH").unwrap_or_else(|_| "ffmpeg".to_string());
+        let ffprobe_path = env::var("FFPROBE_PATH").unwrap_or_else(|_| "ffprobe".to_string());
+        let hls_renditions = env::var("HLS_RENDITIONS")
+            .map(|v| {
+                v.split(',')
+                    .filter_map(|h| h.trim().parse::<u32>().ok())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let signed_urls = env::var("SIGNED_URLS")
+            .map(|v| v == "true")
+            .unwrap_or(false);
+        let file_url_secret = env::var("FILE_URL_SECRET").unwrap_or_else(|_| jwt_secret.clone());
+        let file_url_ttl_secs = env::var("FILE_URL_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(3600);
+        let file_url_bind_user = env::var("FILE_URL_BIND_USER")
+            .map(|v| v == "true")
+            .unwrap_or(false);
+        let temp_ttl_secs = env::var("TEMP_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(7 * 86400);
+        let temp_quota_bytes = env::var("TEMP_QUOTA_BYTES")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(0);
+        let janitor_interval_secs = env::var("JANITOR_INTERVAL_SECS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(3600);
         Ok(Config {
             server_addr,
             log_level,
@@ -68,7 +258,62 @@ impl Config {
             img_tmp_path,
             img_tmp_point,
             wf_json_path,
+            img2img_json_path,
             solana_points,
+            require_memo_signer,
+            payment_treasury,
+            payment_mint,
+            payment_amount,
+            blob_backend,
+            blob_local_path,
+            mirror_max_bytes,
+            s3_endpoint,
+            s3_bucket,
+            s3_region,
+            s3_access_key,
+            s3_secret_key,
+            output_backend,
+            output_s3_prefix,
+            output_presign,
+            uploader,
+            ipfs_api,
+            ipfs_gateway,
+            arweave_node,
+            arweave_gateway,
+            arweave_wallet_path,
+            public_base_url,
+            nft_symbol,
+            nft_seller_fee_bps,
+            bubblegum_tree,
+            tree_authority_keypair,
+            store_path,
+            jwt_secret,
+            jwt_ttl_secs,
+            membership_memo_ops,
+            comment_memo_ops,
+            moderation_blocklist,
+            moderation_webhook,
+            moderation_fail_closed,
+            template_path,
+            font_path,
+            png_privacy_mode,
+            watermark,
+            manifest_keypair,
+            upload_max_bytes,
+            upload_max_dimension,
+            resumable_path,
+            resumable_max_bytes,
+            resumable_ttl_secs,
+            ffmpeg_path,
+            ffprobe_path,
+            hls_renditions,
+            signed_urls,
+            file_url_secret,
+            file_url_ttl_secs,
+            file_url_bind_user,
+            temp_ttl_secs,
+            temp_quota_bytes,
+            janitor_interval_secs,
         })
     }
 }
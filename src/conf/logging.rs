@@ -6,7 +6,7 @@ use std::io::Write;
 
 pub fn init_logger(config: &Config) {
     let log_file = BasicRollingFileAppender::new(
-        &format!("logs/{}.log", chrono::Local::now().format("%Y-%m-%d")),
+        format!("logs/{}.log", chrono::Local::now().format("%Y-%m-%d")),
         RollingConditionBasic::new().daily(),
         30,
     )
@@ -0,0 +1,294 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::sync::Arc;
+
+use log::{error, info};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use serde_json::json;
+use tokio::sync::Mutex;
+
+use crate::{
+    api::publish_api::get_published,
+    auth::signed_url::{file_url, local_file_name},
+    conf::config::Config,
+    storage::storage::{blob_store_from_config, local_output, output_storage_from_config},
+    store::{
+        models::{Memory, PublishedMemory},
+        store::read_store,
+    },
+    ws::task_ws::{
+        TaskEvent, TaskKind, TaskParams, TaskStatus, get_task_params, publish_task_event,
+        tasks_by_author,
+    },
+};
+
+use super::archive::ZipWriter;
+
+/// What an export bundles.
+#[derive(Debug, Serialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ExportSource {
+    Album {
+        album_id: String,
+    },
+    /// Memories and generated images of one author.
+    Author {
+        author: String,
+    },
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct ExportJob {
+    pub id: String,
+    pub owner: String,
+    pub source: ExportSource,
+    pub status: TaskStatus,
+    /// The ZIP in the output storage, once written.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub created_at: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<i64>,
+}
+
+/// One memory or generated image, as listed in the archive's `metadata.json`.
+#[derive(Debug, Serialize)]
+struct ExportItem {
+    /// Path inside the archive; `None` when the image is no longer stored.
+    file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    memory: Option<Memory>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    task_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    params: Option<TaskParams>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    published: Option<PublishedMemory>,
+}
+
+static GLOBAL_EXPORTS: Lazy<Arc<Mutex<HashMap<String, ExportJob>>>> =
+    Lazy::new(|| Arc::new(Mutex::new(HashMap::new())));
+
+pub async fn get_export(export_id: &str) -> Option<ExportJob> {
+    let map = GLOBAL_EXPORTS.lock().await;
+    map.get(export_id).cloned()
+}
+
+/// Records the new state of a job and tells its owner; a finished export
+/// carries a signed download link.
+async fn update_export(config: &Config, job: ExportJob) {
+    let data = match (&job.file_name, &job.error) {
+        (Some(file_name), _) => Some(json!({
+            "file_name": file_name,
+            "url": file_url(config, file_name, Some(&job.owner)),
+        })),
+        (None, Some(error)) => Some(json!({ "error": error })),
+        (None, None) => None,
+    };
+    publish_task_event(TaskEvent {
+        task_id: job.id.clone(),
+        kind: TaskKind::Export,
+        status: job.status.clone(),
+        data,
+        author: Some(job.owner.clone()),
+    });
+    GLOBAL_EXPORTS.lock().await.insert(job.id.clone(), job);
+}
+
+/// Queues an export and builds it in the background.
+pub async fn start_export(config: &Config, owner: &str, source: ExportSource) -> ExportJob {
+    let job = ExportJob {
+        id: uuid::Uuid::new_v4().simple().to_string(),
+        owner: owner.to_string(),
+        source,
+        status: TaskStatus::Submited,
+        file_name: None,
+        error: None,
+        created_at: chrono::Utc::now().timestamp(),
+        finished_at: None,
+    };
+    update_export(config, job.clone()).await;
+    tokio::spawn(run_export(config.clone(), job.clone()));
+    job
+}
+
+async fn run_export(config: Config, mut job: ExportJob) {
+    job.status = TaskStatus::Executing;
+    update_export(&config, job.clone()).await;
+    match write_export(&config, &job).await {
+        Ok(file_name) => {
+            info!("export {} written to {}", job.id, file_name);
+            job.status = TaskStatus::ExecutionSuccess;
+            job.file_name = Some(file_name);
+        }
+        Err(e) => {
+            error!("export {} failed: {}", job.id, e);
+            job.status = TaskStatus::ExecutionFailed;
+            job.error = Some(e.to_string());
+        }
+    }
+    job.finished_at = Some(chrono::Utc::now().timestamp());
+    update_export(&config, job).await;
+}
+
+/// The memories of the export and, for an author, the images they generated
+/// that no memory shows. A memory only brings along the file, parameters and
+/// publish record of a task or upload its own author made.
+async fn collect_items(config: &Config, source: &ExportSource) -> Vec<(ExportItem, String)> {
+    let mut memories = read_store(|store| {
+        store
+            .memories
+            .values()
+            .filter(|m| match source {
+                ExportSource::Album { album_id } => m.album_id.as_ref() == Some(album_id),
+                ExportSource::Author { author } => &m.author == author,
+            })
+            .cloned()
+            .collect::<Vec<_>>()
+    })
+    .await;
+    memories.sort_by_key(|m| m.sort_time());
+
+    let mut items = Vec::new();
+    let mut shown = HashSet::new();
+    for memory in memories {
+        let mut file_name = local_file_name(config, &memory.image)
+            .unwrap_or_default()
+            .to_string();
+        let task_id = Path::new(&file_name)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or_default()
+            .to_string();
+        let owner = read_store(|store| store.file_name_owner(&file_name).map(str::to_string)).await;
+        let owned = owner.as_deref() == Some(memory.author.as_str());
+        if owner.is_some() && !owned {
+            // the memory shows someone else's task or upload
+            file_name.clear();
+        }
+        let params = get_task_params(&task_id)
+            .await
+            .filter(|params| params.author == memory.author);
+        let published = if owned {
+            get_published(&task_id).await
+        } else {
+            None
+        };
+        shown.insert(task_id.clone());
+        items.push((
+            ExportItem {
+                file: None,
+                task_id: params.is_some().then_some(task_id),
+                params,
+                published,
+                memory: Some(memory),
+            },
+            file_name,
+        ));
+    }
+    if let ExportSource::Author { author } = source {
+        let mut tasks = tasks_by_author(author).await;
+        tasks.retain(|(task_id, _)| !shown.contains(task_id));
+        tasks.sort_by(|a, b| a.0.cmp(&b.0));
+        for (task_id, params) in tasks {
+            let file_name = format!("{}.png", task_id);
+            items.push((
+                ExportItem {
+                    file: None,
+                    published: get_published(&task_id).await,
+                    task_id: Some(task_id),
+                    params: Some(params),
+                    memory: None,
+                },
+                file_name,
+            ));
+        }
+    }
+    items
+}
+
+/// The bytes of an image, from our outputs or, for mirrored images, the blob
+/// store.
+async fn image_data(config: &Config, file_name: &str, memory: Option<&Memory>) -> Option<Vec<u8>> {
+    if !file_name.is_empty()
+        && let Ok(Some(path)) = local_output(config, file_name).await
+    {
+        return tokio::fs::read(path).await.ok();
+    }
+    let sha256 = memory?.image_sha256.as_deref()?;
+    blob_store_from_config(config)
+        .ok()?
+        .get(sha256)
+        .await
+        .ok()?
+}
+
+/// Writes the ZIP next to the other outputs, then hands it to the output
+/// storage.
+async fn write_export(config: &Config, job: &ExportJob) -> anyhow::Result<String> {
+    let file_name = format!("export_{}.zip", job.id);
+    let path = Path::new(&config.img_tmp_path).join(&file_name);
+    let tmp_path = path.with_extension("zip.part");
+    if let Err(e) = write_archive(config, job, &tmp_path).await {
+        let _ = tokio::fs::remove_file(&tmp_path).await;
+        return Err(e);
+    }
+    tokio::fs::rename(&tmp_path, &path).await?;
+
+    if config.output_backend != "local" {
+        let data = tokio::fs::read(&path).await?;
+        output_storage_from_config(config)?
+            .put(&file_name, data, "application/zip")
+            .await?;
+    }
+    Ok(file_name)
+}
+
+/// Adds the images one at a time, then `metadata.json` describing them.
+async fn write_archive(config: &Config, job: &ExportJob, tmp_path: &Path) -> anyhow::Result<()> {
+    let file = File::create(tmp_path)?;
+    let mut writer = ZipWriter::new(BufWriter::new(file));
+
+    let mut entries = Vec::new();
+    let mut added = HashSet::new();
+    for (mut item, source_name) in collect_items(config, &job.source).await {
+        if let Some(data) = image_data(config, &source_name, item.memory.as_ref()).await {
+            let name = if source_name.is_empty() {
+                // a mirrored image, named after its memory
+                let id = item.memory.as_ref().map_or("", |m| m.id.as_str());
+                let extension = image::guess_format(&data)
+                    .ok()
+                    .and_then(|format| format.extensions_str().first().copied())
+                    .unwrap_or("bin");
+                format!("images/{}.{}", id, extension)
+            } else {
+                format!("images/{}", source_name)
+            };
+            if added.insert(name.clone()) {
+                writer = tokio::task::spawn_blocking({
+                    let name = name.clone();
+                    move || writer.add(&name, &data).map(|_| writer)
+                })
+                .await??;
+            }
+            item.file = Some(name);
+        }
+        entries.push(item);
+    }
+    let metadata = serde_json::to_vec_pretty(&json!({
+        "source": job.source,
+        "exported_at": chrono::Utc::now().timestamp(),
+        "items": entries,
+    }))?;
+    tokio::task::spawn_blocking(move || {
+        writer.add("metadata.json", &metadata)?;
+        writer.finish()?.into_inner().map_err(|e| e.into_error())
+    })
+    .await??;
+    Ok(())
+}
@@ -1,4 +1,4 @@
-use std::{io::ErrorKind, sync::Arc};
+use std::sync::Arc;
 
 use actix_web::{App, HttpServer, web};
 use log::info;
@@ -9,11 +9,24 @@ mod sd3;
 mod utils;
 mod ws;
 mod solana;
+mod storage;
+mod store;
+mod uploader;
+mod auth;
+mod moderation;
+mod render;
+mod provenance;
+mod upload;
+mod export;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     let config = conf::config::Config::from_env().expect("Failed to load configuration");
     conf::logging::init_logger(&config);
+    store::store::init_store(&config.store_path)
+        .await
+        .map_err(std::io::Error::other)?;
+    provenance::manifest::init_signing_key(&config).map_err(std::io::Error::other)?;
     let config_arc = Arc::new(config.clone());
 
     let serve_addr = config.server_addr.clone();
@@ -27,18 +40,86 @@ async fn main() -> std::io::Result<()> {
     info!("serve was running: {}", &serve_addr);
     let server_handle = serve.handle();
     tokio::spawn(serve);
+    tokio::spawn(storage::janitor::run_janitor((*config_arc).clone()));
+    tokio::spawn(api::share_api::run_view_flusher());
     tokio::spawn(async move {
         let _ = ws::task_ws::ws_connect(config_arc).await;
     });
     tokio::signal::ctrl_c()
         .await
-        .map_err(|e| std::io::Error::new(ErrorKind::Other, e))?;
+        .map_err(std::io::Error::other)?;
     server_handle.stop(true).await;
+    api::share_api::flush_views().await;
     Ok(())
 }
 
 pub fn router_config(cfg: &mut web::ServiceConfig) {
     cfg.service(api::task_api::submit_imageine)
         .service(api::task_api::fetch_task)
-        .service(api::file_api::file);
+        .service(api::task_api::task_events)
+        .service(api::task_api::list_author_tasks)
+        .service(api::task_api::rerun_task)
+        .service(api::task_api::tweak_task)
+        .service(api::task_api::img2img_task)
+        .service(api::file_api::file)
+        .service(api::file_api::file_metadata)
+        .service(api::file_api::hls)
+        .service(api::file_api::blob)
+        .service(api::publish_api::publish)
+        .service(api::publish_api::fetch_metadata)
+        .service(api::nft_api::mint)
+        .service(api::nft_api::fetch_mint)
+        .service(api::album_api::create_album)
+        .service(api::album_api::list_albums)
+        .service(api::album_api::get_album)
+        .service(api::album_api::update_album)
+        .service(api::album_api::delete_album)
+        .service(api::album_api::add_memory)
+        .service(api::album_api::list_memories)
+        .service(api::album_api::list_author_memories)
+        .service(api::album_api::get_memory)
+        .service(api::album_api::update_memory)
+        .service(api::album_api::delete_memory)
+        .service(api::album_api::index_memory)
+        .service(api::auth_api::challenge)
+        .service(api::auth_api::login)
+        .service(api::member_api::create_invitation)
+        .service(api::member_api::get_invitation)
+        .service(api::member_api::accept_invitation)
+        .service(api::member_api::list_members)
+        .service(api::member_api::set_member_role)
+        .service(api::member_api::remove_member)
+        .service(api::comment_api::like)
+        .service(api::comment_api::unlike)
+        .service(api::comment_api::fetch_likes)
+        .service(api::comment_api::add_comment)
+        .service(api::comment_api::list_comments)
+        .service(api::comment_api::delete_comment)
+        .service(api::comment_api::moderate_comment)
+        .service(api::feed_api::follow)
+        .service(api::feed_api::unfollow)
+        .service(api::feed_api::list_following)
+        .service(api::feed_api::list_followers)
+        .service(api::feed_api::feed)
+        .service(api::share_api::create_share)
+        .service(api::share_api::revoke_share)
+        .service(api::share_api::view_share)
+        .service(api::render_api::list_templates)
+        .service(api::render_api::render)
+        .service(api::render_api::collage)
+        .service(api::provenance_api::get_manifest)
+        .service(api::provenance_api::provenance_key)
+        .service(api::provenance_api::verify)
+        .service(api::upload_api::upload_image)
+        .service(api::upload_api::get_upload)
+        .service(api::resumable_api::resumable_options)
+        .service(api::resumable_api::create_resumable)
+        .service(api::resumable_api::resumable_offset)
+        .service(api::resumable_api::get_resumable)
+        .service(api::resumable_api::patch_resumable)
+        .service(api::resumable_api::delete_resumable)
+        .service(api::metrics_api::prometheus_metrics)
+        .service(api::export_api::export_album)
+        .service(api::export_api::export_author)
+        .service(api::export_api::fetch_export);
 }
@@ -0,0 +1,29 @@
+use async_trait::async_trait;
+
+use crate::store::models::Comment;
+
+use super::moderation::{Moderator, Verdict};
+
+/// Hides comments containing any of the configured words, case-insensitively.
+pub struct BlocklistModerator {
+    words: Vec<String>,
+}
+
+impl BlocklistModerator {
+    pub fn new(words: &[String]) -> Self {
+        BlocklistModerator {
+            words: words.iter().map(|w| w.to_lowercase()).collect(),
+        }
+    }
+}
+
+#[async_trait]
+impl Moderator for BlocklistModerator {
+    async fn review(&self, comment: &Comment) -> anyhow::Result<Verdict> {
+        let body = comment.body.to_lowercase();
+        if self.words.iter().any(|word| body.contains(word.as_str())) {
+            return Ok(Verdict::Hide);
+        }
+        Ok(Verdict::Allow)
+    }
+}
@@ -0,0 +1,4 @@
+pub mod blocklist_moderator;
+#[allow(clippy::module_inception)]
+pub mod moderation;
+pub mod webhook_moderator;
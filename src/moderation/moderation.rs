@@ -0,0 +1,66 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use log::error;
+use serde::{Deserialize, Serialize};
+
+use crate::{conf::config::Config, store::models::Comment};
+
+use super::{blocklist_moderator::BlocklistModerator, webhook_moderator::WebhookModerator};
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(tag = "action", content = "reason", rename_all = "snake_case")]
+pub enum Verdict {
+    Allow,
+    /// Keep the comment but do not show it.
+    Hide,
+    Reject(String),
+}
+
+/// Reviews a comment before it is stored.
+#[async_trait]
+pub trait Moderator: Send + Sync {
+    async fn review(&self, comment: &Comment) -> anyhow::Result<Verdict>;
+}
+
+/// The moderation hooks enabled by `MODERATION_BLOCKLIST` and `MODERATION_WEBHOOK`.
+pub fn moderators_from_config(config: &Config) -> anyhow::Result<Vec<Arc<dyn Moderator>>> {
+    let mut moderators: Vec<Arc<dyn Moderator>> = Vec::new();
+    if !config.moderation_blocklist.is_empty() {
+        moderators.push(Arc::new(BlocklistModerator::new(
+            &config.moderation_blocklist,
+        )));
+    }
+    if let Some(url) = &config.moderation_webhook {
+        moderators.push(Arc::new(WebhookModerator::new(url)?));
+    }
+    Ok(moderators)
+}
+
+/// Runs every hook and returns the first verdict that is not `Allow`. A failing
+/// hook lets the comment through so an outage does not block all comments,
+/// unless `MODERATION_FAIL_CLOSED` is set, when the comment is rejected.
+pub async fn moderate(config: &Config, comment: &Comment) -> Verdict {
+    let failed = |e: anyhow::Error| {
+        error!("moderate comment {} failed: {}", comment.id, e);
+        config
+            .moderation_fail_closed
+            .then(|| Verdict::Reject("moderation is unavailable, try again later".to_string()))
+    };
+    let moderators = match moderators_from_config(config) {
+        Ok(moderators) => moderators,
+        Err(e) => return failed(e).unwrap_or(Verdict::Allow),
+    };
+    for moderator in moderators {
+        match moderator.review(comment).await {
+            Ok(Verdict::Allow) => {}
+            Ok(verdict) => return verdict,
+            Err(e) => {
+                if let Some(verdict) = failed(e) {
+                    return verdict;
+                }
+            }
+        }
+    }
+    Verdict::Allow
+}
@@ -0,0 +1,42 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use reqwest::Client;
+
+use crate::store::models::Comment;
+
+use super::moderation::{Moderator, Verdict};
+
+/// How long a comment waits for the webhook before moderation counts as failed.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Posts the comment as JSON to an external service, which answers with a
+/// verdict such as `{"action":"allow"}` or `{"action":"reject","reason":"spam"}`.
+pub struct WebhookModerator {
+    client: Client,
+    url: String,
+}
+
+impl WebhookModerator {
+    pub fn new(url: &str) -> anyhow::Result<Self> {
+        Ok(WebhookModerator {
+            client: Client::builder().timeout(WEBHOOK_TIMEOUT).build()?,
+            url: url.to_string(),
+        })
+    }
+}
+
+#[async_trait]
+impl Moderator for WebhookModerator {
+    async fn review(&self, comment: &Comment) -> anyhow::Result<Verdict> {
+        Ok(self
+            .client
+            .post(&self.url)
+            .json(comment)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<Verdict>()
+            .await?)
+    }
+}
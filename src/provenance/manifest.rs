@@ -0,0 +1,200 @@
+use std::path::Path;
+use std::str::FromStr;
+
+use log::warn;
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signature, Signer, read_keypair_file, write_keypair_file},
+};
+
+use crate::{conf::config::Config, storage::storage::output_storage_from_config};
+
+/// What the service asserts about an image it generated.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Claim {
+    pub generator: String,
+    pub ai_generated: bool,
+    pub task_id: String,
+    pub model: String,
+    pub created_at: i64,
+    /// SHA-256 of the file as it left the service.
+    pub content_sha256: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub watermark: Option<String>,
+}
+
+/// A claim signed with the service's ed25519 key, stored as a JSON sidecar
+/// next to the image.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Manifest {
+    pub claim: Claim,
+    /// Base58 public key of the signer.
+    pub signer: String,
+    /// Base58 signature of the claim's JSON.
+    pub signature: String,
+}
+
+static SIGNING_KEY: OnceCell<Keypair> = OnceCell::new();
+
+/// Loads the key of `MANIFEST_KEYPAIR` at startup. When the file does not
+/// exist yet a new key is saved there, so manifests stay verifiable after a
+/// restart; a file that cannot be read stops the service.
+pub fn init_signing_key(config: &Config) -> anyhow::Result<()> {
+    let path = Path::new(&config.manifest_keypair);
+    let keypair = if path.exists() {
+        read_keypair_file(path).map_err(|e| {
+            anyhow::anyhow!("read MANIFEST_KEYPAIR {} failed: {}", path.display(), e)
+        })?
+    } else {
+        let keypair = Keypair::new();
+        write_keypair_file(&keypair, path).map_err(|e| {
+            anyhow::anyhow!("write MANIFEST_KEYPAIR {} failed: {}", path.display(), e)
+        })?;
+        warn!(
+            "MANIFEST_KEYPAIR {} did not exist, created a new manifest key {}; back it up, manifests signed with it cannot be verified without it",
+            path.display(),
+            keypair.pubkey()
+        );
+        keypair
+    };
+    let _ = SIGNING_KEY.set(keypair);
+    Ok(())
+}
+
+/// The key manifests are signed with.
+pub fn signing_key() -> &'static Keypair {
+    SIGNING_KEY
+        .get()
+        .expect("the manifest key is loaded at startup")
+}
+
+pub fn sign(claim: Claim) -> anyhow::Result<Manifest> {
+    let key = signing_key();
+    let signature = key.sign_message(&serde_json::to_vec(&claim)?);
+    Ok(Manifest {
+        claim,
+        signer: key.pubkey().to_string(),
+        signature: signature.to_string(),
+    })
+}
+
+/// Whether the manifest is signed by our key and untampered.
+pub fn verify(manifest: &Manifest) -> bool {
+    let key = signing_key().pubkey();
+    let (Ok(signer), Ok(signature), Ok(message)) = (
+        Pubkey::from_str(&manifest.signer),
+        Signature::from_str(&manifest.signature),
+        serde_json::to_vec(&manifest.claim),
+    ) else {
+        return false;
+    };
+    signer == key && signature.verify(signer.as_ref(), &message)
+}
+
+/// Manifests sit next to their image in the output storage.
+pub fn manifest_key(task_id: &str) -> String {
+    format!("{}.manifest.json", task_id)
+}
+
+pub async fn write_manifest(config: &Config, manifest: &Manifest) -> anyhow::Result<()> {
+    output_storage_from_config(config)?
+        .put(
+            &manifest_key(&manifest.claim.task_id),
+            serde_json::to_vec_pretty(manifest)?,
+            "application/json",
+        )
+        .await
+}
+
+pub async fn read_manifest(config: &Config, task_id: &str) -> anyhow::Result<Option<Manifest>> {
+    if !task_id
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-')
+    {
+        anyhow::bail!("invalid task id: {}", task_id);
+    }
+    match output_storage_from_config(config)?
+        .get(&manifest_key(task_id))
+        .await?
+    {
+        Some(data) => Ok(Some(serde_json::from_slice(&data)?)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claim() -> Claim {
+        Claim {
+            generator: "Chroniq".to_string(),
+            ai_generated: true,
+            task_id: "5f0c6a1e-8a43-4c3b-9d2e-0b7c1f6d2a94".to_string(),
+            model: "sd3.5_large_turbo.safetensors".to_string(),
+            created_at: 1_760_000_000,
+            content_sha256: "ab".repeat(32),
+            watermark: Some(crate::provenance::watermark::WATERMARK_SCHEME.to_string()),
+        }
+    }
+
+    fn signed() -> Manifest {
+        SIGNING_KEY.get_or_init(Keypair::new);
+        sign(claim()).unwrap()
+    }
+
+    #[test]
+    fn signed_manifest_verifies() {
+        let manifest = signed();
+        assert_eq!(manifest.signer, signing_key().pubkey().to_string());
+        assert!(verify(&manifest));
+        let stored = serde_json::to_vec_pretty(&manifest).unwrap();
+        assert!(verify(&serde_json::from_slice(&stored).unwrap()));
+    }
+
+    #[test]
+    fn tampered_manifest_is_rejected() {
+        let mut manifest = signed();
+        manifest.claim.content_sha256 = "cd".repeat(32);
+        assert!(!verify(&manifest));
+
+        let mut manifest = signed();
+        manifest.claim.ai_generated = false;
+        assert!(!verify(&manifest));
+
+        let mut manifest = signed();
+        manifest.signature = "1".repeat(64);
+        assert!(!verify(&manifest));
+    }
+
+    #[test]
+    fn manifest_signed_by_another_key_is_rejected() {
+        let other = Keypair::new();
+        let mut manifest = signed();
+        manifest.signer = other.pubkey().to_string();
+        manifest.signature = other
+            .sign_message(&serde_json::to_vec(&manifest.claim).unwrap())
+            .to_string();
+        assert!(!verify(&manifest));
+    }
+
+    #[test]
+    fn missing_key_file_is_created_and_reloaded() {
+        let dir = std::env::temp_dir().join(format!("manifest-key-{}", uuid::Uuid::new_v4()));
+        let path = dir.join("keypair.json");
+        let config = Config {
+            manifest_keypair: path.to_string_lossy().to_string(),
+            ..Default::default()
+        };
+        init_signing_key(&config).unwrap();
+        let created = read_keypair_file(&path).unwrap();
+        init_signing_key(&config).unwrap();
+        assert_eq!(read_keypair_file(&path).unwrap().pubkey(), created.pubkey());
+
+        std::fs::write(&path, "not a keypair").unwrap();
+        assert!(init_signing_key(&config).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,245 @@
+use image::{RgbImage, imageops::FilterType};
+use sha2::{Digest, Sha256};
+
+/// Name recorded in manifests for this scheme.
+pub const WATERMARK_SCHEME: &str = "chroniq-qim-v1";
+
+/// Side of the pixel blocks carrying one bit each.
+const BLOCK: u32 = 8;
+/// Quantization step of a block's mean brightness. Larger survives more
+/// re-encoding but shows in flat areas.
+const STEP: f32 = 4.0;
+/// 16 bytes of task id plus a 2 byte check.
+const PAYLOAD_BITS: usize = 18 * 8;
+/// What the default workflow generates. A resized copy is scaled back to it
+/// to line the blocks up again.
+const GENERATED_SIZES: [(u32, u32); 1] = [(1024, 1024)];
+
+/// A watermark read back from an image.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct DetectedWatermark {
+    pub task_id: String,
+    /// Share of blocks agreeing with the decoded bits, 0.5 is noise.
+    pub confidence: f32,
+}
+
+fn payload(task_id: &uuid::Uuid) -> Vec<bool> {
+    let mut bytes = task_id.as_bytes().to_vec();
+    bytes.extend_from_slice(&check(task_id.as_bytes()));
+    bytes
+        .iter()
+        .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1 == 1))
+        .collect()
+}
+
+fn check(id: &[u8]) -> [u8; 2] {
+    let digest = Sha256::digest([b"chroniq".as_slice(), id].concat());
+    [digest[0], digest[1]]
+}
+
+fn blocks(image: &RgbImage) -> impl Iterator<Item = (usize, u32, u32)> + use<> {
+    let columns = image.width() / BLOCK;
+    let rows = image.height() / BLOCK;
+    (0..rows * columns).map(move |i| (i as usize, (i % columns) * BLOCK, (i / columns) * BLOCK))
+}
+
+fn block_mean(image: &RgbImage, x: u32, y: u32) -> f32 {
+    let mut sum = 0u32;
+    for dy in 0..BLOCK {
+        for dx in 0..BLOCK {
+            let [r, g, b] = image.get_pixel(x + dx, y + dy).0;
+            sum += r as u32 + g as u32 + b as u32;
+        }
+    }
+    sum as f32 / (BLOCK * BLOCK * 3) as f32
+}
+
+/// Hides a task id in the mean brightness of 8x8 blocks (quantization index
+/// modulation), repeating the payload over the whole image. It survives
+/// lossless and mild lossy re-encoding and resizing of a generated image, not
+/// cropping.
+pub fn embed(image: &mut RgbImage, task_id: &str) -> anyhow::Result<()> {
+    let task_id = uuid::Uuid::parse_str(task_id)?;
+    let bits = payload(&task_id);
+    let block_count = (image.width() / BLOCK) * (image.height() / BLOCK);
+    if (block_count as usize) < PAYLOAD_BITS {
+        anyhow::bail!("image is too small for a watermark");
+    }
+    for (i, x, y) in blocks(image) {
+        let bit = bits[i % PAYLOAD_BITS];
+        let mean = block_mean(image, x, y);
+        let offset = if bit { STEP / 2.0 } else { 0.0 };
+        let target = ((mean - offset) / STEP).round() * STEP + offset;
+        let delta = (target - mean).round() as i16;
+        if delta == 0 {
+            continue;
+        }
+        for dy in 0..BLOCK {
+            for dx in 0..BLOCK {
+                let pixel = image.get_pixel_mut(x + dx, y + dy);
+                for c in &mut pixel.0 {
+                    *c = (*c as i16 + delta).clamp(0, 255) as u8;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reads the task id back, trying the image as it is and then scaled to each
+/// generated size. `None` when the image carries no watermark of ours.
+pub fn detect(image: &RgbImage) -> Option<DetectedWatermark> {
+    read(image).or_else(|| {
+        GENERATED_SIZES
+            .iter()
+            .filter(|&&size| size != image.dimensions())
+            .find_map(|&(width, height)| {
+                read(&image::imageops::resize(
+                    image,
+                    width,
+                    height,
+                    FilterType::Triangle,
+                ))
+            })
+    })
+}
+
+/// Majority vote over all repetitions of the payload.
+fn read(image: &RgbImage) -> Option<DetectedWatermark> {
+    let mut votes = vec![(0u32, 0u32); PAYLOAD_BITS];
+    for (i, x, y) in blocks(image) {
+        let phase = block_mean(image, x, y).rem_euclid(STEP);
+        // closer to STEP / 2 than to a multiple of STEP means 1
+        let one = (phase - STEP / 2.0).abs() < STEP / 4.0;
+        let vote = &mut votes[i % PAYLOAD_BITS];
+        if one {
+            vote.1 += 1;
+        } else {
+            vote.0 += 1;
+        }
+    }
+    if votes.iter().any(|(zeros, ones)| zeros + ones == 0) {
+        return None;
+    }
+    let bits = votes
+        .iter()
+        .map(|(zeros, ones)| ones > zeros)
+        .collect::<Vec<_>>();
+    let agreeing = votes
+        .iter()
+        .map(|(zeros, ones)| zeros.max(ones))
+        .sum::<u32>();
+    let total = votes.iter().map(|(zeros, ones)| zeros + ones).sum::<u32>();
+    let bytes = bits
+        .chunks(8)
+        .map(|byte| byte.iter().fold(0u8, |acc, bit| (acc << 1) | *bit as u8))
+        .collect::<Vec<_>>();
+    let (id, expected) = bytes.split_at(16);
+    if check(id) != expected {
+        return None;
+    }
+    Some(DetectedWatermark {
+        task_id: uuid::Uuid::from_slice(id).ok()?.to_string(),
+        confidence: agreeing as f32 / total as f32,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use image::{ImageFormat, codecs::jpeg::JpegEncoder};
+
+    use super::*;
+
+    const TASK_ID: &str = "5f0c6a1e-8a43-4c3b-9d2e-0b7c1f6d2a94";
+
+    /// Something like a photo: smooth gradients with some texture.
+    fn picture() -> RgbImage {
+        RgbImage::from_fn(1024, 1024, |x, y| {
+            let texture = ((x * 31 + y * 17) ^ (x * y)) % 24;
+            image::Rgb([
+                (40 + x / 8 + texture) as u8,
+                (60 + y / 8 + texture) as u8,
+                (90 + (x + y) / 16 + texture) as u8,
+            ])
+        })
+    }
+
+    fn watermarked() -> RgbImage {
+        let mut image = picture();
+        embed(&mut image, TASK_ID).unwrap();
+        image
+    }
+
+    fn detected(image: &RgbImage) -> Option<String> {
+        detect(image).map(|watermark| watermark.task_id)
+    }
+
+    fn jpeg(image: &RgbImage, quality: u8) -> RgbImage {
+        let mut data = Vec::new();
+        JpegEncoder::new_with_quality(&mut data, quality)
+            .encode_image(image)
+            .unwrap();
+        image::load_from_memory(&data).unwrap().to_rgb8()
+    }
+
+    #[test]
+    fn embedded_task_id_is_detected() {
+        let watermark = detect(&watermarked()).unwrap();
+        assert_eq!(watermark.task_id, TASK_ID);
+        assert!(watermark.confidence > 0.95);
+    }
+
+    #[test]
+    fn unmarked_image_has_no_watermark() {
+        assert_eq!(detected(&picture()), None);
+    }
+
+    #[test]
+    fn watermark_survives_png_and_mild_jpeg() {
+        let image = watermarked();
+        let mut png = Vec::new();
+        image
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        let png = image::load_from_memory(&png).unwrap().to_rgb8();
+        assert_eq!(detected(&png).as_deref(), Some(TASK_ID));
+        assert_eq!(detected(&jpeg(&image, 90)).as_deref(), Some(TASK_ID));
+    }
+
+    #[test]
+    fn watermark_survives_resizing() {
+        let image = watermarked();
+        for (width, height) in [(512, 512), (768, 768), (1280, 1280), (1000, 1024)] {
+            let resized = image::imageops::resize(&image, width, height, FilterType::Triangle);
+            assert_eq!(
+                detected(&resized).as_deref(),
+                Some(TASK_ID),
+                "{}x{}",
+                width,
+                height
+            );
+            assert_eq!(
+                detected(&jpeg(&resized, 85)).as_deref(),
+                Some(TASK_ID),
+                "{}x{} as JPEG",
+                width,
+                height
+            );
+        }
+    }
+
+    #[test]
+    fn cropped_image_is_not_attributed() {
+        let image = watermarked();
+        let cropped = image::imageops::crop_imm(&image, 3, 5, 900, 900).to_image();
+        assert_eq!(detected(&cropped), None);
+    }
+
+    #[test]
+    fn small_image_is_refused() {
+        let mut image = RgbImage::new(64, 64);
+        assert!(embed(&mut image, TASK_ID).is_err());
+    }
+}
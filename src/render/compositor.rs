@@ -0,0 +1,157 @@
+use ab_glyph::{FontArc, PxScale};
+use image::{DynamicImage, Rgba, RgbaImage, imageops::FilterType};
+use imageproc::{
+    drawing::{draw_filled_rect_mut, draw_text_mut, text_size},
+    rect::Rect,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    filter::Filter,
+    template::{Frame, Template, parse_color},
+};
+
+/// The memory values captions can refer to.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct CardText {
+    pub title: String,
+    pub content: String,
+    pub date: String,
+}
+
+/// Renders `images` into the slots of `template`, cycling through them when
+/// there are more slots than images. `filter` overrides the template's.
+pub fn render(
+    template: &Template,
+    images: &[DynamicImage],
+    text: &CardText,
+    filter: Option<Filter>,
+    font: Option<&FontArc>,
+) -> anyhow::Result<RgbaImage> {
+    if images.is_empty() {
+        anyhow::bail!("nothing to render");
+    }
+    template.validate()?;
+    let mut canvas = RgbaImage::from_pixel(
+        template.width,
+        template.height,
+        parse_color(&template.background)?,
+    );
+    let filter = filter.unwrap_or(template.filter);
+    for (slot, image) in template.all_slots().iter().zip(images.iter().cycle()) {
+        if slot.width == 0 || slot.height == 0 {
+            continue;
+        }
+        let mut photo = image
+            .resize_to_fill(slot.width, slot.height, FilterType::Triangle)
+            .to_rgba8();
+        filter.apply(&mut photo);
+        image::imageops::overlay(&mut canvas, &photo, slot.x as i64, slot.y as i64);
+        if let Some(frame) = &slot.frame {
+            draw_frame(&mut canvas, slot.x, slot.y, slot.width, slot.height, frame)?;
+        }
+    }
+    if let Some(frame) = &template.frame {
+        draw_frame(&mut canvas, 0, 0, template.width, template.height, frame)?;
+    }
+
+    for caption in &template.captions {
+        let value = caption
+            .text
+            .replace("{title}", &text.title)
+            .replace("{content}", &text.content)
+            .replace("{date}", &text.date);
+        if value.trim().is_empty() {
+            continue;
+        }
+        let font = font.ok_or_else(|| anyhow::anyhow!("captions need a font, check FONT_PATH"))?;
+        let scale = PxScale::from(caption.size);
+        let color = parse_color(&caption.color)?;
+        let line_height = (caption.size * 1.25).round() as i32;
+        let lines = wrap_text(font, scale, &value, caption.max_width, caption.max_lines);
+        for (i, line) in lines.iter().enumerate() {
+            let y = caption.y as i32 + i as i32 * line_height;
+            draw_text_mut(&mut canvas, color, caption.x as i32, y, scale, font, line);
+        }
+    }
+    Ok(canvas)
+}
+
+/// Draws a border of `frame.width` pixels inside the given rectangle.
+fn draw_frame(
+    canvas: &mut RgbaImage,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    frame: &Frame,
+) -> anyhow::Result<()> {
+    let border = frame.width.min(width / 2).min(height / 2);
+    if border == 0 {
+        return Ok(());
+    }
+    let color: Rgba<u8> = parse_color(&frame.color)?;
+    let (x, y) = (x as i32, y as i32);
+    let edges = [
+        Rect::at(x, y).of_size(width, border),
+        Rect::at(x, y + (height - border) as i32).of_size(width, border),
+        Rect::at(x, y).of_size(border, height),
+        Rect::at(x + (width - border) as i32, y).of_size(border, height),
+    ];
+    for edge in edges {
+        draw_filled_rect_mut(canvas, edge, color);
+    }
+    Ok(())
+}
+
+/// Greedy line breaking that prefers spaces and falls back to breaking
+/// anywhere, so text without spaces wraps too. The last line ends in an
+/// ellipsis when text is cut.
+pub(crate) fn wrap_text(
+    font: &FontArc,
+    scale: PxScale,
+    text: &str,
+    max_width: u32,
+    max_lines: usize,
+) -> Vec<String> {
+    let fits = |line: &str| text_size(scale, font, line).0 <= max_width;
+    let mut lines: Vec<String> = Vec::new();
+    let mut rest = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    while !rest.is_empty() {
+        let mut end = 0;
+        let mut last_space = None;
+        for (i, c) in rest.char_indices() {
+            let next = i + c.len_utf8();
+            if !fits(&rest[..next]) {
+                break;
+            }
+            if c == ' ' {
+                last_space = Some(i);
+            }
+            end = next;
+        }
+        let line = if end == rest.len() {
+            std::mem::take(&mut rest)
+        } else {
+            // always make progress, even when a single glyph is too wide
+            let first = rest.chars().next().map_or(1, char::len_utf8);
+            let cut = last_space.unwrap_or(end.max(first));
+            let line = rest[..cut].trim_end().to_string();
+            rest = rest[cut..].trim_start().to_string();
+            line
+        };
+        lines.push(line);
+        if lines.len() == max_lines.max(1) {
+            break;
+        }
+    }
+    if !rest.is_empty()
+        && let Some(last) = lines.last_mut()
+    {
+        while !last.is_empty() && !fits(&format!("{}…", last)) {
+            last.pop();
+        }
+        last.push('…');
+    }
+    lines
+}
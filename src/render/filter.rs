@@ -0,0 +1,78 @@
+use image::RgbaImage;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Filter {
+    #[default]
+    None,
+    Sepia,
+    #[serde(alias = "bw")]
+    Grayscale,
+    /// Warm sepia tint, faded contrast and a vignette.
+    Vintage,
+}
+
+impl Filter {
+    pub fn apply(self, image: &mut RgbaImage) {
+        match self {
+            Filter::None => {}
+            Filter::Sepia => map_pixels(image, |r, g, b| sepia(r, g, b, 1.0)),
+            Filter::Grayscale => map_pixels(image, |r, g, b| {
+                let l = luma(r, g, b);
+                (l, l, l)
+            }),
+            Filter::Vintage => {
+                map_pixels(image, |r, g, b| {
+                    let (r, g, b) = sepia(r, g, b, 0.6);
+                    // lift the blacks and pull the whites down
+                    let fade = |c: f32| 24.0 + c * 0.82;
+                    (fade(r), fade(g), fade(b) * 0.94)
+                });
+                vignette(image, 0.45);
+            }
+        }
+    }
+}
+
+fn luma(r: f32, g: f32, b: f32) -> f32 {
+    0.299 * r + 0.587 * g + 0.114 * b
+}
+
+/// Blends the classic sepia matrix with the original by `amount`.
+fn sepia(r: f32, g: f32, b: f32, amount: f32) -> (f32, f32, f32) {
+    let sr = 0.393 * r + 0.769 * g + 0.189 * b;
+    let sg = 0.349 * r + 0.686 * g + 0.168 * b;
+    let sb = 0.272 * r + 0.534 * g + 0.131 * b;
+    (
+        r + (sr - r) * amount,
+        g + (sg - g) * amount,
+        b + (sb - b) * amount,
+    )
+}
+
+fn map_pixels(image: &mut RgbaImage, f: impl Fn(f32, f32, f32) -> (f32, f32, f32)) {
+    for pixel in image.pixels_mut() {
+        let [r, g, b, a] = pixel.0;
+        let (r, g, b) = f(r as f32, g as f32, b as f32);
+        pixel.0 = [clamp(r), clamp(g), clamp(b), a];
+    }
+}
+
+/// Darkens the corners by up to `strength`.
+fn vignette(image: &mut RgbaImage, strength: f32) {
+    let (width, height) = image.dimensions();
+    let (cx, cy) = (width as f32 / 2.0, height as f32 / 2.0);
+    let max = (cx * cx + cy * cy).sqrt();
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        let (dx, dy) = (x as f32 - cx, y as f32 - cy);
+        let factor = 1.0 - strength * ((dx * dx + dy * dy).sqrt() / max).powi(2);
+        for c in &mut pixel.0[..3] {
+            *c = clamp(*c as f32 * factor);
+        }
+    }
+}
+
+fn clamp(value: f32) -> u8 {
+    value.round().clamp(0.0, 255.0) as u8
+}
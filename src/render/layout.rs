@@ -0,0 +1,269 @@
+use ab_glyph::{FontArc, PxScale};
+use image::{DynamicImage, Rgba, RgbaImage, imageops::FilterType};
+use imageproc::{
+    drawing::{draw_filled_circle_mut, draw_filled_rect_mut, draw_text_mut},
+    rect::Rect,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{compositor::wrap_text, template::parse_color};
+
+/// Largest poster side, in pixels.
+pub const MAX_POSTER_SIDE: u32 = 8192;
+/// Widest gap between images, in pixels.
+pub const MAX_GAP: u32 = 256;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Layout {
+    /// Square cells, cropped to fill.
+    #[default]
+    Grid,
+    /// Columns of uncropped images, each placed under the shortest column.
+    Masonry,
+    /// One row of uncropped images in time order, joined by a time line.
+    Timeline,
+}
+
+/// One image of a collage with its optional caption.
+pub struct Tile {
+    pub image: DynamicImage,
+    pub caption: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LayoutOptions {
+    /// Poster width for grid and masonry, row height for the timeline.
+    pub size: u32,
+    pub columns: Option<u32>,
+    pub gap: u32,
+    pub background: String,
+    pub color: String,
+    pub title: Option<String>,
+}
+
+struct Placement {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+const CAPTION_SIZE: f32 = 26.0;
+const TITLE_SIZE: f32 = 56.0;
+
+fn too_large() -> anyhow::Error {
+    anyhow::anyhow!("poster exceeds {} pixels", MAX_POSTER_SIDE)
+}
+
+/// `a + b + ...`, failing instead of overflowing.
+fn sum(values: &[u32]) -> anyhow::Result<u32> {
+    values
+        .iter()
+        .try_fold(0u32, |total, value| total.checked_add(*value))
+        .ok_or_else(too_large)
+}
+
+pub fn compose(
+    layout: Layout,
+    tiles: &[Tile],
+    options: &LayoutOptions,
+    font: Option<&FontArc>,
+) -> anyhow::Result<RgbaImage> {
+    if tiles.is_empty() {
+        anyhow::bail!("nothing to lay out");
+    }
+    let background = parse_color(&options.background)?;
+    let color = parse_color(&options.color)?;
+    let gap = options.gap;
+    // captions and the title only take room when they can be drawn
+    let caption_band = if font.is_some() && tiles.iter().any(|t| t.caption.is_some()) {
+        (CAPTION_SIZE * 1.6) as u32
+    } else {
+        0
+    };
+    let title_band = match (&options.title, font) {
+        (Some(title), Some(_)) if !title.trim().is_empty() => (TITLE_SIZE * 1.8) as u32,
+        _ => 0,
+    };
+    let top = sum(&[gap, title_band])?;
+
+    let (width, height, placements) = match layout {
+        Layout::Grid => {
+            let columns = options
+                .columns
+                .unwrap_or_else(|| (tiles.len() as f64).sqrt().ceil() as u32)
+                .clamp(1, tiles.len() as u32);
+            let rows = (tiles.len() as u32).div_ceil(columns);
+            let gaps = gap.checked_mul(columns + 1).ok_or_else(too_large)?;
+            let cell = options.size.saturating_sub(gaps) / columns;
+            if cell == 0 {
+                anyhow::bail!("poster is too narrow for {} columns", columns);
+            }
+            let row = sum(&[cell, caption_band, gap])?;
+            let height = rows
+                .checked_mul(row)
+                .and_then(|rows| rows.checked_add(top))
+                .ok_or_else(too_large)?;
+            let placements = (0..tiles.len() as u32)
+                .map(|i| Placement {
+                    x: gap + (i % columns) * (cell + gap),
+                    y: top + (i / columns) * row,
+                    width: cell,
+                    height: cell,
+                })
+                .collect::<Vec<_>>();
+            (options.size, height, placements)
+        }
+        Layout::Masonry => {
+            let columns = options.columns.unwrap_or(3).clamp(1, tiles.len() as u32);
+            let gaps = gap.checked_mul(columns + 1).ok_or_else(too_large)?;
+            let column_width = options.size.saturating_sub(gaps) / columns;
+            if column_width == 0 {
+                anyhow::bail!("poster is too narrow for {} columns", columns);
+            }
+            let mut heights = vec![top; columns as usize];
+            let mut placements = Vec::with_capacity(tiles.len());
+            for tile in tiles {
+                let height = scaled_height(&tile.image, column_width)?;
+                let (column, y) = heights
+                    .iter()
+                    .copied()
+                    .enumerate()
+                    .min_by_key(|(_, h)| *h)
+                    .unwrap_or((0, top));
+                placements.push(Placement {
+                    x: gap + column as u32 * (column_width + gap),
+                    y,
+                    width: column_width,
+                    height,
+                });
+                heights[column] = sum(&[y, height, caption_band, gap])?;
+            }
+            let height = heights.into_iter().max().unwrap_or(top);
+            (options.size, height, placements)
+        }
+        Layout::Timeline => {
+            let row_height = options.size;
+            let mut x = gap;
+            let mut placements = Vec::with_capacity(tiles.len());
+            for tile in tiles {
+                let width = scaled_width(&tile.image, row_height)?;
+                placements.push(Placement {
+                    x,
+                    y: top,
+                    width,
+                    height: row_height,
+                });
+                x = sum(&[x, width, gap])?;
+            }
+            // room for the time line below the images
+            let line_band = 40;
+            (
+                x,
+                sum(&[top, row_height, line_band, caption_band, gap])?,
+                placements,
+            )
+        }
+    };
+    if width > MAX_POSTER_SIDE || height > MAX_POSTER_SIDE || width == 0 || height == 0 {
+        anyhow::bail!(
+            "poster of {}x{} exceeds {} pixels",
+            width,
+            height,
+            MAX_POSTER_SIDE
+        );
+    }
+
+    let mut canvas = RgbaImage::from_pixel(width, height, background);
+    if let (Some(title), Some(font)) = (&options.title, font)
+        && title_band > 0
+    {
+        let scale = PxScale::from(TITLE_SIZE);
+        for line in wrap_text(
+            font,
+            scale,
+            title,
+            width.saturating_sub(gap.saturating_mul(2)),
+            1,
+        ) {
+            draw_text_mut(
+                &mut canvas,
+                color,
+                gap as i32,
+                gap as i32,
+                scale,
+                font,
+                &line,
+            );
+        }
+    }
+    if layout == Layout::Timeline {
+        let y = (top + options.size + 20) as i32;
+        draw_filled_rect_mut(
+            &mut canvas,
+            Rect::at(gap as i32, y - 1)
+                .of_size(width.saturating_sub(gap.saturating_mul(2)).max(1), 3),
+            color,
+        );
+    }
+    for (tile, placement) in tiles.iter().zip(&placements) {
+        let photo = fill(&tile.image, placement.width, placement.height);
+        image::imageops::overlay(&mut canvas, &photo, placement.x as i64, placement.y as i64);
+        let mut caption_y = placement.y + placement.height;
+        if layout == Layout::Timeline {
+            let center = (placement.x + placement.width / 2) as i32;
+            draw_filled_circle_mut(&mut canvas, (center, caption_y as i32 + 20), 8, color);
+            caption_y += 40;
+        }
+        if let (Some(caption), Some(font)) = (&tile.caption, font) {
+            draw_caption(&mut canvas, font, caption, placement, caption_y, color);
+        }
+    }
+    Ok(canvas)
+}
+
+fn draw_caption(
+    canvas: &mut RgbaImage,
+    font: &FontArc,
+    caption: &str,
+    placement: &Placement,
+    y: u32,
+    color: Rgba<u8>,
+) {
+    let scale = PxScale::from(CAPTION_SIZE);
+    for line in wrap_text(font, scale, caption, placement.width, 1) {
+        let y = y as i32 + (CAPTION_SIZE * 0.3) as i32;
+        draw_text_mut(canvas, color, placement.x as i32, y, scale, font, &line);
+    }
+}
+
+/// Crops to the aspect ratio of the placement before scaling, so an extreme
+/// aspect ratio never scales up to a huge intermediate image.
+fn fill(image: &DynamicImage, width: u32, height: u32) -> RgbaImage {
+    let (w, h) = (image.width().max(1) as u64, image.height().max(1) as u64);
+    let (crop_w, crop_h) = if w * height as u64 > h * width as u64 {
+        ((h * width as u64 / height as u64).max(1), h)
+    } else {
+        (w, (w * height as u64 / width as u64).max(1))
+    };
+    image
+        .crop_imm(
+            ((w - crop_w) / 2) as u32,
+            ((h - crop_h) / 2) as u32,
+            crop_w as u32,
+            crop_h as u32,
+        )
+        .resize_exact(width, height, FilterType::Triangle)
+        .to_rgba8()
+}
+
+fn scaled_height(image: &DynamicImage, width: u32) -> anyhow::Result<u32> {
+    let (w, h) = (image.width().max(1), image.height());
+    u32::try_from(((h as u64 * width as u64) / w as u64).max(1)).map_err(|_| too_large())
+}
+
+fn scaled_width(image: &DynamicImage, height: u32) -> anyhow::Result<u32> {
+    let (w, h) = (image.width(), image.height().max(1));
+    u32::try_from(((w as u64 * height as u64) / h as u64).max(1)).map_err(|_| too_large())
+}
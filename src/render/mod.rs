@@ -0,0 +1,6 @@
+pub mod compositor;
+pub mod filter;
+pub mod layout;
+pub mod png_metadata;
+pub mod template;
+pub mod variant;
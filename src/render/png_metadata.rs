@@ -0,0 +1,169 @@
+use anyhow::anyhow;
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Prefix of the keywords we write.
+pub const KEYWORD_PREFIX: &str = "Chroniq:";
+
+/// Keywords ComfyUI uses to embed the prompt graph and the editor workflow.
+const COMFYUI_KEYWORDS: [&str; 2] = ["prompt", "workflow"];
+
+/// A text chunk of a PNG.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct TextChunk {
+    pub keyword: String,
+    pub text: String,
+}
+
+struct Chunk<'a> {
+    kind: [u8; 4],
+    data: &'a [u8],
+}
+
+fn chunks(png: &[u8]) -> anyhow::Result<Vec<Chunk<'_>>> {
+    if !png.starts_with(PNG_SIGNATURE) {
+        return Err(anyhow!("not a PNG"));
+    }
+    let mut chunks = Vec::new();
+    let mut pos = PNG_SIGNATURE.len();
+    while pos < png.len() {
+        if pos + 12 > png.len() {
+            return Err(anyhow!("truncated chunk at {}", pos));
+        }
+        let len = u32::from_be_bytes(png[pos..pos + 4].try_into()?) as usize;
+        let end = pos + 12 + len;
+        if end > png.len() {
+            return Err(anyhow!("truncated chunk at {}", pos));
+        }
+        let kind: [u8; 4] = png[pos + 4..pos + 8].try_into()?;
+        chunks.push(Chunk {
+            kind,
+            data: &png[pos + 8..pos + 8 + len],
+        });
+        pos = end;
+        if &kind == b"IEND" {
+            break;
+        }
+    }
+    Ok(chunks)
+}
+
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(kind);
+    hasher.update(data);
+    out.extend_from_slice(&hasher.finalize().to_be_bytes());
+}
+
+/// Decodes tEXt and uncompressed iTXt chunks; compressed text is skipped.
+fn decode_text(chunk: &Chunk) -> Option<TextChunk> {
+    let split = chunk.data.iter().position(|b| *b == 0)?;
+    let keyword = latin1(&chunk.data[..split]);
+    let rest = &chunk.data[split + 1..];
+    match &chunk.kind {
+        b"tEXt" => Some(TextChunk {
+            keyword,
+            text: latin1(rest),
+        }),
+        b"iTXt" => {
+            // compression flag, compression method, language tag\0, translated keyword\0
+            let (&compressed, rest) = rest.split_first()?;
+            if compressed != 0 {
+                return None;
+            }
+            let rest = rest.get(1..)?;
+            let language_end = rest.iter().position(|b| *b == 0)?;
+            let rest = &rest[language_end + 1..];
+            let translated_end = rest.iter().position(|b| *b == 0)?;
+            Some(TextChunk {
+                keyword,
+                text: String::from_utf8_lossy(&rest[translated_end + 1..]).into_owned(),
+            })
+        }
+        _ => None,
+    }
+}
+
+fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| *b as char).collect()
+}
+
+/// Returns the readable text chunks of a PNG in file order.
+pub fn read_text_chunks(png: &[u8]) -> anyhow::Result<Vec<TextChunk>> {
+    Ok(chunks(png)?.iter().filter_map(decode_text).collect())
+}
+
+/// Rewrites a PNG without the text chunks `remove` matches, adding `add` as
+/// uncompressed iTXt chunks before the image data. Pixel data is copied as is.
+pub fn rewrite_text_chunks(
+    png: &[u8],
+    remove: impl Fn(&str) -> bool,
+    add: &[TextChunk],
+) -> anyhow::Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(png.len());
+    out.extend_from_slice(PNG_SIGNATURE);
+    let mut added = false;
+    for chunk in chunks(png)? {
+        if matches!(&chunk.kind, b"tEXt" | b"iTXt" | b"zTXt") {
+            let keyword_end = chunk
+                .data
+                .iter()
+                .position(|b| *b == 0)
+                .unwrap_or(chunk.data.len());
+            if remove(&latin1(&chunk.data[..keyword_end])) {
+                continue;
+            }
+        }
+        if !added && matches!(&chunk.kind, b"IDAT" | b"IEND") {
+            for text in add {
+                write_chunk(&mut out, b"iTXt", &encode_itxt(text)?);
+            }
+            added = true;
+        }
+        write_chunk(&mut out, &chunk.kind, chunk.data);
+    }
+    Ok(out)
+}
+
+fn encode_itxt(text: &TextChunk) -> anyhow::Result<Vec<u8>> {
+    let keyword = text.keyword.as_bytes();
+    if keyword.is_empty() || keyword.len() > 79 || !text.keyword.is_ascii() {
+        return Err(anyhow!("invalid PNG keyword: {}", text.keyword));
+    }
+    let mut data = Vec::with_capacity(keyword.len() + text.text.len() + 5);
+    data.extend_from_slice(keyword);
+    // no compression, empty language tag and translated keyword
+    data.extend_from_slice(&[0, 0, 0, 0, 0]);
+    data.extend_from_slice(text.text.as_bytes());
+    Ok(data)
+}
+
+pub fn is_comfyui_keyword(keyword: &str) -> bool {
+    COMFYUI_KEYWORDS.contains(&keyword)
+}
+
+/// Replaces our metadata in a generated image and, in privacy mode, drops the
+/// workflow ComfyUI embeds.
+pub fn annotate(
+    png: &[u8],
+    metadata: &[(&str, String)],
+    strip_workflow: bool,
+) -> anyhow::Result<Vec<u8>> {
+    let add = metadata
+        .iter()
+        .map(|(key, value)| TextChunk {
+            keyword: format!("{}{}", KEYWORD_PREFIX, key),
+            text: value.clone(),
+        })
+        .collect::<Vec<_>>();
+    rewrite_text_chunks(
+        png,
+        |keyword| {
+            keyword.starts_with(KEYWORD_PREFIX) || (strip_workflow && is_comfyui_keyword(keyword))
+        },
+        &add,
+    )
+}
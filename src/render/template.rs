@@ -0,0 +1,176 @@
+use std::collections::BTreeMap;
+
+use image::Rgba;
+use serde::{Deserialize, Serialize};
+
+use crate::conf::config::Config;
+
+use super::filter::Filter;
+
+/// Templates shipped with the server. Files in `TEMPLATE_PATH` with the same
+/// name take precedence.
+const BUILTIN_TEMPLATES: [&str; 4] = [
+    include_str!("../../templates/polaroid.json"),
+    include_str!("../../templates/vintage.json"),
+    include_str!("../../templates/grid.json"),
+    include_str!("../../templates/noir.json"),
+];
+
+/// A memory card layout, declared in JSON.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Template {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    #[serde(default = "default_background")]
+    pub background: String,
+    #[serde(default)]
+    pub filter: Filter,
+    /// Border drawn around the whole card.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frame: Option<Frame>,
+    #[serde(default)]
+    pub slots: Vec<Slot>,
+    /// Adds evenly sized slots after `slots`, for collages.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grid: Option<Grid>,
+    #[serde(default)]
+    pub captions: Vec<Caption>,
+}
+
+/// A rectangle an image is scaled into, cropping what does not fit.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Slot {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frame: Option<Frame>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Frame {
+    pub width: u32,
+    pub color: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Grid {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub rows: u32,
+    pub columns: u32,
+    #[serde(default)]
+    pub gap: u32,
+}
+
+/// Text drawn onto the card. `{title}`, `{content}` and `{date}` are replaced
+/// by the memory's values.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Caption {
+    pub text: String,
+    pub x: u32,
+    pub y: u32,
+    pub size: f32,
+    pub color: String,
+    pub max_width: u32,
+    #[serde(default = "default_max_lines")]
+    pub max_lines: usize,
+}
+
+fn default_background() -> String {
+    "#ffffff".to_string()
+}
+
+fn default_max_lines() -> usize {
+    1
+}
+
+impl Template {
+    /// The explicit slots followed by the grid cells, in reading order.
+    pub fn all_slots(&self) -> Vec<Slot> {
+        let mut slots = self.slots.clone();
+        if let Some(grid) = &self.grid {
+            let columns = grid.columns.max(1);
+            let rows = grid.rows.max(1);
+            let cell_width = grid.width.saturating_sub(grid.gap * (columns - 1)) / columns;
+            let cell_height = grid.height.saturating_sub(grid.gap * (rows - 1)) / rows;
+            for row in 0..rows {
+                for column in 0..columns {
+                    slots.push(Slot {
+                        x: grid.x + column * (cell_width + grid.gap),
+                        y: grid.y + row * (cell_height + grid.gap),
+                        width: cell_width,
+                        height: cell_height,
+                        frame: None,
+                    });
+                }
+            }
+        }
+        slots
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.width == 0 || self.height == 0 || self.width > 8192 || self.height > 8192 {
+            anyhow::bail!("template {} must be between 1 and 8192 pixels", self.name);
+        }
+        parse_color(&self.background)?;
+        for caption in &self.captions {
+            parse_color(&caption.color)?;
+        }
+        for frame in self
+            .frame
+            .iter()
+            .chain(self.slots.iter().filter_map(|s| s.frame.as_ref()))
+        {
+            parse_color(&frame.color)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses `#rrggbb` or `#rrggbbaa`.
+pub fn parse_color(color: &str) -> anyhow::Result<Rgba<u8>> {
+    let hex = color.trim().trim_start_matches('#');
+    let bytes = hex::decode(hex).map_err(|_| anyhow::anyhow!("invalid color: {}", color))?;
+    match bytes.as_slice() {
+        [r, g, b] => Ok(Rgba([*r, *g, *b, 255])),
+        [r, g, b, a] => Ok(Rgba([*r, *g, *b, *a])),
+        _ => Err(anyhow::anyhow!("invalid color: {}", color)),
+    }
+}
+
+/// The built-in templates merged with the `*.json` files in `TEMPLATE_PATH`.
+/// Invalid files are skipped with an error log.
+pub async fn load_templates(config: &Config) -> BTreeMap<String, Template> {
+    let mut templates = BTreeMap::new();
+    for source in BUILTIN_TEMPLATES {
+        if let Ok(template) = serde_json::from_str::<Template>(source) {
+            templates.insert(template.name.clone(), template);
+        }
+    }
+    let Ok(mut dir) = tokio::fs::read_dir(&config.template_path).await else {
+        return templates;
+    };
+    while let Ok(Some(entry)) = dir.next_entry().await {
+        let path = entry.path();
+        if path.extension().is_none_or(|ext| ext != "json") {
+            continue;
+        }
+        let loaded = tokio::fs::read_to_string(&path)
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|source| Ok(serde_json::from_str::<Template>(&source)?))
+            .and_then(|template| template.validate().map(|_| template));
+        match loaded {
+            Ok(template) => {
+                templates.insert(template.name.clone(), template);
+            }
+            Err(e) => log::error!("load template {} failed: {}", path.display(), e),
+        }
+    }
+    templates
+}
@@ -0,0 +1,223 @@
+use std::path::{Path, PathBuf};
+
+use image::{DynamicImage, ImageFormat, codecs::avif::AvifEncoder, codecs::jpeg::JpegEncoder};
+use log::error;
+use serde::{Deserialize, Serialize};
+
+use crate::{conf::config::Config, storage::storage::local_output};
+
+/// Sub directory of an original's directory holding its variants.
+pub const VARIANT_DIR: &str = "variants";
+
+/// Widths a variant may have; requests are rounded up to the next one so an
+/// image has a bounded number of variants.
+const WIDTHS: [u32; 8] = [64, 128, 256, 512, 768, 1024, 2048, 4096];
+/// Qualities a variant may have; requests are rounded to the nearest one.
+const QUALITIES: [u8; 5] = [40, 60, 80, 90, 100];
+pub const DEFAULT_QUALITY: u8 = 80;
+
+/// Variants made as soon as a task image arrives, sized for album grids.
+pub const PREGENERATED_VARIANTS: [VariantSpec; 2] = [
+    VariantSpec {
+        width: Some(256),
+        format: VariantFormat::Webp,
+        quality: DEFAULT_QUALITY,
+    },
+    VariantSpec {
+        width: Some(512),
+        format: VariantFormat::Webp,
+        quality: DEFAULT_QUALITY,
+    },
+];
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum VariantFormat {
+    Png,
+    Jpeg,
+    Webp,
+    Avif,
+}
+
+impl VariantFormat {
+    pub fn parse(format: &str) -> Option<Self> {
+        match format.to_ascii_lowercase().as_str() {
+            "png" => Some(VariantFormat::Png),
+            "jpg" | "jpeg" => Some(VariantFormat::Jpeg),
+            "webp" => Some(VariantFormat::Webp),
+            "avif" => Some(VariantFormat::Avif),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            VariantFormat::Png => "png",
+            VariantFormat::Jpeg => "jpg",
+            VariantFormat::Webp => "webp",
+            VariantFormat::Avif => "avif",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            VariantFormat::Png => "image/png",
+            VariantFormat::Jpeg => "image/jpeg",
+            VariantFormat::Webp => "image/webp",
+            VariantFormat::Avif => "image/avif",
+        }
+    }
+
+    /// The smaller encoding a client lists in its `Accept` header, AVIF before
+    /// WebP. Wildcards don't count: browsers send `*/*` without decoding either.
+    pub fn negotiate(accept: &str) -> Option<Self> {
+        let accepted = |format: VariantFormat| {
+            accept.split(',').any(|range| {
+                let mut params = range.split(';').map(str::trim);
+                params
+                    .next()
+                    .is_some_and(|media| media.eq_ignore_ascii_case(format.content_type()))
+                    && params
+                        .filter_map(|param| param.strip_prefix("q="))
+                        .all(|q| q.parse::<f32>().is_ok_and(|q| q > 0.0))
+            })
+        };
+        [VariantFormat::Avif, VariantFormat::Webp]
+            .into_iter()
+            .find(|format| accepted(*format))
+    }
+}
+
+/// A resized and/or re-encoded copy of an image. Images are never upscaled.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct VariantSpec {
+    pub width: Option<u32>,
+    pub format: VariantFormat,
+    /// One of `QUALITIES`, ignored for PNG.
+    pub quality: u8,
+}
+
+impl VariantSpec {
+    /// Rounds the width up to the next allowed width and the quality to the
+    /// nearest allowed quality.
+    pub fn new(width: Option<u32>, format: VariantFormat, quality: Option<u8>) -> Self {
+        let quality = quality.unwrap_or(DEFAULT_QUALITY);
+        VariantSpec {
+            width: width.map(|w| {
+                WIDTHS
+                    .into_iter()
+                    .find(|snapped| *snapped >= w)
+                    .unwrap_or(WIDTHS[WIDTHS.len() - 1])
+            }),
+            format,
+            quality: QUALITIES
+                .into_iter()
+                .min_by_key(|snapped| snapped.abs_diff(quality))
+                .unwrap_or(DEFAULT_QUALITY),
+        }
+    }
+
+    /// `{stem}.w256.q80.webp`, or `{stem}.full.q80.webp` without resizing.
+    pub fn file_name(&self, stem: &str) -> String {
+        let width = self
+            .width
+            .map_or("full".to_string(), |width| format!("w{}", width));
+        format!(
+            "{}.{}.q{}.{}",
+            stem,
+            width,
+            self.quality,
+            self.format.extension()
+        )
+    }
+
+    pub fn encode(&self, image: &DynamicImage) -> anyhow::Result<Vec<u8>> {
+        let image = match self.width {
+            Some(width) if width < image.width() => {
+                let height =
+                    ((image.height() as u64 * width as u64) / image.width() as u64).max(1) as u32;
+                image.resize_exact(width, height, image::imageops::FilterType::Lanczos3)
+            }
+            _ => image.clone(),
+        };
+        let mut data = Vec::new();
+        match self.format {
+            VariantFormat::Png => {
+                image.write_to(&mut std::io::Cursor::new(&mut data), ImageFormat::Png)?
+            }
+            VariantFormat::Jpeg => {
+                image
+                    .to_rgb8()
+                    .write_with_encoder(JpegEncoder::new_with_quality(&mut data, self.quality))?;
+            }
+            VariantFormat::Webp => {
+                let rgba = image.to_rgba8();
+                let encoded = webp::Encoder::from_rgba(&rgba, rgba.width(), rgba.height())
+                    .encode(self.quality as f32);
+                data.extend_from_slice(&encoded);
+            }
+            VariantFormat::Avif => {
+                image
+                    .to_rgba8()
+                    .write_with_encoder(AvifEncoder::new_with_speed_quality(
+                        &mut data,
+                        8,
+                        self.quality,
+                    ))?;
+            }
+        }
+        Ok(data)
+    }
+}
+
+/// Returns the path of the variant of `original`, creating it when missing or
+/// older than the original.
+pub async fn ensure_variant(original: &Path, spec: &VariantSpec) -> anyhow::Result<PathBuf> {
+    let stem = original
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .ok_or_else(|| anyhow::anyhow!("invalid file name: {}", original.display()))?;
+    let dir = original
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+        .join(VARIANT_DIR);
+    let path = dir.join(spec.file_name(stem));
+    let original_modified = tokio::fs::metadata(original).await?.modified()?;
+    if let Ok(metadata) = tokio::fs::metadata(&path).await
+        && metadata.modified()? >= original_modified
+    {
+        return Ok(path);
+    }
+
+    let data = tokio::fs::read(original).await?;
+    let spec = *spec;
+    let encoded = tokio::task::spawn_blocking(move || {
+        let image = image::load_from_memory(&data)?;
+        spec.encode(&image)
+    })
+    .await??;
+    tokio::fs::create_dir_all(&dir).await?;
+    // concurrent requests for the same variant each write their own tmp file
+    let tmp_path = path.with_extension(format!("{}.tmp", uuid::Uuid::new_v4().simple()));
+    tokio::fs::write(&tmp_path, encoded).await?;
+    tokio::fs::rename(&tmp_path, &path).await?;
+    Ok(path)
+}
+
+/// Renders the common variants of an output, fetching it first when it was
+/// saved by another instance.
+pub async fn pregenerate_variants(config: Config, file_name: String) {
+    let original = match local_output(&config, &file_name).await {
+        Ok(Some(original)) => original,
+        Ok(None) => return,
+        Err(e) => {
+            error!("fetch {} failed: {}", file_name, e);
+            return;
+        }
+    };
+    for spec in PREGENERATED_VARIANTS {
+        if let Err(e) = ensure_variant(&original, &spec).await {
+            error!("generate variant of {} failed: {}", original.display(), e);
+        }
+    }
+}
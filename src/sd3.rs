@@ -1,13 +1,19 @@
 use anyhow::{Ok, Result, anyhow};
-use log::info;
+use log::{error, info};
 use rand::Rng;
 use rand::rng;
 use reqwest::Client;
 use serde_json::Value;
 
-use std::fs::File;
-use std::io::Write;
+use std::io::Cursor;
 
+use image::ImageFormat;
+use sha2::{Digest, Sha256};
+
+use crate::conf::config::Config;
+use crate::provenance::{manifest, watermark};
+use crate::render::png_metadata;
+use crate::storage::storage::output_storage_from_config;
 use crate::ws;
 use crate::ws::task_ws::TaskStatus;
 use crate::ws::task_ws::update_task_status;
@@ -25,6 +31,11 @@ pub struct ImagineRequest {
     pub sd3_clip_name1: String,
     pub sd3_clip_name2: String,
     pub sd3_clip_name3: String,
+    /// Sampled at random when unset.
+    pub seed: Option<u32>,
+    /// An image uploaded with [`SD3Client::upload_image`], for img2img workflows.
+    pub input_image: Option<String>,
+    pub denoise: Option<f64>,
 }
 
 impl SD3Client {
@@ -35,14 +46,37 @@ impl SD3Client {
         }
     }
 
-    pub async fn submit_imagine(&self, imagine: ImagineRequest) -> anyhow::Result<String> {
+    /// Queues the workflow and returns the prompt id with the seed it was sampled with.
+    pub async fn submit_imagine(&self, imagine: ImagineRequest) -> anyhow::Result<(String, u32)> {
         let workflow_id = uuid::Uuid::new_v4().to_string();
         let client_id = uuid::Uuid::new_v4().to_string();
-        let seed = rng().random_range(0..=u32::MAX) as i32;
+        let seed = imagine
+            .seed
+            .unwrap_or_else(|| rng().random_range(0..=u32::MAX));
         let images = self
             .submit_workflow(imagine, seed, workflow_id, client_id)
             .await?;
-        Ok(images)
+        Ok((images, seed))
+    }
+
+    /// Uploads an input image to ComfyUI and returns the name `LoadImage` knows it by.
+    pub async fn upload_image(&self, file_name: &str, data: Vec<u8>) -> anyhow::Result<String> {
+        let url = format!("http://{}/upload/image", self.server_address);
+        let part = reqwest::multipart::Part::bytes(data)
+            .file_name(file_name.to_string())
+            .mime_str("image/png")?;
+        let form = reqwest::multipart::Form::new()
+            .part("image", part)
+            .text("overwrite", "true");
+        let res = self.client.post(&url).multipart(form).send().await?;
+        let json: Value = res.error_for_status()?.json().await?;
+        let name = json["name"]
+            .as_str()
+            .ok_or_else(|| anyhow!("upload image failed: no name returned"))?;
+        match json["subfolder"].as_str() {
+            Some(subfolder) if !subfolder.is_empty() => Ok(format!("{}/{}", subfolder, name)),
+            _ => Ok(name.to_string()),
+        }
     }
 
     async fn queue_prompt(&self, workflow_data: Value) -> anyhow::Result<String> {
@@ -63,19 +97,19 @@ impl SD3Client {
 
     pub async fn fetch_sd3_image(
         &self,
-        img_tmp_path: &str,
+        config: &Config,
         prompt_id: &str,
     ) -> anyhow::Result<(TaskStatus, String)> {
         match ws::task_ws::get_task_status(prompt_id).await {
             Some(state) => {
                 let file_name = format!("{}.png", prompt_id);
-                let file_path = format!("{}/{}", img_tmp_path, file_name);
-                if File::open(file_path.clone()).is_ok() {
+                let storage = output_storage_from_config(config)?;
+                if storage.stat(&file_name).await?.is_some() {
                     return Ok((state, file_name));
                 }
-                let history = self.get_history(&prompt_id).await?;
+                let history = self.get_history(prompt_id).await?;
                 let mut output_images = std::collections::HashMap::new();
-                if let Some(history_data) = history.get(&prompt_id) {
+                if let Some(history_data) = history.get(prompt_id) {
                     for (node_id, node_output) in history_data["outputs"]
                         .as_object()
                         .ok_or_else(|| anyhow!("Outputs not found"))?
@@ -107,7 +141,27 @@ impl SD3Client {
 
                     for (_node_id, image_data_vec) in output_images {
                         for image_data in image_data_vec {
-                            self.save_image(&image_data, &file_path)?;
+                            // The raw output still carries the workflow, which
+                            // privacy mode promises never to serve.
+                            let image_data = match finish_image(config, prompt_id, &image_data)
+                                .await
+                            {
+                                std::result::Result::Ok(data) => data,
+                                Err(e) if config.png_privacy_mode => {
+                                    update_task_status(prompt_id, TaskStatus::ExecutionFailed)
+                                        .await;
+                                    return Err(anyhow!(
+                                        "finish image {} failed, not serving the raw output: {}",
+                                        prompt_id,
+                                        e
+                                    ));
+                                }
+                                Err(e) => {
+                                    error!("finish image {} failed: {}", prompt_id, e);
+                                    image_data
+                                }
+                            };
+                            storage.put(&file_name, image_data, "image/png").await?;
                         }
                     }
                     update_task_status(prompt_id, TaskStatus::ExecutionSuccess).await;
@@ -139,17 +193,36 @@ impl SD3Client {
     async fn submit_workflow(
         &self,
         imagine: ImagineRequest,
-        _seed: i32,
+        seed: u32,
         workflow_id: String,
         client_id: String,
     ) -> anyhow::Result<String> {
         let mut workflow_data: Value = imagine.workflow.clone();
         workflow_data["prompt"]["6"]["inputs"]["text"] = Value::String(imagine.prompt);
         workflow_data["prompt"]["294"]["inputs"]["steps"] = Value::Number(imagine.steps.into());
-        replace_placeholder(&mut workflow_data,"${model_name}",&imagine.sd3_model_file);
-        replace_placeholder(&mut workflow_data,"${sd3_clip_name1}",&imagine.sd3_clip_name1,        );
-        replace_placeholder(&mut workflow_data,"${sd3_clip_name2}",&imagine.sd3_clip_name2,);
-        replace_placeholder(&mut workflow_data,"${sd3_clip_name3}",&imagine.sd3_clip_name3,);
+        workflow_data["prompt"]["294"]["inputs"]["seed"] = Value::Number(seed.into());
+        if let Some(denoise) = imagine.denoise.and_then(serde_json::Number::from_f64) {
+            workflow_data["prompt"]["294"]["inputs"]["denoise"] = Value::Number(denoise);
+        }
+        if let Some(input_image) = &imagine.input_image {
+            replace_placeholder(&mut workflow_data, "${input_image}", input_image);
+        }
+        replace_placeholder(&mut workflow_data, "${model_name}", &imagine.sd3_model_file);
+        replace_placeholder(
+            &mut workflow_data,
+            "${sd3_clip_name1}",
+            &imagine.sd3_clip_name1,
+        );
+        replace_placeholder(
+            &mut workflow_data,
+            "${sd3_clip_name2}",
+            &imagine.sd3_clip_name2,
+        );
+        replace_placeholder(
+            &mut workflow_data,
+            "${sd3_clip_name3}",
+            &imagine.sd3_clip_name3,
+        );
         replace_placeholder(&mut workflow_data, "${client_id}", client_id.as_str());
         replace_placeholder(&mut workflow_data, "${workflow_id}", workflow_id.as_str());
         self.submit_sd3_queue(workflow_data).await
@@ -162,21 +235,75 @@ impl SD3Client {
         let json: Value = res.json().await?;
         anyhow::Ok(json)
     }
+}
 
-    #[allow(dead_code)]
-    fn save_image(&self, image_data: &[u8], path: &str) -> Result<()> {
-        let mut file = File::create(path)?;
-        file.write_all(image_data)?;
-        Ok(())
+/// Turns raw ComfyUI output into the file we serve: our metadata in the PNG
+/// text chunks, the task id watermarked into the pixels and a signed
+/// provenance manifest next to it.
+async fn finish_image(config: &Config, prompt_id: &str, data: &[u8]) -> Result<Vec<u8>> {
+    let metadata = task_metadata(prompt_id).await;
+    let mut data = png_metadata::annotate(data, &metadata, config.png_privacy_mode)?;
+    let watermarked = if config.watermark {
+        watermark_png(&data, prompt_id)
+            .inspect_err(|e| error!("watermark {} failed: {}", prompt_id, e))
+            .ok()
+    } else {
+        None
+    };
+    let mut scheme = None;
+    if let Some(watermarked) = watermarked {
+        data = watermarked;
+        scheme = Some(watermark::WATERMARK_SCHEME.to_string());
     }
+    let model = metadata
+        .iter()
+        .find(|(key, _)| *key == "model")
+        .map_or(config.sd3_model_file_name.clone(), |(_, model)| {
+            model.clone()
+        });
+    let claim = manifest::Claim {
+        generator: "Chroniq".to_string(),
+        ai_generated: true,
+        task_id: prompt_id.to_string(),
+        model,
+        created_at: chrono::Utc::now().timestamp(),
+        content_sha256: hex::encode(Sha256::digest(&data)),
+        watermark: scheme,
+    };
+    manifest::write_manifest(config, &manifest::sign(claim)?).await?;
+    Ok(data)
+}
+
+/// Re-encodes the pixels with the watermark, keeping the text chunks.
+fn watermark_png(data: &[u8], prompt_id: &str) -> Result<Vec<u8>> {
+    let text = png_metadata::read_text_chunks(data)?;
+    let mut image = image::load_from_memory(data)?.to_rgb8();
+    watermark::embed(&mut image, prompt_id)?;
+    let mut encoded = Vec::new();
+    image.write_to(&mut Cursor::new(&mut encoded), ImageFormat::Png)?;
+    png_metadata::rewrite_text_chunks(&encoded, |_| false, &text)
+}
+
+/// What we write into the PNG text chunks of a generated image.
+async fn task_metadata(prompt_id: &str) -> Vec<(&'static str, String)> {
+    let mut metadata = vec![("task_id", prompt_id.to_string())];
+    if let Some(params) = ws::task_ws::get_task_params(prompt_id).await {
+        metadata.extend([
+            ("prompt", params.prompt),
+            ("style", params.style),
+            ("seed", params.seed.to_string()),
+            ("steps", params.steps.to_string()),
+            ("model", params.model),
+            ("author", params.author),
+        ]);
+    }
+    metadata
 }
 
 fn replace_placeholder(value: &mut Value, placeholder: &str, replacement: &str) {
     match value {
-        Value::String(s) => {
-            if s.contains(placeholder) {
-                *s = s.replace(placeholder, replacement);
-            }
+        Value::String(s) if s.contains(placeholder) => {
+            *s = s.replace(placeholder, replacement);
         }
         Value::Array(arr) => {
             for item in arr {
@@ -188,6 +315,6 @@ fn replace_placeholder(value: &mut Value, placeholder: &str, replacement: &str)
                 replace_placeholder(val, placeholder, replacement);
             }
         }
-        _ => {} 
+        _ => {}
     }
 }
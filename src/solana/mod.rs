@@ -1 +1,4 @@
-pub mod solana;
\ No newline at end of file
+pub mod nft;
+#[allow(clippy::module_inception)]
+pub mod solana;
+pub mod verify;
\ No newline at end of file
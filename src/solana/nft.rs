@@ -0,0 +1,441 @@
+use std::str::FromStr;
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    pubkey,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer, read_keypair_file},
+    system_instruction, system_program,
+    transaction::Transaction,
+};
+
+use crate::{
+    conf::config::Config,
+    store::{
+        models::MintRecord,
+        store::{read_store, write_store},
+    },
+};
+
+const TOKEN_PROGRAM_ID: Pubkey = pubkey!("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA");
+const ASSOCIATED_TOKEN_PROGRAM_ID: Pubkey = pubkey!("ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL");
+const TOKEN_METADATA_PROGRAM_ID: Pubkey = pubkey!("metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s");
+const BUBBLEGUM_PROGRAM_ID: Pubkey = pubkey!("BGUMAp9Gq7iTEuizy4pqaxsTyUCBK68MDfK752saRPUY");
+const NOOP_PROGRAM_ID: Pubkey = pubkey!("noopb9bkMVfRPU8AsbpTUg8AQkHtKwMYZiFUjNRtMmV");
+const COMPRESSION_PROGRAM_ID: Pubkey = pubkey!("cmtDvXumGCrqC1Age74AVPhSRVXJMd8PJS91L8KbNCK");
+
+/// Size of an SPL token mint account.
+const MINT_ACCOUNT_LEN: usize = 82;
+/// Metaplex caps on-chain names and symbols at these lengths.
+const MAX_NAME_LEN: usize = 32;
+const MAX_SYMBOL_LEN: usize = 10;
+
+/// What to put on chain for one finished task.
+#[derive(Debug, Clone)]
+pub struct NftMetadata {
+    pub name: String,
+    pub symbol: String,
+    pub uri: String,
+    pub seller_fee_basis_points: u16,
+}
+
+pub async fn get_mint_record(prompt_id: &str) -> Option<MintRecord> {
+    read_store(|store| store.mints.get(prompt_id).cloned()).await
+}
+
+pub async fn record_mint(record: MintRecord) {
+    write_store(|store| store.mints.insert(record.prompt_id.clone(), record)).await;
+}
+
+/// Tasks and uploads minted so far; their files must be kept.
+pub async fn minted_ids() -> Vec<String> {
+    read_store(|store| store.mints.keys().cloned().collect()).await
+}
+
+/// Builds a Token Metadata NFT mint (mint account, ATA, metadata and master edition)
+/// paid for and owned by `owner`.
+pub async fn build_mint_transaction(
+    rpc_url: &str,
+    prompt_id: &str,
+    owner: &Pubkey,
+    metadata: &NftMetadata,
+) -> anyhow::Result<MintRecord> {
+    let client = RpcClient::new(rpc_url.to_string());
+    let mint = Keypair::new();
+    let mint_pubkey = mint.pubkey();
+    let rent = client
+        .get_minimum_balance_for_rent_exemption(MINT_ACCOUNT_LEN)
+        .await?;
+    let token_account = associated_token_address(owner, &mint_pubkey);
+    let (metadata_account, _) = Pubkey::find_program_address(
+        &[
+            b"metadata",
+            TOKEN_METADATA_PROGRAM_ID.as_ref(),
+            mint_pubkey.as_ref(),
+        ],
+        &TOKEN_METADATA_PROGRAM_ID,
+    );
+    let (edition_account, _) = Pubkey::find_program_address(
+        &[
+            b"metadata",
+            TOKEN_METADATA_PROGRAM_ID.as_ref(),
+            mint_pubkey.as_ref(),
+            b"edition",
+        ],
+        &TOKEN_METADATA_PROGRAM_ID,
+    );
+
+    let instructions = vec![
+        system_instruction::create_account(
+            owner,
+            &mint_pubkey,
+            rent,
+            MINT_ACCOUNT_LEN as u64,
+            &TOKEN_PROGRAM_ID,
+        ),
+        initialize_mint2(&mint_pubkey, owner),
+        create_associated_token_account_idempotent(owner, &token_account, owner, &mint_pubkey),
+        mint_to(&mint_pubkey, &token_account, owner, 1),
+        create_metadata_account_v3(&metadata_account, &mint_pubkey, owner, metadata),
+        create_master_edition_v3(&edition_account, &mint_pubkey, owner, &metadata_account),
+    ];
+    let blockhash = client.get_latest_blockhash().await?;
+    let mut transaction = Transaction::new_with_payer(&instructions, Some(owner));
+    transaction.try_partial_sign(&[&mint], blockhash)?;
+
+    Ok(MintRecord {
+        prompt_id: prompt_id.to_string(),
+        owner: owner.to_string(),
+        compressed: false,
+        mint: mint_pubkey.to_string(),
+        tree: None,
+        transaction: STANDARD.encode(bincode::serialize(&transaction)?),
+    })
+}
+
+/// Builds a Bubblegum `mint_v1` into the configured merkle tree. The server signs
+/// as tree delegate; the owner pays and receives the leaf.
+pub async fn build_compressed_mint_transaction(
+    config: &Config,
+    rpc_url: &str,
+    prompt_id: &str,
+    owner: &Pubkey,
+    metadata: &NftMetadata,
+) -> anyhow::Result<MintRecord> {
+    let tree = Pubkey::from_str(
+        config
+            .bubblegum_tree
+            .as_deref()
+            .ok_or_else(|| anyhow::anyhow!("BUBBLEGUM_TREE is required for compressed mints"))?,
+    )?;
+    let tree_authority = read_keypair_file(
+        config
+            .tree_authority_keypair
+            .as_deref()
+            .ok_or_else(|| anyhow::anyhow!("TREE_AUTHORITY_KEYPAIR is required"))?,
+    )
+    .map_err(|e| anyhow::anyhow!("failed to read tree authority keypair: {}", e))?;
+    let client = RpcClient::new(rpc_url.to_string());
+    let (tree_config, _) = Pubkey::find_program_address(&[tree.as_ref()], &BUBBLEGUM_PROGRAM_ID);
+
+    // the asset id depends on the leaf index, which is the tree's mint count
+    // at execution time; predict it from the current count
+    let tree_config_data = client.get_account_data(&tree_config).await?;
+    let num_minted = tree_config_data
+        .get(80..88)
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(u64::from_le_bytes)
+        .ok_or_else(|| anyhow::anyhow!("invalid tree config account"))?;
+    let (asset_id, _) = Pubkey::find_program_address(
+        &[b"asset", tree.as_ref(), &num_minted.to_le_bytes()],
+        &BUBBLEGUM_PROGRAM_ID,
+    );
+
+    let mut data = vec![145, 98, 192, 118, 184, 147, 118, 104];
+    write_string(&mut data, &truncate(&metadata.name, MAX_NAME_LEN));
+    write_string(&mut data, &truncate(&metadata.symbol, MAX_SYMBOL_LEN));
+    write_string(&mut data, &metadata.uri);
+    data.extend_from_slice(&metadata.seller_fee_basis_points.to_le_bytes());
+    data.push(0); // primary_sale_happened
+    data.push(1); // is_mutable
+    data.push(0); // edition_nonce: None
+    data.extend_from_slice(&[1, 0]); // token_standard: Some(NonFungible)
+    data.push(0); // collection: None
+    data.push(0); // uses: None
+    data.push(0); // token_program_version: Original
+    data.extend_from_slice(&1u32.to_le_bytes()); // creators
+    data.extend_from_slice(owner.as_ref());
+    data.push(0); // verified
+    data.push(100); // share
+    let instruction = Instruction {
+        program_id: BUBBLEGUM_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(tree_config, false),
+            AccountMeta::new_readonly(*owner, false),
+            AccountMeta::new_readonly(*owner, false),
+            AccountMeta::new(tree, false),
+            AccountMeta::new(*owner, true),
+            AccountMeta::new_readonly(tree_authority.pubkey(), true),
+            AccountMeta::new_readonly(NOOP_PROGRAM_ID, false),
+            AccountMeta::new_readonly(COMPRESSION_PROGRAM_ID, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data,
+    };
+    let blockhash = client.get_latest_blockhash().await?;
+    let mut transaction = Transaction::new_with_payer(&[instruction], Some(owner));
+    transaction.try_partial_sign(&[&tree_authority], blockhash)?;
+
+    Ok(MintRecord {
+        prompt_id: prompt_id.to_string(),
+        owner: owner.to_string(),
+        compressed: true,
+        mint: asset_id.to_string(),
+        tree: Some(tree.to_string()),
+        transaction: STANDARD.encode(bincode::serialize(&transaction)?),
+    })
+}
+
+fn associated_token_address(wallet: &Pubkey, mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[wallet.as_ref(), TOKEN_PROGRAM_ID.as_ref(), mint.as_ref()],
+        &ASSOCIATED_TOKEN_PROGRAM_ID,
+    )
+    .0
+}
+
+fn initialize_mint2(mint: &Pubkey, authority: &Pubkey) -> Instruction {
+    let mut data = vec![20, 0];
+    data.extend_from_slice(authority.as_ref());
+    data.push(1); // freeze authority: Some
+    data.extend_from_slice(authority.as_ref());
+    Instruction {
+        program_id: TOKEN_PROGRAM_ID,
+        accounts: vec![AccountMeta::new(*mint, false)],
+        data,
+    }
+}
+
+fn create_associated_token_account_idempotent(
+    payer: &Pubkey,
+    token_account: &Pubkey,
+    wallet: &Pubkey,
+    mint: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: ASSOCIATED_TOKEN_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(*payer, true),
+            AccountMeta::new(*token_account, false),
+            AccountMeta::new_readonly(*wallet, false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(TOKEN_PROGRAM_ID, false),
+        ],
+        data: vec![1],
+    }
+}
+
+fn mint_to(mint: &Pubkey, token_account: &Pubkey, authority: &Pubkey, amount: u64) -> Instruction {
+    let mut data = vec![7];
+    data.extend_from_slice(&amount.to_le_bytes());
+    Instruction {
+        program_id: TOKEN_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(*mint, false),
+            AccountMeta::new(*token_account, false),
+            AccountMeta::new_readonly(*authority, true),
+        ],
+        data,
+    }
+}
+
+fn create_metadata_account_v3(
+    metadata_account: &Pubkey,
+    mint: &Pubkey,
+    owner: &Pubkey,
+    metadata: &NftMetadata,
+) -> Instruction {
+    let mut data = vec![33];
+    write_string(&mut data, &truncate(&metadata.name, MAX_NAME_LEN));
+    write_string(&mut data, &truncate(&metadata.symbol, MAX_SYMBOL_LEN));
+    write_string(&mut data, &metadata.uri);
+    data.extend_from_slice(&metadata.seller_fee_basis_points.to_le_bytes());
+    data.push(1); // creators: Some
+    data.extend_from_slice(&1u32.to_le_bytes());
+    data.extend_from_slice(owner.as_ref());
+    data.push(1); // verified, the owner signs as update authority
+    data.push(100); // share
+    data.push(0); // collection: None
+    data.push(0); // uses: None
+    data.push(1); // is_mutable
+    data.push(0); // collection_details: None
+    Instruction {
+        program_id: TOKEN_METADATA_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(*metadata_account, false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new_readonly(*owner, true),
+            AccountMeta::new(*owner, true),
+            AccountMeta::new_readonly(*owner, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data,
+    }
+}
+
+fn create_master_edition_v3(
+    edition_account: &Pubkey,
+    mint: &Pubkey,
+    owner: &Pubkey,
+    metadata_account: &Pubkey,
+) -> Instruction {
+    let mut data = vec![17];
+    data.push(1); // max_supply: Some(0), a one of one
+    data.extend_from_slice(&0u64.to_le_bytes());
+    Instruction {
+        program_id: TOKEN_METADATA_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(*edition_account, false),
+            AccountMeta::new(*mint, false),
+            AccountMeta::new_readonly(*owner, true),
+            AccountMeta::new_readonly(*owner, true),
+            AccountMeta::new(*owner, true),
+            AccountMeta::new(*metadata_account, false),
+            AccountMeta::new_readonly(TOKEN_PROGRAM_ID, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data,
+    }
+}
+
+/// Borsh string: u32 little-endian length followed by the UTF-8 bytes.
+fn write_string(data: &mut Vec<u8>, value: &str) {
+    data.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    data.extend_from_slice(value.as_bytes());
+}
+
+fn truncate(value: &str, max_len: usize) -> String {
+    let mut end = value.len().min(max_len);
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    value[..end].to_string()
+}
+
+// The mint tests need a local validator with the Token Metadata program:
+//
+// ```sh
+// solana-test-validator --reset --url mainnet-beta \
+//     --clone-upgradeable-program metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s
+// cargo test -- --ignored
+// ```
+//
+// `SOLANA_TEST_RPC` overrides the default `http://127.0.0.1:8899`.
+#[cfg(test)]
+mod tests {
+    use solana_sdk::commitment_config::CommitmentConfig;
+
+    use super::*;
+
+    fn rpc_url() -> String {
+        std::env::var("SOLANA_TEST_RPC").unwrap_or_else(|_| "http://127.0.0.1:8899".to_string())
+    }
+
+    fn metadata() -> NftMetadata {
+        NftMetadata {
+            name: "A lake at dawn, seen from the old boat house".to_string(),
+            symbol: "CHRONIQUE".to_string(),
+            uri: "https://example.com/metadata/task.json".to_string(),
+            seller_fee_basis_points: 500,
+        }
+    }
+
+    /// A funded wallet standing in for the owner.
+    async fn funded_owner(client: &RpcClient) -> Keypair {
+        let owner = Keypair::new();
+        let signature = client
+            .request_airdrop(&owner.pubkey(), 1_000_000_000)
+            .await
+            .unwrap();
+        while !client.confirm_transaction(&signature).await.unwrap() {
+            tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        }
+        owner
+    }
+
+    #[test]
+    fn truncate_keeps_char_boundaries() {
+        assert_eq!(truncate("CHRONIQUE", MAX_SYMBOL_LEN), "CHRONIQUE");
+        assert_eq!(truncate("ÅÅÅÅÅÅ", MAX_SYMBOL_LEN), "ÅÅÅÅÅ");
+        assert_eq!(truncate("ÅÅÅÅÅÅ", 9), "ÅÅÅÅ");
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a local validator with Token Metadata"]
+    async fn mint_transaction_lands_on_local_validator() {
+        let client = RpcClient::new_with_commitment(rpc_url(), CommitmentConfig::confirmed());
+        let owner = funded_owner(&client).await;
+        let record = build_mint_transaction(&rpc_url(), "task", &owner.pubkey(), &metadata())
+            .await
+            .unwrap();
+        assert_eq!(record.owner, owner.pubkey().to_string());
+        assert!(!record.compressed);
+
+        // what the owner's wallet does with the returned transaction
+        let mut transaction: Transaction =
+            bincode::deserialize(&STANDARD.decode(&record.transaction).unwrap()).unwrap();
+        let blockhash = transaction.message.recent_blockhash;
+        transaction.try_partial_sign(&[&owner], blockhash).unwrap();
+        client
+            .send_and_confirm_transaction(&transaction)
+            .await
+            .unwrap();
+
+        let mint = Pubkey::from_str(&record.mint).unwrap();
+        let mint_account = client.get_account(&mint).await.unwrap();
+        assert_eq!(mint_account.owner, TOKEN_PROGRAM_ID);
+        assert_eq!(mint_account.data.len(), MINT_ACCOUNT_LEN);
+        // supply sits after the optional mint authority
+        let supply = u64::from_le_bytes(mint_account.data[36..44].try_into().unwrap());
+        assert_eq!(supply, 1);
+
+        let token_account = client
+            .get_account(&associated_token_address(&owner.pubkey(), &mint))
+            .await
+            .unwrap();
+        assert_eq!(token_account.owner, TOKEN_PROGRAM_ID);
+
+        let (metadata_account, _) = Pubkey::find_program_address(
+            &[
+                b"metadata",
+                TOKEN_METADATA_PROGRAM_ID.as_ref(),
+                mint.as_ref(),
+            ],
+            &TOKEN_METADATA_PROGRAM_ID,
+        );
+        let metadata_account = client.get_account(&metadata_account).await.unwrap();
+        assert_eq!(metadata_account.owner, TOKEN_METADATA_PROGRAM_ID);
+        // name and symbol are cut to the Metaplex limits
+        let data = String::from_utf8_lossy(&metadata_account.data);
+        assert!(data.contains(&truncate(&metadata().name, MAX_NAME_LEN)));
+        assert!(data.contains("CHRONIQUE"));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a local validator"]
+    async fn unsigned_mint_transaction_is_rejected() {
+        let client = RpcClient::new_with_commitment(rpc_url(), CommitmentConfig::confirmed());
+        let owner = funded_owner(&client).await;
+        let record = build_mint_transaction(&rpc_url(), "task", &owner.pubkey(), &metadata())
+            .await
+            .unwrap();
+        // only the mint keypair signed; the owner must still sign
+        let transaction: Transaction =
+            bincode::deserialize(&STANDARD.decode(&record.transaction).unwrap()).unwrap();
+        assert!(!transaction.is_signed());
+        assert!(client.send_transaction(&transaction).await.is_err());
+    }
+}
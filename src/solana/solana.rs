@@ -1,17 +1,30 @@
 use std::{error::Error, str::FromStr};
 
-use log::error;
+use log::{error, info};
 use rand::seq::IndexedRandom;
 use serde::{Deserialize, Serialize};
 
 use solana_client::{nonblocking::rpc_client::RpcClient, rpc_config::RpcTransactionConfig};
-use solana_sdk::{commitment_config::CommitmentConfig, signature::Signature};
+use solana_sdk::{
+    commitment_config::CommitmentConfig, message::MessageHeader, signature::Signature,
+};
 use solana_transaction_status::{
-    EncodedConfirmedTransactionWithStatusMeta, EncodedTransaction, UiInstruction, UiMessage,
-    UiParsedInstruction, UiTransactionEncoding, option_serializer::OptionSerializer,
+    EncodedConfirmedTransactionWithStatusMeta, EncodedTransaction, TransactionBinaryEncoding,
+    UiCompiledInstruction, UiInstruction, UiLoadedAddresses, UiMessage, UiParsedInstruction,
+    UiTransactionEncoding, option_serializer::OptionSerializer,
+};
+
+use crate::{
+    conf::config::Config,
+    moderation::moderation::{Verdict, moderate},
+    storage::{mirror::mirror_memory_image, storage::blob_store_from_config},
+    store::{
+        models::{Comment, MemberChange, Memory, Role},
+        store::write_store,
+    },
 };
 
-use crate::conf::config::Config;
+use super::verify;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ProcessedTransaction {
@@ -25,6 +38,19 @@ pub struct ProcessedTransaction {
     pub raw_data: Option<serde_json::Value>,
     pub from: String,
     pub to: Vec<String>,
+    pub memos: Vec<String>,
+    pub memo_signers: Vec<String>,
+    pub balance_changes: Vec<BalanceChange>,
+}
+
+/// Lamport or SPL token balance of one account before and after the transaction.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BalanceChange {
+    pub account: String,
+    pub mint: Option<String>,
+    pub owner: Option<String>,
+    pub pre: u64,
+    pub post: u64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -33,6 +59,131 @@ pub struct TitleContent {
     pub uri: String,
     pub title: String,
     pub content: String,
+    /// Album of the author the memory belongs to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub album: Option<String>,
+    /// Links the memo to a memory already added through the API.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+}
+
+/// A CHRO memo carrying an operation instead of a memory, e.g.
+/// `{"p":"CHRO","op":"member",...}`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OpMemo {
+    pub p: String,
+    #[serde(flatten)]
+    pub op: ChroOp,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum ChroOp {
+    /// A membership change. A missing role removes the member. Members
+    /// joining by themselves name the invitation by its token hash.
+    Member {
+        album: String,
+        member: String,
+        #[serde(default)]
+        role: Option<Role>,
+        #[serde(default)]
+        invitation: Option<String>,
+    },
+    /// A comment on a memory, referenced by id or memo transaction signature.
+    /// `id` links it to a comment already posted through the API.
+    Comment {
+        memory: String,
+        body: String,
+        #[serde(default)]
+        id: Option<String>,
+        #[serde(default)]
+        parent: Option<String>,
+    },
+}
+
+/// What the indexer made of a transaction.
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum IndexedItem {
+    Memory(Memory),
+    Membership(MemberChange),
+    Comment(Comment),
+}
+
+fn parse_op_memo(transcation: &ProcessedTransaction) -> Option<ChroOp> {
+    let value = match transcation.raw_data.as_ref()? {
+        serde_json::Value::String(s) => serde_json::from_str(s).ok()?,
+        raw_data => raw_data.clone(),
+    };
+    let memo = serde_json::from_value::<OpMemo>(value).ok()?;
+    (memo.p == "CHRO").then_some(memo.op)
+}
+
+/// Operations cost nothing, but must be signed by the fee payer they are
+/// attributed to.
+async fn index_op(
+    transaction: &ProcessedTransaction,
+    op: ChroOp,
+    config: &Config,
+) -> anyhow::Result<IndexedItem> {
+    if !transaction.success {
+        return Err(verify::RejectReason::TransactionFailed.into());
+    }
+    if config.require_memo_signer && !transaction.memo_signers.contains(&transaction.from) {
+        return Err(verify::RejectReason::UnsignedMemo.into());
+    }
+    let at = transaction
+        .block_time
+        .unwrap_or_else(|| chrono::Utc::now().timestamp());
+    match op {
+        ChroOp::Member {
+            album,
+            member,
+            role,
+            invitation,
+        } => {
+            let change = MemberChange {
+                album_id: album,
+                member,
+                role,
+                by: transaction.from.clone(),
+                at,
+                tx_signature: Some(transaction.signature.clone()),
+            };
+            let change = write_store(|store| {
+                store.apply_indexed_member_change(change, invitation.as_deref())
+            })
+            .await?;
+            Ok(IndexedItem::Membership(change))
+        }
+        ChroOp::Comment {
+            memory,
+            body,
+            id,
+            parent,
+        } => {
+            if body.trim().is_empty() {
+                anyhow::bail!("comment body is empty");
+            }
+            let mut comment = Comment {
+                id: id.unwrap_or_else(|| uuid::Uuid::new_v4().to_string()),
+                memory_id: memory,
+                parent_id: parent,
+                author: transaction.from.clone(),
+                body,
+                hidden: false,
+                deleted: false,
+                tx_signature: Some(transaction.signature.clone()),
+                slot: transaction.slot,
+                block_time: transaction.block_time,
+                created_at: at,
+            };
+            // what is on chain can not be rejected, only kept out of sight
+            comment.hidden = moderate(config, &comment).await != Verdict::Allow;
+            let comment = write_store(|store| store.apply_indexed_comment(comment)).await?;
+            Ok(IndexedItem::Comment(comment))
+        }
+    }
 }
 
 fn parse_raw_data(transcation: &ProcessedTransaction) -> anyhow::Result<TitleContent> {
@@ -48,7 +199,9 @@ fn parse_raw_data(transcation: &ProcessedTransaction) -> anyhow::Result<TitleCon
         };
         match serde_json::from_value::<TitleContent>(parsed_value) {
             Ok(chroniq) => {
-                if chroniq.p != "CHRO" {}
+                if chroniq.p != "CHRO" {
+                    return Err(anyhow::Error::msg("memo is not a CHRO payload"));
+                }
                 if chroniq.uri.trim().is_empty()
                     || chroniq.title.trim().is_empty()
                     || chroniq.content.trim().is_empty()
@@ -56,47 +209,90 @@ fn parse_raw_data(transcation: &ProcessedTransaction) -> anyhow::Result<TitleCon
                     error!("Invalid chroniq data: {:?}", chroniq);
                     return Err(anyhow::Error::msg("Invalid chroniq data"));
                 }
-                return anyhow::Ok(chroniq);
+                anyhow::Ok(chroniq)
             }
             Err(e) => {
                 error!("{:?}", e);
-                return Err(anyhow::Error::msg(format!(
+                Err(anyhow::Error::msg(format!(
                     "raw_data parse obj failed: {}",
                     e
-                )));
+                )))
             }
         }
     } else {
-        return Err(anyhow::Error::msg("raw_data has None"));
+        Err(anyhow::Error::msg("raw_data has None"))
     }
 }
 
-#[allow(dead_code)]
-fn get_random_point(solana_points: &Vec<String>) -> &String {
+fn get_random_point(solana_points: &[String]) -> &String {
     let mut rng = rand::rng();
     solana_points.choose(&mut rng).unwrap()
 }
 
-#[allow(dead_code)]
+/// Fetches a transaction, verifies its CHRO memo and indexes it as a memory
+/// or operation. Returns `None` when the transaction carries no CHRO memo.
 pub async fn solana_http_query(
     tx_hash: &str,
     config: &Config,
-) -> anyhow::Result<(), anyhow::Error> {
+) -> anyhow::Result<Option<IndexedItem>> {
     let solana_point = get_random_point(&config.solana_points);
     match signature_query(solana_point, tx_hash).await {
         Ok(transaction) => {
+            if let Some(op) = parse_op_memo(&transaction) {
+                return Ok(Some(index_op(&transaction, op, config).await?));
+            }
             match parse_raw_data(&transaction) {
-                Ok(_title_content) => {
-                    // let author = &transaction.from;
-                    // let image_url = &title_content.uri;
-                    // let title = &title_content.title;
-                    // let content = &title_content.content;
-                    //download img& upload to AWS OSS
-                    anyhow::Ok(())
+                Ok(title_content) => {
+                    let author = verify::verify_transaction(
+                        &transaction,
+                        &verify::VerifyPolicy::from_config(config),
+                    )?;
+                    info!(
+                        "memory {} accepted: fee payer {}, memo signers {:?}, paid {}",
+                        transaction.signature, author.fee_payer, author.memo_signers, author.paid
+                    );
+                    // a dead image URL must not keep the memory out of the index
+                    let store = blob_store_from_config(config)?;
+                    let mirrored = match mirror_memory_image(
+                        store,
+                        &transaction.signature,
+                        &title_content,
+                        config,
+                    )
+                    .await
+                    {
+                        Ok(mirrored) => Some(mirrored),
+                        Err(e) => {
+                            error!("mirror {} failed: {}", title_content.uri, e);
+                            None
+                        }
+                    };
+                    let memory = Memory {
+                        id: title_content
+                            .id
+                            .clone()
+                            .unwrap_or_else(|| uuid::Uuid::new_v4().to_string()),
+                        album_id: title_content.album.clone(),
+                        author: author.fee_payer,
+                        image: title_content.uri,
+                        image_sha256: mirrored.map(|m| m.sha256),
+                        title: title_content.title,
+                        content: title_content.content,
+                        tx_signature: Some(transaction.signature.clone()),
+                        slot: transaction.slot,
+                        block_time: transaction.block_time,
+                        created_at: chrono::Utc::now().timestamp(),
+                        like_count: 0,
+                        comment_count: 0,
+                        video: None,
+                    };
+                    Ok(Some(IndexedItem::Memory(
+                        write_store(|store| store.upsert_indexed_memory(memory)).await,
+                    )))
                 }
                 Err(_e) => {
                     error!("raw_data parse json failed Ignore this Errors");
-                    return anyhow::Ok(());
+                    anyhow::Ok(None)
                 }
             }
         }
@@ -111,7 +307,9 @@ async fn signature_query(rpc_url: &str, signature: &str) -> anyhow::Result<Proce
         .get_transaction_with_config(
             &signature,
             RpcTransactionConfig {
-                encoding: Some(UiTransactionEncoding::JsonParsed),
+                // the binary form keeps the account list of every instruction,
+                // which the parsed memo instruction drops
+                encoding: Some(UiTransactionEncoding::Base64),
                 commitment: Some(CommitmentConfig::confirmed()),
                 max_supported_transaction_version: Some(0),
             },
@@ -133,6 +331,31 @@ async fn signature_query(rpc_url: &str, signature: &str) -> anyhow::Result<Proce
     }
 }
 
+/// Program ids of the SPL Memo program, v1 and v2.
+const MEMO_PROGRAM_IDS: [&str; 2] = [
+    "Memo1UhkJRfHyvLMcVucJwxXeuD728EqVDDwQDxFMNo",
+    "MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr",
+];
+
+/// An account of the transaction with the loaded lookup table addresses resolved.
+struct AccountView {
+    pubkey: String,
+    signer: bool,
+    writable: bool,
+}
+
+/// An instruction normalized across encodings, top-level or inner.
+struct InstructionView {
+    program_id: String,
+    accounts: Vec<String>,
+    data: InstructionData,
+}
+
+enum InstructionData {
+    Raw(Vec<u8>),
+    Parsed(serde_json::Value),
+}
+
 async fn extract_transaction_info(
     transaction: EncodedConfirmedTransactionWithStatusMeta,
     signature: &Signature,
@@ -140,7 +363,7 @@ async fn extract_transaction_info(
     let mut processed_tx = ProcessedTransaction {
         signature: signature.to_string(),
         slot: Some(transaction.slot),
-        block_time: None,
+        block_time: transaction.block_time,
         from: "".to_string(),
         to: Vec::new(),
         program_id: None,
@@ -148,59 +371,392 @@ async fn extract_transaction_info(
         logs: Vec::new(),
         encoding_type: String::new(),
         raw_data: None,
+        memos: Vec::new(),
+        memo_signers: Vec::new(),
+        balance_changes: Vec::new(),
+    };
+    let meta = transaction.transaction.meta;
+    let mut loaded_addresses = UiLoadedAddresses {
+        writable: Vec::new(),
+        readonly: Vec::new(),
     };
-    if let Some(meta) = transaction.transaction.meta {
+    let mut inner_instructions = Vec::new();
+    let mut lamports = (Vec::new(), Vec::new());
+    let mut token_balances = (Vec::new(), Vec::new());
+    if let Some(meta) = meta {
+        lamports = (meta.pre_balances, meta.post_balances);
         processed_tx.success = meta.status.is_ok();
         if let OptionSerializer::Some(logs) = meta.log_messages {
             processed_tx.logs = logs;
         }
+        if let OptionSerializer::Some(loaded) = meta.loaded_addresses {
+            loaded_addresses = loaded;
+        }
+        if let OptionSerializer::Some(inner) = meta.inner_instructions {
+            inner_instructions = inner;
+        }
+        if let OptionSerializer::Some(pre) = meta.pre_token_balances {
+            token_balances.0 = pre;
+        }
+        if let OptionSerializer::Some(post) = meta.post_token_balances {
+            token_balances.1 = post;
+        }
     };
-    match &transaction.transaction.transaction {
-        EncodedTransaction::Json(ui_transaction) => {
-            processed_tx.encoding_type = "Json".to_string();
-            match &ui_transaction.message {
-                UiMessage::Parsed(parsed_message) => {
-                    if !&parsed_message.account_keys.is_empty() {
-                        for account in &parsed_message.account_keys {
-                            if account.signer {
-                                processed_tx.from = account.pubkey.clone()
-                            } else {
-                                if account.writable {
-                                    processed_tx.to.push(account.pubkey.clone());
-                                }
-                            }
-                        }
-                    }
-                    if !&parsed_message.instructions.is_empty() {
-                        if parsed_message.instructions.len() > 2 as usize {
-                            for instruction in &parsed_message.instructions {
-                                match &instruction {
-                                    UiInstruction::Parsed(UiParsedInstruction::Parsed(
-                                        parsed_info,
-                                    )) => {
-                                        if parsed_info.program == "spl-memo".to_string()
-                                            && parsed_info.program_id
-                                                == "MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr"
-                                                    .to_string()
-                                        {
-                                            let data = parsed_info.parsed.clone();
-                                            processed_tx.program_id =
-                                                Some(parsed_info.program_id.clone());
-                                            processed_tx.slot = Some(transaction.slot);
-                                            processed_tx.block_time = transaction.block_time;
-                                            processed_tx.raw_data = Some(data);
-                                        }
-                                    }
-                                    _ => {}
-                                }
-                            }
-                        }
-                    }
-                }
-                _ => {}
+
+    let (accounts, instructions) = match &transaction.transaction.transaction {
+        EncodedTransaction::Json(ui_transaction) => match &ui_transaction.message {
+            UiMessage::Parsed(parsed_message) => {
+                processed_tx.encoding_type = "JsonParsed".to_string();
+                let accounts = parsed_message
+                    .account_keys
+                    .iter()
+                    .map(|account| AccountView {
+                        pubkey: account.pubkey.clone(),
+                        signer: account.signer,
+                        writable: account.writable,
+                    })
+                    .collect::<Vec<_>>();
+                let instructions = parsed_message
+                    .instructions
+                    .iter()
+                    .map(|instruction| ui_instruction_view(instruction, &accounts))
+                    .collect::<Vec<_>>();
+                (accounts, instructions)
+            }
+            UiMessage::Raw(raw_message) => {
+                processed_tx.encoding_type = "Json".to_string();
+                let accounts = resolve_accounts(
+                    &raw_message.account_keys,
+                    &raw_message.header,
+                    &loaded_addresses,
+                );
+                let instructions = raw_message
+                    .instructions
+                    .iter()
+                    .map(|instruction| compiled_instruction_view(instruction, &accounts))
+                    .collect::<Vec<_>>();
+                (accounts, instructions)
+            }
+        },
+        encoded @ (EncodedTransaction::LegacyBinary(_) | EncodedTransaction::Binary(_, _)) => {
+            processed_tx.encoding_type = match encoded {
+                EncodedTransaction::Binary(_, TransactionBinaryEncoding::Base64) => "Base64",
+                _ => "Base58",
             }
+            .to_string();
+            let versioned = encoded
+                .decode()
+                .ok_or("failed to decode binary transaction")?;
+            let static_keys = versioned
+                .message
+                .static_account_keys()
+                .iter()
+                .map(|key| key.to_string())
+                .collect::<Vec<_>>();
+            let accounts =
+                resolve_accounts(&static_keys, versioned.message.header(), &loaded_addresses);
+            let instructions = versioned
+                .message
+                .instructions()
+                .iter()
+                .map(|instruction| InstructionView {
+                    program_id: account_at(&accounts, instruction.program_id_index),
+                    accounts: instruction
+                        .accounts
+                        .iter()
+                        .map(|index| account_at(&accounts, *index))
+                        .collect(),
+                    data: InstructionData::Raw(instruction.data.clone()),
+                })
+                .collect::<Vec<_>>();
+            (accounts, instructions)
+        }
+        EncodedTransaction::Accounts(_) => {
+            return Err("transaction was fetched with accounts-only details".into());
+        }
+    };
+
+    // the fee payer is always the first account of the message
+    if let Some(fee_payer) = accounts.first() {
+        processed_tx.from = fee_payer.pubkey.clone();
+    }
+    for account in &accounts {
+        if !account.signer && account.writable {
+            processed_tx.to.push(account.pubkey.clone());
+        }
+    }
+    for (index, account) in accounts.iter().enumerate() {
+        if let (Some(pre), Some(post)) = (lamports.0.get(index), lamports.1.get(index)) {
+            processed_tx.balance_changes.push(BalanceChange {
+                account: account.pubkey.clone(),
+                mint: None,
+                owner: None,
+                pre: *pre,
+                post: *post,
+            });
+        }
+    }
+    for post in &token_balances.1 {
+        let pre = token_balances
+            .0
+            .iter()
+            .find(|pre| pre.account_index == post.account_index && pre.mint == post.mint)
+            .and_then(|pre| pre.ui_token_amount.amount.parse::<u64>().ok())
+            .unwrap_or(0);
+        processed_tx.balance_changes.push(BalanceChange {
+            account: account_at(&accounts, post.account_index),
+            mint: Some(post.mint.clone()),
+            owner: match &post.owner {
+                OptionSerializer::Some(owner) => Some(owner.clone()),
+                _ => None,
+            },
+            pre,
+            post: post.ui_token_amount.amount.parse::<u64>().unwrap_or(0),
+        });
+    }
+
+    // inner instructions are reported per top-level index, so memos sent via CPI
+    // are visited right after the instruction that invoked them
+    let mut ordered = Vec::new();
+    for (index, instruction) in instructions.into_iter().enumerate() {
+        ordered.push(instruction);
+        for inner in inner_instructions
+            .iter()
+            .filter(|inner| inner.index as usize == index)
+        {
+            ordered.extend(
+                inner
+                    .instructions
+                    .iter()
+                    .map(|instruction| ui_instruction_view(instruction, &accounts)),
+            );
+        }
+    }
+
+    // wallets may add memos of their own, so the first CHRO memo is indexed and
+    // only without one the first memo is kept to report
+    let mut chro_found = false;
+    for instruction in ordered {
+        if !MEMO_PROGRAM_IDS.contains(&instruction.program_id.as_str()) {
+            continue;
+        }
+        let memo = match instruction.data {
+            InstructionData::Parsed(serde_json::Value::String(memo)) => memo,
+            InstructionData::Parsed(other) => other.to_string(),
+            InstructionData::Raw(bytes) => match String::from_utf8(bytes) {
+                Ok(memo) => memo,
+                Err(_) => continue,
+            },
+        };
+        let chro = is_chro_memo(&memo);
+        if processed_tx.raw_data.is_none() || (chro && !chro_found) {
+            chro_found = chro;
+            processed_tx.memo_signers = instruction
+                .accounts
+                .iter()
+                .filter(|pubkey| {
+                    accounts
+                        .iter()
+                        .any(|account| account.signer && &account.pubkey == *pubkey)
+                })
+                .cloned()
+                .collect();
+            processed_tx.program_id = Some(instruction.program_id.clone());
+            processed_tx.raw_data = Some(serde_json::Value::String(memo.clone()));
         }
-        _ => {}
+        processed_tx.memos.push(memo);
     }
     Ok(processed_tx)
 }
+
+fn is_chro_memo(memo: &str) -> bool {
+    serde_json::from_str::<serde_json::Value>(memo)
+        .is_ok_and(|value| value.get("p").and_then(|p| p.as_str()) == Some("CHRO"))
+}
+
+/// Orders static keys followed by the writable and readonly lookup table addresses,
+/// which is how compiled instruction indexes address accounts in a v0 message.
+fn resolve_accounts(
+    static_keys: &[String],
+    header: &MessageHeader,
+    loaded_addresses: &UiLoadedAddresses,
+) -> Vec<AccountView> {
+    let signed = header.num_required_signatures as usize;
+    let writable_signed = signed.saturating_sub(header.num_readonly_signed_accounts as usize);
+    let writable_unsigned = static_keys
+        .len()
+        .saturating_sub(header.num_readonly_unsigned_accounts as usize);
+    let mut accounts = static_keys
+        .iter()
+        .enumerate()
+        .map(|(index, pubkey)| AccountView {
+            pubkey: pubkey.clone(),
+            signer: index < signed,
+            writable: if index < signed {
+                index < writable_signed
+            } else {
+                index < writable_unsigned
+            },
+        })
+        .collect::<Vec<_>>();
+    for (addresses, writable) in [
+        (&loaded_addresses.writable, true),
+        (&loaded_addresses.readonly, false),
+    ] {
+        accounts.extend(addresses.iter().map(|pubkey| AccountView {
+            pubkey: pubkey.clone(),
+            signer: false,
+            writable,
+        }));
+    }
+    accounts
+}
+
+fn account_at(accounts: &[AccountView], index: u8) -> String {
+    accounts
+        .get(index as usize)
+        .map(|account| account.pubkey.clone())
+        .unwrap_or_default()
+}
+
+fn compiled_instruction_view(
+    instruction: &UiCompiledInstruction,
+    accounts: &[AccountView],
+) -> InstructionView {
+    InstructionView {
+        program_id: account_at(accounts, instruction.program_id_index),
+        accounts: instruction
+            .accounts
+            .iter()
+            .map(|index| account_at(accounts, *index))
+            .collect(),
+        data: InstructionData::Raw(
+            bs58::decode(&instruction.data)
+                .into_vec()
+                .unwrap_or_default(),
+        ),
+    }
+}
+
+fn ui_instruction_view(instruction: &UiInstruction, accounts: &[AccountView]) -> InstructionView {
+    match instruction {
+        UiInstruction::Compiled(compiled) => compiled_instruction_view(compiled, accounts),
+        UiInstruction::Parsed(UiParsedInstruction::Parsed(parsed_info)) => InstructionView {
+            program_id: parsed_info.program_id.clone(),
+            // the parsed memo form carries no account list
+            accounts: Vec::new(),
+            data: InstructionData::Parsed(parsed_info.parsed.clone()),
+        },
+        UiInstruction::Parsed(UiParsedInstruction::PartiallyDecoded(decoded)) => InstructionView {
+            program_id: decoded.program_id.clone(),
+            accounts: decoded.accounts.clone(),
+            data: InstructionData::Raw(bs58::decode(&decoded.data).into_vec().unwrap_or_default()),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAYER: &str = "AWxggjuZRmWULwxwPeM6ZZxRtdDdekVq22mFRx2QbW7U";
+    const COSIGNER: &str = "aJ4D1GmusW8Z2FUBfdvFH9uZJmGSGx92B7jZy9EQA2h";
+    const RECIPIENT: &str = "7tark5iZaRrMfGKtKy1aqpGuRgoxbE6ec7Z5Qa4Jc5xr";
+    const LOOKUP_READONLY: &str = "9iJBDb6PyPuQRHW6YJWjFqmkLiUd2zF4kHSWFKapr2qg";
+    const MEMO: &str = r#"{"p": "CHRO", "uri": "https://example.com/a.png", "title": "Lake", "content": "first swim"}"#;
+
+    /// `getTransaction` results as returned with base64 encoding.
+    async fn fixture(json: &str) -> ProcessedTransaction {
+        let transaction: EncodedConfirmedTransactionWithStatusMeta =
+            serde_json::from_str(json).unwrap();
+        extract_transaction_info(transaction, &Signature::default())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn v0_lookup_table_accounts_are_resolved() {
+        let tx = fixture(include_str!("fixtures/v0_lookup_table.json")).await;
+        assert_eq!(tx.encoding_type, "Base64");
+        assert!(tx.success);
+        assert_eq!(tx.slot, Some(330000001));
+        assert_eq!(tx.from, PAYER);
+        // the loaded writable address is a recipient, the readonly one is not
+        assert_eq!(tx.to, vec![RECIPIENT.to_string()]);
+        let recipient = tx
+            .balance_changes
+            .iter()
+            .find(|change| change.account == RECIPIENT)
+            .unwrap();
+        assert_eq!((recipient.pre, recipient.post), (2000000, 3000000));
+        assert!(
+            tx.balance_changes
+                .iter()
+                .any(|change| change.account == LOOKUP_READONLY)
+        );
+        assert_eq!(tx.program_id.as_deref(), Some(MEMO_PROGRAM_IDS[1]));
+        assert_eq!(
+            tx.memo_signers,
+            vec![PAYER.to_string(), COSIGNER.to_string()]
+        );
+        assert_eq!(tx.memos, vec![MEMO.to_string()]);
+        let title_content = parse_raw_data(&tx).unwrap();
+        assert_eq!(title_content.title, "Lake");
+    }
+
+    #[tokio::test]
+    async fn inner_memo_is_read_before_later_instructions() {
+        let tx = fixture(include_str!("fixtures/inner_memo.json")).await;
+        assert_eq!(tx.from, PAYER);
+        // sent via CPI from the first instruction, so it comes before the top-level memo;
+        // the memo that is not UTF-8 is skipped
+        assert_eq!(tx.memos, vec![MEMO.to_string(), "second".to_string()]);
+        assert_eq!(
+            tx.raw_data,
+            Some(serde_json::Value::String(MEMO.to_string()))
+        );
+        assert_eq!(tx.program_id.as_deref(), Some(MEMO_PROGRAM_IDS[0]));
+        assert_eq!(tx.memo_signers, vec![PAYER.to_string()]);
+        assert!(parse_op_memo(&tx).is_none());
+        assert!(parse_raw_data(&tx).is_ok());
+    }
+
+    /// `getTransaction` output for a transaction signed with solana-sdk and
+    /// encoded by solana-transaction-status the way an RPC node encodes it: a
+    /// wallet memo without signers, then the CHRO memo signed by the payer.
+    #[tokio::test]
+    async fn first_chro_memo_is_indexed() {
+        let wallet_payer = "sXubZSsJDCBeGccrAKMfjLCsuK9kPkqcnN8LBzht9Yr";
+        let tx = fixture(include_str!("fixtures/wallet_memo_before_chro.json")).await;
+        assert_eq!(tx.encoding_type, "Base64");
+        assert!(tx.success);
+        assert_eq!(tx.slot, Some(331204518));
+        assert_eq!(tx.block_time, Some(1760812345));
+        assert_eq!(tx.from, wallet_payer);
+        assert_eq!(tx.memos.len(), 2);
+        assert_eq!(tx.memos[0], "gm");
+        assert_eq!(
+            tx.raw_data,
+            Some(serde_json::Value::String(tx.memos[1].clone()))
+        );
+        assert_eq!(tx.memo_signers, vec![wallet_payer.to_string()]);
+        let title_content = parse_raw_data(&tx).unwrap();
+        assert_eq!(title_content.title, "Harbour at dusk");
+        assert_eq!(
+            title_content.uri,
+            "https://arweave.net/2u4cU3bJ4a2F7d0kJk3bq0bq9w3Xy0d3hZ9YqkVv0hM"
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_transaction_is_not_indexed() {
+        let mut json: serde_json::Value =
+            serde_json::from_str(include_str!("fixtures/inner_memo.json")).unwrap();
+        json["meta"]["err"] = serde_json::json!({"InstructionError": [0, "InvalidArgument"]});
+        json["meta"]["status"] =
+            serde_json::json!({"Err": {"InstructionError": [0, "InvalidArgument"]}});
+        let tx = fixture(&json.to_string()).await;
+        assert!(!tx.success);
+        assert!(parse_raw_data(&tx).is_err());
+    }
+}
@@ -0,0 +1,271 @@
+use crate::conf::config::Config;
+
+use super::solana::ProcessedTransaction;
+
+/// Why an on-chain memory was not accepted.
+#[derive(Debug, thiserror::Error, PartialEq)]
+pub enum RejectReason {
+    #[error("transaction did not succeed")]
+    TransactionFailed,
+    #[error("transaction carries no memo")]
+    MissingMemo,
+    #[error("memo is not signed by any account")]
+    UnsignedMemo,
+    #[error("fee payer {fee_payer} did not sign the memo (signers: {signers:?})")]
+    SignerMismatch {
+        fee_payer: String,
+        signers: Vec<String>,
+    },
+    #[error("no payment to treasury {treasury} found")]
+    MissingPayment { treasury: String },
+    #[error("payment of {received} to treasury is below the required {expected}")]
+    InsufficientPayment { expected: u64, received: u64 },
+}
+
+/// Checks applied to a transaction before its memo is accepted as a memory.
+#[derive(Debug, Clone, Default)]
+pub struct VerifyPolicy {
+    pub require_memo_signer: bool,
+    pub treasury: Option<String>,
+    /// SPL token mint of the payment, `None` for SOL.
+    pub payment_mint: Option<String>,
+    /// Required payment in lamports or base token units.
+    pub payment_amount: u64,
+}
+
+impl VerifyPolicy {
+    pub fn from_config(config: &Config) -> Self {
+        VerifyPolicy {
+            require_memo_signer: config.require_memo_signer,
+            treasury: config.payment_treasury.clone(),
+            payment_mint: config.payment_mint.clone(),
+            payment_amount: config.payment_amount,
+        }
+    }
+}
+
+/// The author of an accepted memory.
+#[derive(Debug, Clone)]
+pub struct VerifiedAuthor {
+    pub fee_payer: String,
+    pub memo_signers: Vec<String>,
+    pub paid: u64,
+}
+
+pub fn verify_transaction(
+    transaction: &ProcessedTransaction,
+    policy: &VerifyPolicy,
+) -> Result<VerifiedAuthor, RejectReason> {
+    if !transaction.success {
+        return Err(RejectReason::TransactionFailed);
+    }
+    if transaction.raw_data.is_none() {
+        return Err(RejectReason::MissingMemo);
+    }
+    if policy.require_memo_signer {
+        if transaction.memo_signers.is_empty() {
+            return Err(RejectReason::UnsignedMemo);
+        }
+        if !transaction.memo_signers.contains(&transaction.from) {
+            return Err(RejectReason::SignerMismatch {
+                fee_payer: transaction.from.clone(),
+                signers: transaction.memo_signers.clone(),
+            });
+        }
+    }
+    let paid = match &policy.treasury {
+        Some(treasury) => {
+            let received = received_payment(transaction, treasury, policy.payment_mint.as_deref())
+                .ok_or_else(|| RejectReason::MissingPayment {
+                    treasury: treasury.clone(),
+                })?;
+            if received < policy.payment_amount {
+                return Err(RejectReason::InsufficientPayment {
+                    expected: policy.payment_amount,
+                    received,
+                });
+            }
+            received
+        }
+        None => 0,
+    };
+    Ok(VerifiedAuthor {
+        fee_payer: transaction.from.clone(),
+        memo_signers: transaction.memo_signers.clone(),
+        paid,
+    })
+}
+
+/// Sums what the treasury gained in this transaction. For SPL payments the treasury
+/// may be either the token account itself or the wallet owning it.
+fn received_payment(
+    transaction: &ProcessedTransaction,
+    treasury: &str,
+    mint: Option<&str>,
+) -> Option<u64> {
+    let mut found = false;
+    let mut received = 0u64;
+    for change in &transaction.balance_changes {
+        if change.mint.as_deref() != mint {
+            continue;
+        }
+        let is_treasury = change.account == treasury
+            || (mint.is_some() && change.owner.as_deref() == Some(treasury));
+        if is_treasury && change.post > change.pre {
+            found = true;
+            received = received.saturating_add(change.post - change.pre);
+        }
+    }
+    found.then_some(received)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solana::solana::BalanceChange;
+
+    const PAYER: &str = "payer";
+    const TREASURY: &str = "treasury";
+    const MINT: &str = "mint";
+
+    fn transaction() -> ProcessedTransaction {
+        ProcessedTransaction {
+            signature: "signature".to_string(),
+            slot: Some(1),
+            block_time: Some(1),
+            program_id: None,
+            success: true,
+            logs: Vec::new(),
+            encoding_type: "Base64".to_string(),
+            raw_data: Some(serde_json::Value::String("memo".to_string())),
+            from: PAYER.to_string(),
+            to: Vec::new(),
+            memos: vec!["memo".to_string()],
+            memo_signers: vec![PAYER.to_string()],
+            balance_changes: Vec::new(),
+        }
+    }
+
+    fn change(
+        account: &str,
+        mint: Option<&str>,
+        owner: Option<&str>,
+        pre: u64,
+        post: u64,
+    ) -> BalanceChange {
+        BalanceChange {
+            account: account.to_string(),
+            mint: mint.map(str::to_string),
+            owner: owner.map(str::to_string),
+            pre,
+            post,
+        }
+    }
+
+    fn policy(mint: Option<&str>, amount: u64) -> VerifyPolicy {
+        VerifyPolicy {
+            require_memo_signer: true,
+            treasury: Some(TREASURY.to_string()),
+            payment_mint: mint.map(str::to_string),
+            payment_amount: amount,
+        }
+    }
+
+    #[test]
+    fn failed_transaction() {
+        let mut tx = transaction();
+        tx.success = false;
+        assert_eq!(
+            verify_transaction(&tx, &VerifyPolicy::default()).unwrap_err(),
+            RejectReason::TransactionFailed
+        );
+    }
+
+    #[test]
+    fn missing_memo() {
+        let mut tx = transaction();
+        tx.raw_data = None;
+        assert_eq!(
+            verify_transaction(&tx, &VerifyPolicy::default()).unwrap_err(),
+            RejectReason::MissingMemo
+        );
+    }
+
+    #[test]
+    fn unsigned_memo() {
+        let mut tx = transaction();
+        tx.memo_signers.clear();
+        assert_eq!(
+            verify_transaction(&tx, &policy(None, 0)).unwrap_err(),
+            RejectReason::UnsignedMemo
+        );
+        // only enforced when required
+        assert!(verify_transaction(&tx, &VerifyPolicy::default()).is_ok());
+    }
+
+    #[test]
+    fn signer_mismatch() {
+        let mut tx = transaction();
+        tx.memo_signers = vec!["someone".to_string()];
+        assert_eq!(
+            verify_transaction(&tx, &policy(None, 0)).unwrap_err(),
+            RejectReason::SignerMismatch {
+                fee_payer: PAYER.to_string(),
+                signers: vec!["someone".to_string()],
+            }
+        );
+    }
+
+    #[test]
+    fn missing_payment() {
+        let mut tx = transaction();
+        // the treasury lost lamports and was paid in another token
+        tx.balance_changes = vec![
+            change(TREASURY, None, None, 10, 5),
+            change("token_account", Some("other"), Some(TREASURY), 0, 100),
+        ];
+        assert_eq!(
+            verify_transaction(&tx, &policy(None, 1)).unwrap_err(),
+            RejectReason::MissingPayment {
+                treasury: TREASURY.to_string(),
+            }
+        );
+        assert_eq!(
+            verify_transaction(&tx, &policy(Some(MINT), 1)).unwrap_err(),
+            RejectReason::MissingPayment {
+                treasury: TREASURY.to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn insufficient_payment() {
+        let mut tx = transaction();
+        tx.balance_changes = vec![change(TREASURY, None, None, 10, 50)];
+        assert_eq!(
+            verify_transaction(&tx, &policy(None, 100)).unwrap_err(),
+            RejectReason::InsufficientPayment {
+                expected: 100,
+                received: 40,
+            }
+        );
+    }
+
+    #[test]
+    fn accepts_sol_and_token_payments() {
+        let mut tx = transaction();
+        tx.balance_changes = vec![change(TREASURY, None, None, 10, 110)];
+        let author = verify_transaction(&tx, &policy(None, 100)).unwrap();
+        assert_eq!(author.fee_payer, PAYER);
+        assert_eq!(author.paid, 100);
+
+        // a token account owned by the treasury counts as the treasury
+        tx.balance_changes = vec![change("token_account", Some(MINT), Some(TREASURY), 0, 7)];
+        assert_eq!(
+            verify_transaction(&tx, &policy(Some(MINT), 7))
+                .unwrap()
+                .paid,
+            7
+        );
+    }
+}
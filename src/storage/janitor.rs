@@ -0,0 +1,327 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, UNIX_EPOCH};
+
+use log::{error, info, warn};
+use once_cell::sync::Lazy;
+
+use crate::{
+    api::publish_api::published_ids,
+    conf::config::Config,
+    render::variant::VARIANT_DIR,
+    solana::nft::minted_ids,
+    store::store::{read_store, write_store},
+    upload::{resumable, video::HLS_DIR},
+    utils::metrics,
+};
+
+use super::storage::output_storage_from_config;
+
+/// Last time each file was served, by the name `file_api` knows it under.
+/// Lost on restart, when modification times take over.
+static LAST_ACCESS: Lazy<Mutex<HashMap<String, i64>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Records that a file (or the HLS folder of a video id) was just served.
+pub fn touch(name: &str) {
+    let now = chrono::Utc::now().timestamp();
+    LAST_ACCESS.lock().unwrap().insert(name.to_string(), now);
+}
+
+/// A file, or a whole HLS folder, the janitor may delete.
+#[derive(Debug)]
+struct Entry {
+    path: PathBuf,
+    name: String,
+    size: u64,
+    last_used: i64,
+    /// Variants can be made again, so they expire even when their original
+    /// is pinned.
+    variant: bool,
+}
+
+/// What one pass did.
+#[derive(Debug)]
+pub struct Sweep {
+    pub deleted: u64,
+    pub reclaimed: u64,
+    pub remaining: u64,
+    pub pinned: u64,
+}
+
+pub(crate) fn modified_secs(metadata: &fs::Metadata) -> i64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs() as i64)
+}
+
+fn file_entry(path: PathBuf, metadata: &fs::Metadata) -> Option<Entry> {
+    let name = path.file_name()?.to_str()?.to_string();
+    Some(Entry {
+        name,
+        size: metadata.len(),
+        last_used: modified_secs(metadata),
+        path,
+        variant: false,
+    })
+}
+
+/// Sums an HLS folder; it counts as used when its newest file was written.
+fn dir_entry(path: PathBuf) -> std::io::Result<Option<Entry>> {
+    let Some(name) = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .map(str::to_string)
+    else {
+        return Ok(None);
+    };
+    let (mut size, mut last_used) = (0, 0);
+    for entry in fs::read_dir(&path)? {
+        let metadata = entry?.metadata()?;
+        size += metadata.len();
+        last_used = last_used.max(modified_secs(&metadata));
+    }
+    Ok(Some(Entry {
+        path,
+        name,
+        size,
+        last_used,
+        variant: false,
+    }))
+}
+
+/// Top-level files, variants and HLS folders of the temp directory.
+fn scan(root: &Path) -> std::io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(root)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_file() {
+            entries.extend(file_entry(entry.path(), &metadata));
+        } else if entry.file_name() == VARIANT_DIR {
+            for variant in fs::read_dir(entry.path())? {
+                let variant = variant?;
+                let metadata = variant.metadata()?;
+                if metadata.is_file() {
+                    entries.extend(file_entry(variant.path(), &metadata).map(|entry| Entry {
+                        variant: true,
+                        ..entry
+                    }));
+                }
+            }
+        } else if entry.file_name() == HLS_DIR {
+            for video in fs::read_dir(entry.path())? {
+                let video = video?;
+                if video.metadata()?.is_dir() {
+                    entries.extend(dir_entry(video.path())?);
+                }
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// Files belong to the id before their first `.` or `_`: task and upload
+/// images, their variants, posters, manifests, renders and HLS folders.
+/// Stored playlists and segments belong to their folder's id.
+fn is_pinned(name: &str, pinned: &HashSet<String>) -> bool {
+    let name = name
+        .strip_prefix(HLS_DIR)
+        .and_then(|rest| rest.strip_prefix('/'))
+        .map_or(name, |rest| rest.split('/').next().unwrap_or(rest));
+    pinned.contains(name)
+        || name
+            .match_indices(['.', '_'])
+            .any(|(i, _)| pinned.contains(&name[..i]))
+}
+
+/// Ids of files shown by a memory or published for a memo or mint.
+async fn pinned_ids() -> HashSet<String> {
+    let id = |url: &str| {
+        let name = url.rsplit('/').next().unwrap_or(url);
+        name.split_once('.').map_or(name, |(id, _)| id).to_string()
+    };
+    let mut pinned = read_store(|store| {
+        store
+            .memories
+            .values()
+            .flat_map(|m| {
+                let poster = m.video.as_ref().and_then(|v| v.poster_url.as_deref());
+                std::iter::once(m.image.as_str()).chain(poster)
+            })
+            .map(id)
+            .collect::<HashSet<_>>()
+    })
+    .await;
+    pinned.extend(published_ids().await);
+    pinned.extend(minted_ids().await);
+    pinned
+}
+
+/// Deletes unpinned files unused for longer than the TTL, then evicts the
+/// least recently used ones until the directory fits the quota. With a remote
+/// output backend the directory is only a cache; expired outputs are deleted
+/// from the backend too.
+pub async fn sweep(config: &Config) -> anyhow::Result<Sweep> {
+    let pinned = pinned_ids().await;
+    let root = PathBuf::from(&config.img_tmp_path);
+    let mut entries = tokio::task::spawn_blocking(move || scan(&root)).await??;
+    {
+        let mut last_access = LAST_ACCESS.lock().unwrap();
+        let names = entries
+            .iter()
+            .map(|e| e.name.as_str())
+            .collect::<HashSet<_>>();
+        last_access.retain(|name, _| names.contains(name.as_str()));
+        for entry in entries.iter_mut() {
+            if let Some(at) = last_access.get(&entry.name) {
+                entry.last_used = entry.last_used.max(*at);
+            }
+        }
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    let (kept, mut candidates): (Vec<_>, Vec<_>) = entries
+        .into_iter()
+        .partition(|e| !e.variant && is_pinned(&e.name, &pinned));
+    let pinned_bytes = kept.iter().map(|e| e.size).sum::<u64>();
+    candidates.sort_by_key(|e| e.last_used);
+    let mut total = pinned_bytes + candidates.iter().map(|e| e.size).sum::<u64>();
+    let mut doomed = Vec::new();
+    for entry in candidates {
+        let expired =
+            config.temp_ttl_secs > 0 && now - entry.last_used > config.temp_ttl_secs as i64;
+        let over_quota = config.temp_quota_bytes > 0 && total > config.temp_quota_bytes;
+        if expired || over_quota {
+            total -= entry.size;
+            doomed.push(entry);
+        }
+    }
+    if config.temp_quota_bytes > 0 && total > config.temp_quota_bytes {
+        warn!(
+            "pinned files alone take {} bytes, over the {} byte quota",
+            pinned_bytes, config.temp_quota_bytes
+        );
+    }
+
+    let doomed_bytes = doomed.iter().map(|e| e.size).sum::<u64>();
+    let deleted = tokio::task::spawn_blocking(move || {
+        doomed
+            .into_iter()
+            .filter(|entry| {
+                let removed = if entry.path.is_dir() {
+                    fs::remove_dir_all(&entry.path)
+                } else {
+                    fs::remove_file(&entry.path)
+                };
+                removed
+                    .inspect_err(|e| error!("delete {} failed: {}", entry.path.display(), e))
+                    .is_ok()
+            })
+            .collect::<Vec<_>>()
+    })
+    .await?;
+    let local_reclaimed = deleted.iter().map(|e| e.size).sum::<u64>();
+    let mut reclaimed = local_reclaimed;
+    let mut names = deleted
+        .iter()
+        .map(|e| e.name.clone())
+        .collect::<HashSet<_>>();
+    let storage = output_storage_from_config(config)?;
+    if config.output_backend != "local" && config.temp_ttl_secs > 0 {
+        for object in storage.list("").await? {
+            let last_used = LAST_ACCESS
+                .lock()
+                .unwrap()
+                .get(&object.key)
+                .map_or(object.modified, |at| object.modified.max(*at));
+            if is_pinned(&object.key, &pinned) || now - last_used <= config.temp_ttl_secs as i64 {
+                continue;
+            }
+            match storage.delete(&object.key).await {
+                Ok(()) => {
+                    reclaimed += object.size;
+                    names.insert(object.key);
+                }
+                Err(e) => error!("delete {} failed: {}", object.key, e),
+            }
+        }
+    }
+
+    LAST_ACCESS
+        .lock()
+        .unwrap()
+        .retain(|name, _| !names.contains(name));
+    // an upload whose file is gone everywhere would otherwise be returned as
+    // a duplicate
+    let uploads = read_store(|store| {
+        store
+            .uploads
+            .values()
+            .filter(|u| names.contains(&u.file_name))
+            .map(|u| (u.id.clone(), u.file_name.clone()))
+            .collect::<Vec<_>>()
+    })
+    .await;
+    let mut orphaned = HashSet::new();
+    for (id, file_name) in uploads {
+        if !Path::new(&config.img_tmp_path).join(&file_name).exists()
+            && storage.stat(&file_name).await?.is_none()
+        {
+            orphaned.insert(id);
+        }
+    }
+    if !orphaned.is_empty() {
+        write_store(|store| store.uploads.retain(|id, _| !orphaned.contains(id))).await;
+    }
+    Ok(Sweep {
+        deleted: names.len() as u64,
+        reclaimed,
+        // files that failed to delete are still there
+        remaining: total + doomed_bytes - local_reclaimed,
+        pinned: pinned_bytes,
+    })
+}
+
+/// Sweeps the temp directory every `JANITOR_INTERVAL_SECS`.
+pub async fn run_janitor(config: Config) {
+    if config.janitor_interval_secs == 0 {
+        return;
+    }
+    let mut interval = tokio::time::interval(Duration::from_secs(config.janitor_interval_secs));
+    loop {
+        interval.tick().await;
+        match sweep(&config).await {
+            Ok(sweep) => {
+                metrics::JANITOR_RUNS.add(1);
+                metrics::JANITOR_FILES_DELETED.add(sweep.deleted);
+                metrics::JANITOR_BYTES_RECLAIMED.add(sweep.reclaimed);
+                metrics::TEMP_DIR_BYTES.set(sweep.remaining);
+                metrics::TEMP_DIR_PINNED_BYTES.set(sweep.pinned);
+                if sweep.deleted > 0 {
+                    info!(
+                        "janitor deleted {} files, reclaimed {} bytes, {} bytes left ({} pinned)",
+                        sweep.deleted, sweep.reclaimed, sweep.remaining, sweep.pinned
+                    );
+                }
+            }
+            Err(e) => error!("janitor sweep failed: {}", e),
+        }
+        match resumable::expire(&config).await {
+            Ok(0) => {}
+            Ok(expired) => info!("janitor dropped {} abandoned resumable uploads", expired),
+            Err(e) => error!("expire resumable uploads failed: {}", e),
+        }
+        // changes of a failed store write are kept only in memory until a
+        // write succeeds, which may otherwise not happen for a while
+        if metrics::STORE_UNPERSISTED.get() > 0 {
+            write_store(|_| ()).await;
+            if metrics::STORE_UNPERSISTED.get() == 0 {
+                info!("janitor persisted the store after a failed write");
+            }
+        }
+    }
+}
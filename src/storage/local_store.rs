@@ -0,0 +1,221 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use async_trait::async_trait;
+
+use super::storage::{ObjectMeta, Storage, validate_key};
+
+/// Suffix of files still being written.
+const PART_SUFFIX: &str = ".part";
+
+/// Keeps objects as plain files below a root directory.
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: &str) -> Self {
+        LocalStorage {
+            root: PathBuf::from(root),
+        }
+    }
+
+    fn key_path(&self, key: &str) -> anyhow::Result<PathBuf> {
+        Ok(self.root.join(validate_key(key)?))
+    }
+}
+
+fn object_meta(key: String, metadata: &std::fs::Metadata) -> ObjectMeta {
+    ObjectMeta {
+        key,
+        size: metadata.len(),
+        modified: metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_secs() as i64),
+    }
+}
+
+/// Every file below `dir`, keyed by its path relative to `root`.
+fn walk(root: &Path, dir: &Path, objects: &mut Vec<ObjectMeta>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        let path = entry.path();
+        if metadata.is_dir() {
+            walk(root, &path, objects)?;
+        } else if metadata.is_file() && !path.to_string_lossy().ends_with(PART_SUFFIX) {
+            let key = path
+                .strip_prefix(root)
+                .unwrap_or(&path)
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            objects.push(object_meta(key, &metadata));
+        }
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn put(&self, key: &str, data: Vec<u8>, _content_type: &str) -> anyhow::Result<()> {
+        let path = self.key_path(key)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        // write to a sibling first so readers never see a partial object
+        let mut tmp_path = path.clone().into_os_string();
+        tmp_path.push(format!(".{}{}", uuid::Uuid::new_v4().simple(), PART_SUFFIX));
+        tokio::fs::write(&tmp_path, data).await?;
+        tokio::fs::rename(&tmp_path, &path).await?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let path = self.key_path(key)?;
+        match tokio::fs::read(&path).await {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn stat(&self, key: &str) -> anyhow::Result<Option<ObjectMeta>> {
+        let path = self.key_path(key)?;
+        match tokio::fs::metadata(&path).await {
+            Ok(metadata) if metadata.is_file() => Ok(Some(object_meta(key.to_string(), &metadata))),
+            Ok(_) => Ok(None),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> anyhow::Result<()> {
+        let path = self.key_path(key)?;
+        match tokio::fs::remove_file(&path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn list(&self, prefix: &str) -> anyhow::Result<Vec<ObjectMeta>> {
+        let root = self.root.clone();
+        let mut objects = tokio::task::spawn_blocking(move || {
+            let mut objects = Vec::new();
+            match walk(&root, &root, &mut objects) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+                _ => Ok(objects),
+            }
+        })
+        .await??;
+        objects.retain(|o| o.key.starts_with(prefix));
+        Ok(objects)
+    }
+
+    /// Local files are served by `file_api`.
+    async fn presign(&self, _key: &str, _expires_in: Duration) -> anyhow::Result<Option<String>> {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A store in a fresh directory, removed when dropped.
+    struct TempStore {
+        storage: LocalStorage,
+    }
+
+    impl TempStore {
+        fn new() -> Self {
+            let root = std::env::temp_dir().join(format!(
+                "chroniq-local-store-{}",
+                uuid::Uuid::new_v4().simple()
+            ));
+            TempStore {
+                storage: LocalStorage::new(&root.to_string_lossy()),
+            }
+        }
+    }
+
+    impl Drop for TempStore {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.storage.root);
+        }
+    }
+
+    fn keys(objects: Vec<ObjectMeta>) -> Vec<String> {
+        let mut keys = objects.into_iter().map(|o| o.key).collect::<Vec<_>>();
+        keys.sort();
+        keys
+    }
+
+    #[tokio::test]
+    async fn objects_round_trip() {
+        let store = TempStore::new();
+        let storage = &store.storage;
+        storage
+            .put("a.png", b"png".to_vec(), "image/png")
+            .await
+            .unwrap();
+        storage
+            .put("hls/abc/master.m3u8", b"#EXTM3U".to_vec(), "")
+            .await
+            .unwrap();
+
+        assert_eq!(storage.get("a.png").await.unwrap(), Some(b"png".to_vec()));
+        assert_eq!(storage.stat("a.png").await.unwrap().unwrap().size, 3);
+        assert!(storage.get("b.png").await.unwrap().is_none());
+        assert!(storage.stat("b.png").await.unwrap().is_none());
+        // a folder is not an object
+        assert!(storage.stat("hls").await.unwrap().is_none());
+
+        assert_eq!(
+            keys(storage.list("").await.unwrap()),
+            ["a.png", "hls/abc/master.m3u8"]
+        );
+        assert_eq!(
+            keys(storage.list("hls/").await.unwrap()),
+            ["hls/abc/master.m3u8"]
+        );
+
+        storage.delete("a.png").await.unwrap();
+        assert!(storage.get("a.png").await.unwrap().is_none());
+        storage.delete("a.png").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn partial_files_are_not_listed() {
+        let store = TempStore::new();
+        let storage = &store.storage;
+        storage
+            .put("a.png", b"png".to_vec(), "image/png")
+            .await
+            .unwrap();
+        std::fs::write(storage.root.join("b.png.1234.part"), b"p").unwrap();
+        assert_eq!(keys(storage.list("").await.unwrap()), ["a.png"]);
+    }
+
+    #[tokio::test]
+    async fn missing_root_lists_nothing() {
+        let store = TempStore::new();
+        assert!(store.storage.list("").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn keys_may_not_leave_the_root() {
+        let store = TempStore::new();
+        for key in ["", "../a.png", "/etc/passwd", "hls/../../a.png", "./a.png"] {
+            assert!(store.storage.get(key).await.is_err(), "{}", key);
+            assert!(
+                store.storage.put(key, Vec::new(), "").await.is_err(),
+                "{}",
+                key
+            );
+        }
+    }
+}
@@ -0,0 +1,242 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use log::info;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::redirect;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    conf::config::Config,
+    solana::solana::TitleContent,
+    store::{models::MirroredImage, store::write_store},
+    uploader::uploader::resolve_content_uri,
+};
+
+use super::storage::Storage;
+
+const MAX_REDIRECTS: usize = 5;
+
+/// Whether an address is reachable from the internet, i.e. not loopback,
+/// private, link-local or otherwise reserved.
+fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                // carrier-grade NAT and 0/8
+                || (a == 100 && (64..128).contains(&b))
+                || a == 0)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_ip(IpAddr::V4(ip)),
+            None => {
+                !(ip.is_unspecified()
+                    || ip.is_loopback()
+                    || ip.is_multicast()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local())
+            }
+        },
+    }
+}
+
+/// Hosts that may resolve to internal addresses: the configured gateways.
+fn trusted_hosts(config: &Config) -> Vec<String> {
+    [&config.ipfs_gateway, &config.arweave_gateway]
+        .iter()
+        .filter_map(|gateway| reqwest::Url::parse(gateway).ok())
+        .filter_map(|url| url.host_str().map(|host| host.to_string()))
+        .collect()
+}
+
+/// Refuses URLs that are not http(s) or name an internal address directly.
+/// Names are checked once resolved, by [`PublicResolver`].
+fn check_url(url: &reqwest::Url, trusted: &[String]) -> anyhow::Result<()> {
+    if !matches!(url.scheme(), "http" | "https") {
+        anyhow::bail!("{} is not an http(s) URL", url);
+    }
+    let host = url
+        .host_str()
+        .ok_or_else(|| anyhow::anyhow!("{} has no host", url))?;
+    if trusted.iter().any(|trusted| trusted == host) {
+        return Ok(());
+    }
+    if let Ok(ip) = host.trim_matches(['[', ']']).parse::<IpAddr>()
+        && !is_public_ip(ip)
+    {
+        anyhow::bail!("{} points to an internal address", url);
+    }
+    Ok(())
+}
+
+/// Resolves names like the system resolver, dropping internal addresses so
+/// neither the URL nor a redirect can reach them.
+struct PublicResolver {
+    trusted: Vec<String>,
+}
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let trusted = self.trusted.iter().any(|host| host == name.as_str());
+        Box::pin(async move {
+            let addrs = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| trusted || is_public_ip(addr.ip()))
+                .collect::<Vec<SocketAddr>>();
+            if addrs.is_empty() {
+                return Err(format!("{} resolves to no public address", name.as_str()).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+fn download_client(trusted: Vec<String>) -> anyhow::Result<reqwest::Client> {
+    let redirect_trusted = trusted.clone();
+    let policy = redirect::Policy::custom(move |attempt| {
+        if attempt.previous().len() >= MAX_REDIRECTS {
+            attempt.error("too many redirects")
+        } else if let Err(e) = check_url(attempt.url(), &redirect_trusted) {
+            attempt.error(e.to_string())
+        } else {
+            attempt.follow()
+        }
+    });
+    Ok(reqwest::Client::builder()
+        .redirect(policy)
+        .dns_resolver(Arc::new(PublicResolver { trusted }))
+        .build()?)
+}
+
+/// Downloads an image URL or content URI, refusing other content types and
+/// anything larger than `MIRROR_MAX_BYTES`. Only http(s) URLs on public
+/// addresses are fetched, apart from the configured gateways.
+pub async fn download_image(uri: &str, config: &Config) -> anyhow::Result<(Vec<u8>, String)> {
+    let max_bytes = config.mirror_max_bytes;
+    let url = reqwest::Url::parse(&resolve_content_uri(uri, config))?;
+    let trusted = trusted_hosts(config);
+    check_url(&url, &trusted)?;
+    let mut res = download_client(trusted)?
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?;
+    let content_type = res
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    if !content_type.starts_with("image/") {
+        return Err(anyhow::anyhow!("{} is not an image: {}", uri, content_type));
+    }
+    if res.content_length().unwrap_or(0) as usize > max_bytes {
+        return Err(anyhow::anyhow!("{} exceeds {} bytes", uri, max_bytes));
+    }
+    let mut data = Vec::new();
+    while let Some(chunk) = res.chunk().await? {
+        if data.len() + chunk.len() > max_bytes {
+            return Err(anyhow::anyhow!("{} exceeds {} bytes", uri, max_bytes));
+        }
+        data.extend_from_slice(&chunk);
+    }
+    Ok((data, content_type))
+}
+
+/// Downloads the `uri` of an indexed memory and stores it under its SHA-256 so
+/// the image stays available even if the original URL dies.
+pub async fn mirror_memory_image(
+    store: Arc<dyn Storage>,
+    signature: &str,
+    title_content: &TitleContent,
+    config: &Config,
+) -> anyhow::Result<MirroredImage> {
+    let (data, content_type) = download_image(&title_content.uri, config).await?;
+    let sha256 = hex::encode(Sha256::digest(&data));
+    let size = data.len();
+    if store.stat(&sha256).await?.is_none() {
+        store.put(&sha256, data, &content_type).await?;
+    }
+    info!(
+        "mirrored {} as {} ({} bytes)",
+        title_content.uri, sha256, size
+    );
+
+    let mirrored = MirroredImage {
+        uri: title_content.uri.clone(),
+        sha256,
+        content_type,
+        size,
+    };
+    write_store(|store| {
+        store
+            .mirrored_images
+            .insert(signature.to_string(), mirrored.clone())
+    })
+    .await;
+    Ok(mirrored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(url: &str) -> reqwest::Url {
+        reqwest::Url::parse(url).unwrap()
+    }
+
+    #[test]
+    fn internal_addresses_are_refused() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fc00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public_ip(ip.parse().unwrap()), "{}", ip);
+        }
+        assert!(is_public_ip("93.184.216.34".parse().unwrap()));
+        assert!(is_public_ip("2606:2800:220:1::".parse().unwrap()));
+    }
+
+    #[test]
+    fn only_public_http_urls_pass() {
+        let trusted = vec!["127.0.0.1".to_string()];
+        assert!(check_url(&url("https://example.com/a.png"), &[]).is_ok());
+        assert!(check_url(&url("file:///etc/passwd"), &[]).is_err());
+        assert!(check_url(&url("ftp://example.com/a.png"), &[]).is_err());
+        assert!(check_url(&url("http://169.254.169.254/latest"), &[]).is_err());
+        assert!(check_url(&url("http://[::1]:8080/a.png"), &[]).is_err());
+        // a configured gateway may be local
+        assert!(check_url(&url("http://127.0.0.1:8080/ipfs/cid"), &trusted).is_ok());
+    }
+
+    #[tokio::test]
+    async fn names_resolving_to_internal_addresses_are_refused() {
+        let resolver = PublicResolver {
+            trusted: Vec::new(),
+        };
+        let name = "localhost".parse::<Name>().unwrap();
+        assert!(resolver.resolve(name).await.is_err());
+
+        let resolver = PublicResolver {
+            trusted: vec!["localhost".to_string()],
+        };
+        let name = "localhost".parse::<Name>().unwrap();
+        assert!(resolver.resolve(name).await.is_ok());
+    }
+}
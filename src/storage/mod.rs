@@ -0,0 +1,6 @@
+pub mod janitor;
+pub mod local_store;
+pub mod mirror;
+pub mod s3_store;
+#[allow(clippy::module_inception)]
+pub mod storage;
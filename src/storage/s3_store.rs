@@ -0,0 +1,548 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use reqwest::{Client, Method, StatusCode};
+use sha2::{Digest, Sha256};
+
+use crate::conf::config::Config;
+
+use super::storage::{ObjectMeta, Storage, validate_key};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// S3-compatible object storage addressed path-style (`{endpoint}/{bucket}/{key}`),
+/// which is what MinIO and most self-hosted gateways expect.
+pub struct S3Storage {
+    client: Client,
+    endpoint: String,
+    bucket: String,
+    region: String,
+    access_key: String,
+    secret_key: String,
+    /// Prepended to every key, so several stores can share a bucket.
+    prefix: String,
+}
+
+/// The date parts and credential scope of a SigV4 signature made now.
+struct Scope {
+    amz_date: String,
+    date: String,
+    scope: String,
+}
+
+impl S3Storage {
+    pub fn from_config(config: &Config, prefix: &str) -> anyhow::Result<Self> {
+        let required = |value: &Option<String>, name: &str| {
+            value
+                .clone()
+                .ok_or_else(|| anyhow::anyhow!("{} is required for the s3 backend", name))
+        };
+        Ok(S3Storage {
+            client: Client::new(),
+            endpoint: required(&config.s3_endpoint, "S3_ENDPOINT")?
+                .trim_end_matches('/')
+                .to_string(),
+            bucket: required(&config.s3_bucket, "S3_BUCKET")?,
+            region: config.s3_region.clone(),
+            access_key: required(&config.s3_access_key, "S3_ACCESS_KEY")?,
+            secret_key: required(&config.s3_secret_key, "S3_SECRET_KEY")?,
+            prefix: prefix.to_string(),
+        })
+    }
+
+    fn object_path(&self, key: &str) -> anyhow::Result<String> {
+        validate_key(key)?;
+        Ok(format!(
+            "/{}/{}",
+            uri_encode(&self.bucket, false),
+            uri_encode(&format!("{}{}", self.prefix, key), true)
+        ))
+    }
+
+    fn host(&self) -> &str {
+        self.endpoint.split("://").nth(1).unwrap_or(&self.endpoint)
+    }
+
+    fn scope(&self) -> Scope {
+        let now = chrono::Utc::now();
+        let date = now.format("%Y%m%d").to_string();
+        Scope {
+            amz_date: now.format("%Y%m%dT%H%M%SZ").to_string(),
+            scope: format!("{}/{}/s3/aws4_request", date, self.region),
+            date,
+        }
+    }
+
+    fn signature(&self, scope: &Scope, canonical_request: &str) -> String {
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            scope.amz_date,
+            scope.scope,
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+        hex::encode(hmac(
+            &signing_key(&self.secret_key, &scope.date, &self.region),
+            string_to_sign.as_bytes(),
+        ))
+    }
+
+    /// Sends a request signed with AWS Signature Version 4.
+    async fn send(
+        &self,
+        method: Method,
+        path: &str,
+        query: &[(&str, &str)],
+        body: Vec<u8>,
+        content_type: Option<&str>,
+    ) -> anyhow::Result<reqwest::Response> {
+        let scope = self.scope();
+        let payload_hash = hex::encode(Sha256::digest(&body));
+        let query = canonical_query(query);
+
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+        let canonical_request = format!(
+            "{}\n{}\n{}\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            method,
+            path,
+            query,
+            self.host(),
+            payload_hash,
+            scope.amz_date,
+            signed_headers,
+            payload_hash
+        );
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.access_key,
+            scope.scope,
+            signed_headers,
+            self.signature(&scope, &canonical_request)
+        );
+
+        let mut url = format!("{}{}", self.endpoint, path);
+        if !query.is_empty() {
+            url = format!("{}?{}", url, query);
+        }
+        let mut request = self
+            .client
+            .request(method, url)
+            .header("x-amz-date", scope.amz_date)
+            .header("x-amz-content-sha256", payload_hash)
+            .header("authorization", authorization);
+        if let Some(content_type) = content_type {
+            request = request.header("content-type", content_type);
+        }
+        Ok(request.body(body).send().await?)
+    }
+}
+
+#[async_trait]
+impl Storage for S3Storage {
+    async fn put(&self, key: &str, data: Vec<u8>, content_type: &str) -> anyhow::Result<()> {
+        let path = self.object_path(key)?;
+        let res = self
+            .send(Method::PUT, &path, &[], data, Some(content_type))
+            .await?;
+        if !res.status().is_success() {
+            return Err(anyhow::anyhow!(
+                "s3 put {} failed: {} {}",
+                key,
+                res.status(),
+                res.text().await.unwrap_or_default()
+            ));
+        }
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let path = self.object_path(key)?;
+        let res = self.send(Method::GET, &path, &[], Vec::new(), None).await?;
+        match res.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => Ok(Some(res.bytes().await?.to_vec())),
+            status => Err(anyhow::anyhow!("s3 get {} failed: {}", key, status)),
+        }
+    }
+
+    async fn stat(&self, key: &str) -> anyhow::Result<Option<ObjectMeta>> {
+        let path = self.object_path(key)?;
+        let res = self
+            .send(Method::HEAD, &path, &[], Vec::new(), None)
+            .await?;
+        match res.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => {
+                let header = |name: &str| {
+                    res.headers()
+                        .get(name)
+                        .and_then(|v| v.to_str().ok())
+                        .map(str::to_string)
+                };
+                Ok(Some(ObjectMeta {
+                    key: key.to_string(),
+                    size: header("content-length")
+                        .and_then(|v| v.parse().ok())
+                        .unwrap_or_default(),
+                    modified: header("last-modified")
+                        .and_then(|v| chrono::DateTime::parse_from_rfc2822(&v).ok())
+                        .map_or(0, |t| t.timestamp()),
+                }))
+            }
+            status => Err(anyhow::anyhow!("s3 head {} failed: {}", key, status)),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> anyhow::Result<()> {
+        let path = self.object_path(key)?;
+        let res = self
+            .send(Method::DELETE, &path, &[], Vec::new(), None)
+            .await?;
+        match res.status() {
+            StatusCode::NOT_FOUND => Ok(()),
+            status if status.is_success() => Ok(()),
+            status => Err(anyhow::anyhow!("s3 delete {} failed: {}", key, status)),
+        }
+    }
+
+    /// Pages through ListObjectsV2.
+    async fn list(&self, prefix: &str) -> anyhow::Result<Vec<ObjectMeta>> {
+        let path = format!("/{}", uri_encode(&self.bucket, false));
+        let full_prefix = format!("{}{}", self.prefix, prefix);
+        let mut objects = Vec::new();
+        let mut token = None::<String>;
+        loop {
+            let mut query = vec![("list-type", "2"), ("prefix", full_prefix.as_str())];
+            if let Some(token) = &token {
+                query.push(("continuation-token", token));
+            }
+            let res = self
+                .send(Method::GET, &path, &query, Vec::new(), None)
+                .await?;
+            if !res.status().is_success() {
+                return Err(anyhow::anyhow!(
+                    "s3 list {} failed: {}",
+                    prefix,
+                    res.status()
+                ));
+            }
+            let xml = res.text().await?;
+            for contents in xml_elements(&xml, "Contents") {
+                let field = |tag| xml_elements(contents, tag).first().map(|v| unescape_xml(v));
+                let Some(key) = field("Key") else {
+                    continue;
+                };
+                objects.push(ObjectMeta {
+                    key: key.strip_prefix(&self.prefix).unwrap_or(&key).to_string(),
+                    size: field("Size")
+                        .and_then(|v| v.parse().ok())
+                        .unwrap_or_default(),
+                    modified: field("LastModified")
+                        .and_then(|v| chrono::DateTime::parse_from_rfc3339(&v).ok())
+                        .map_or(0, |t| t.timestamp()),
+                });
+            }
+            token = xml_elements(&xml, "NextContinuationToken")
+                .first()
+                .map(|v| unescape_xml(v));
+            if xml_elements(&xml, "IsTruncated").first() != Some(&"true") || token.is_none() {
+                return Ok(objects);
+            }
+        }
+    }
+
+    /// A SigV4 query-string signed GET URL.
+    async fn presign(&self, key: &str, expires_in: Duration) -> anyhow::Result<Option<String>> {
+        let path = self.object_path(key)?;
+        let scope = self.scope();
+        let credential = format!("{}/{}", self.access_key, scope.scope);
+        let expires = expires_in.as_secs().clamp(1, 604800).to_string();
+        let query = canonical_query(&[
+            ("X-Amz-Algorithm", "AWS4-HMAC-SHA256"),
+            ("X-Amz-Credential", &credential),
+            ("X-Amz-Date", &scope.amz_date),
+            ("X-Amz-Expires", &expires),
+            ("X-Amz-SignedHeaders", "host"),
+        ]);
+        let canonical_request = format!(
+            "GET\n{}\n{}\nhost:{}\n\nhost\nUNSIGNED-PAYLOAD",
+            path,
+            query,
+            self.host()
+        );
+        Ok(Some(format!(
+            "{}{}?{}&X-Amz-Signature={}",
+            self.endpoint,
+            path,
+            query,
+            self.signature(&scope, &canonical_request)
+        )))
+    }
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn signing_key(secret_key: &str, date: &str, region: &str) -> Vec<u8> {
+    let date_key = hmac(format!("AWS4{}", secret_key).as_bytes(), date.as_bytes());
+    let region_key = hmac(&date_key, region.as_bytes());
+    let service_key = hmac(&region_key, b"s3");
+    hmac(&service_key, b"aws4_request")
+}
+
+/// Percent-encodes everything but the RFC 3986 unreserved characters, as SigV4 requires.
+fn uri_encode(input: &str, keep_slash: bool) -> String {
+    let mut encoded = String::with_capacity(input.len());
+    for byte in input.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            b'/' if keep_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Query parameters encoded and sorted the way SigV4 signs them.
+fn canonical_query(query: &[(&str, &str)]) -> String {
+    let mut pairs = query
+        .iter()
+        .map(|(k, v)| format!("{}={}", uri_encode(k, false), uri_encode(v, false)))
+        .collect::<Vec<_>>();
+    pairs.sort();
+    pairs.join("&")
+}
+
+/// The contents of each `<tag>` element. Enough for S3 listings, which
+/// never nest an element in one of the same name.
+fn xml_elements<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+    let (open, close) = (format!("<{}>", tag), format!("</{}>", tag));
+    let mut elements = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start + open.len()..];
+        let Some(end) = rest.find(&close) else {
+            break;
+        };
+        elements.push(&rest[..end]);
+        rest = &rest[end + close.len()..];
+    }
+    elements
+}
+
+fn unescape_xml(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+    use std::sync::{Arc, Mutex};
+
+    use actix_web::{App, HttpRequest, HttpResponse, HttpServer, web};
+
+    use super::*;
+
+    const BUCKET: &str = "bucket";
+    const LAST_MODIFIED: &str = "Mon, 19 Oct 2026 10:00:00 GMT";
+
+    /// Objects of the fake server by full key.
+    type Objects = Arc<Mutex<BTreeMap<String, Vec<u8>>>>;
+
+    /// Just enough of S3 for [`S3Storage`]: signed path-style object requests
+    /// and a ListObjectsV2 that returns one object per page.
+    async fn fake_s3(
+        req: HttpRequest,
+        query: web::Query<HashMap<String, String>>,
+        body: web::Bytes,
+        objects: web::Data<Objects>,
+    ) -> HttpResponse {
+        let signed = req
+            .headers()
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("AWS4-HMAC-SHA256 Credential=access/"));
+        let Some(path) = req.path().strip_prefix(&format!("/{}", BUCKET)) else {
+            return HttpResponse::NotFound().finish();
+        };
+        if !signed {
+            return HttpResponse::Forbidden().finish();
+        }
+        let mut objects = objects.lock().unwrap();
+        let key = path.trim_start_matches('/');
+        if key.is_empty() {
+            let prefix = query.get("prefix").cloned().unwrap_or_default();
+            let after = query.get("continuation-token");
+            let mut matching = objects
+                .iter()
+                .filter(|(key, _)| key.starts_with(&prefix))
+                .filter(|(key, _)| after.is_none_or(|after| *key > after));
+            let mut xml = String::from("<ListBucketResult>");
+            if let Some((key, data)) = matching.next() {
+                xml.push_str(&format!(
+                    "<Contents><Key>{}</Key><Size>{}</Size>\
+                     <LastModified>2026-10-19T10:00:00.000Z</LastModified></Contents>",
+                    key.replace('&', "&amp;"),
+                    data.len()
+                ));
+                if matching.next().is_some() {
+                    xml.push_str(&format!(
+                        "<IsTruncated>true</IsTruncated>\
+                         <NextContinuationToken>{}</NextContinuationToken>",
+                        key.replace('&', "&amp;")
+                    ));
+                }
+            }
+            xml.push_str("</ListBucketResult>");
+            return HttpResponse::Ok().content_type("application/xml").body(xml);
+        }
+        match req.method().as_str() {
+            "PUT" => {
+                objects.insert(key.to_string(), body.to_vec());
+                HttpResponse::Ok().finish()
+            }
+            "GET" | "HEAD" => match objects.get(key) {
+                Some(data) => HttpResponse::Ok()
+                    .insert_header(("last-modified", LAST_MODIFIED))
+                    .body(data.clone()),
+                None => HttpResponse::NotFound().finish(),
+            },
+            "DELETE" => {
+                objects.remove(key);
+                HttpResponse::NoContent().finish()
+            }
+            _ => HttpResponse::MethodNotAllowed().finish(),
+        }
+    }
+
+    /// Starts a fake server and a store on it that keeps its objects under
+    /// `out/`.
+    fn start() -> (S3Storage, Objects) {
+        let objects = Objects::default();
+        let data = web::Data::new(objects.clone());
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(data.clone())
+                .default_service(web::to(fake_s3))
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let addr = server.addrs()[0];
+        actix_web::rt::spawn(server.run());
+        let config = Config {
+            s3_endpoint: Some(format!("http://{}/", addr)),
+            s3_bucket: Some(BUCKET.to_string()),
+            s3_region: "us-east-1".to_string(),
+            s3_access_key: Some("access".to_string()),
+            s3_secret_key: Some("secret".to_string()),
+            ..Default::default()
+        };
+        (S3Storage::from_config(&config, "out/").unwrap(), objects)
+    }
+
+    #[test]
+    fn required_settings_are_checked() {
+        let error = S3Storage::from_config(&Config::default(), "")
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("S3_ENDPOINT"));
+    }
+
+    #[actix_web::test]
+    async fn objects_round_trip() {
+        let (storage, objects) = start();
+        storage
+            .put("a.png", b"png".to_vec(), "image/png")
+            .await
+            .unwrap();
+        assert!(objects.lock().unwrap().contains_key("out/a.png"));
+
+        assert_eq!(storage.get("a.png").await.unwrap(), Some(b"png".to_vec()));
+        let meta = storage.stat("a.png").await.unwrap().unwrap();
+        assert_eq!(meta.size, 3);
+        assert_eq!(
+            meta.modified,
+            chrono::DateTime::parse_from_rfc2822(LAST_MODIFIED)
+                .unwrap()
+                .timestamp()
+        );
+        assert!(storage.get("b.png").await.unwrap().is_none());
+        assert!(storage.stat("b.png").await.unwrap().is_none());
+
+        storage.delete("a.png").await.unwrap();
+        assert!(storage.get("a.png").await.unwrap().is_none());
+        storage.delete("a.png").await.unwrap();
+        assert!(storage.get("../a.png").await.is_err());
+    }
+
+    #[actix_web::test]
+    async fn listing_follows_continuation_tokens() {
+        let (storage, objects) = start();
+        for key in ["a.png", "hls/abc/720p_0000.ts", "hls/abc/master.m3u8"] {
+            storage.put(key, b"x".to_vec(), "").await.unwrap();
+        }
+        objects
+            .lock()
+            .unwrap()
+            .insert("other/b.png".to_string(), Vec::new());
+
+        let keys =
+            |objects: Vec<ObjectMeta>| objects.into_iter().map(|o| o.key).collect::<Vec<_>>();
+        assert_eq!(
+            keys(storage.list("").await.unwrap()),
+            ["a.png", "hls/abc/720p_0000.ts", "hls/abc/master.m3u8"]
+        );
+        let listed = storage.list("hls/").await.unwrap();
+        assert_eq!(listed.len(), 2);
+        assert_eq!(listed[0].size, 1);
+        assert_eq!(
+            listed[0].modified,
+            chrono::DateTime::parse_from_rfc3339("2026-10-19T10:00:00Z")
+                .unwrap()
+                .timestamp()
+        );
+    }
+
+    #[actix_web::test]
+    async fn presigned_urls_name_the_object() {
+        let (storage, _) = start();
+        let url = storage
+            .presign("a b.png", Duration::from_secs(30 * 86400))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(url.starts_with(&format!("{}/bucket/out/a%20b.png?", storage.endpoint)));
+        assert!(url.contains("X-Amz-Credential=access%2F"));
+        // a week at most
+        assert!(url.contains("X-Amz-Expires=604800&"));
+        assert!(url.contains("&X-Amz-Signature="));
+    }
+
+    #[test]
+    fn queries_are_encoded_and_sorted() {
+        assert_eq!(uri_encode("a b/c~é", true), "a%20b/c~%C3%A9");
+        assert_eq!(uri_encode("a/b", false), "a%2Fb");
+        assert_eq!(
+            canonical_query(&[("prefix", "a b"), ("list-type", "2")]),
+            "list-type=2&prefix=a%20b"
+        );
+    }
+
+    #[test]
+    fn xml_elements_are_found_and_unescaped() {
+        let xml = "<R><Key>a&amp;b</Key><Key>c</Key><Open></R>";
+        assert_eq!(xml_elements(xml, "Key"), ["a&amp;b", "c"]);
+        assert!(xml_elements(xml, "Open").is_empty());
+        assert_eq!(unescape_xml("a&amp;lt;&lt;"), "a&lt;<");
+    }
+}
@@ -0,0 +1,88 @@
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use crate::conf::config::Config;
+
+use super::{local_store::LocalStorage, s3_store::S3Storage};
+
+/// Size and modification time of a stored object.
+#[derive(Debug, Clone)]
+pub struct ObjectMeta {
+    pub key: String,
+    pub size: u64,
+    /// Unix seconds.
+    pub modified: i64,
+}
+
+/// A flat key/value store for binary objects. Keys are relative paths with
+/// `/` separators.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    async fn put(&self, key: &str, data: Vec<u8>, content_type: &str) -> anyhow::Result<()>;
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Vec<u8>>>;
+    async fn stat(&self, key: &str) -> anyhow::Result<Option<ObjectMeta>>;
+    /// Deleting a missing object is not an error.
+    async fn delete(&self, key: &str) -> anyhow::Result<()>;
+    async fn list(&self, prefix: &str) -> anyhow::Result<Vec<ObjectMeta>>;
+    /// A URL clients can fetch the object from directly, when the backend
+    /// has one.
+    async fn presign(&self, key: &str, expires_in: Duration) -> anyhow::Result<Option<String>>;
+}
+
+/// Rejects empty keys and keys that could leave the store's root.
+pub fn validate_key(key: &str) -> anyhow::Result<&Path> {
+    let relative = Path::new(key);
+    if key.is_empty()
+        || relative
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_)))
+    {
+        return Err(anyhow::anyhow!("invalid storage key: {}", key));
+    }
+    Ok(relative)
+}
+
+/// Builds the backend selected by `BLOB_BACKEND` for mirrored images.
+pub fn blob_store_from_config(config: &Config) -> anyhow::Result<Arc<dyn Storage>> {
+    match config.blob_backend.as_str() {
+        "local" => Ok(Arc::new(LocalStorage::new(&config.blob_local_path))),
+        "s3" => Ok(Arc::new(S3Storage::from_config(config, "")?)),
+        other => Err(anyhow::anyhow!("unknown blob backend: {}", other)),
+    }
+}
+
+/// Builds the backend selected by `OUTPUT_BACKEND` for generated images,
+/// uploads and their manifests. The local one keeps them in `img_tmp_path`.
+pub fn output_storage_from_config(config: &Config) -> anyhow::Result<Arc<dyn Storage>> {
+    match config.output_backend.as_str() {
+        "local" => Ok(Arc::new(LocalStorage::new(&config.img_tmp_path))),
+        "s3" => Ok(Arc::new(S3Storage::from_config(
+            config,
+            &config.output_s3_prefix,
+        )?)),
+        other => Err(anyhow::anyhow!("unknown output backend: {}", other)),
+    }
+}
+
+/// The path of an output in `img_tmp_path`, copied there from the output
+/// storage when another instance produced it. Variants, renders and
+/// `NamedFile` all work on these local copies.
+pub async fn local_output(config: &Config, key: &str) -> anyhow::Result<Option<PathBuf>> {
+    let path = Path::new(&config.img_tmp_path).join(validate_key(key)?);
+    if tokio::fs::try_exists(&path).await? {
+        return Ok(Some(path));
+    }
+    let Some(data) = output_storage_from_config(config)?.get(key).await? else {
+        return Ok(None);
+    };
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let tmp_path = path.with_extension(format!("{}.tmp", uuid::Uuid::new_v4().simple()));
+    tokio::fs::write(&tmp_path, data).await?;
+    tokio::fs::rename(&tmp_path, &path).await?;
+    Ok(Some(path))
+}
@@ -0,0 +1,354 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::ws::task_ws::{TaskParams, TaskStatus};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Visibility {
+    #[default]
+    Public,
+    Unlisted,
+    Private,
+}
+
+/// Role of a pubkey in an album, ordered by what it may do.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Viewer,
+    Editor,
+    Owner,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AlbumMember {
+    pub pubkey: String,
+    pub role: Role,
+    pub joined_at: i64,
+}
+
+/// One membership change; `role: None` means the member was removed.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MemberChange {
+    pub album_id: String,
+    pub member: String,
+    pub role: Option<Role>,
+    pub by: String,
+    pub at: i64,
+    /// Set once the change has been seen on chain as a CHRO memo op.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tx_signature: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Invitation {
+    pub token: String,
+    pub album_id: String,
+    pub role: Role,
+    pub created_by: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_uses: Option<u32>,
+    pub uses: u32,
+}
+
+impl Invitation {
+    pub fn is_usable(&self, now: i64) -> bool {
+        self.expires_at.is_none_or(|expires_at| expires_at > now)
+            && self.max_uses.is_none_or(|max_uses| self.uses < max_uses)
+    }
+
+    /// The role the inviter must still hold for the invitation to count:
+    /// editors may invite viewers, only the owner may invite other roles.
+    pub fn inviter_role(&self) -> Role {
+        if self.role == Role::Viewer {
+            Role::Editor
+        } else {
+            Role::Owner
+        }
+    }
+
+    /// How an acceptance memo names the invitation, so the token itself never
+    /// goes on chain.
+    pub fn token_hash(&self) -> String {
+        hex::encode(Sha256::digest(self.token.as_bytes()))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Album {
+    pub id: String,
+    pub owner: String,
+    pub title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cover: Option<String>,
+    pub visibility: Visibility,
+    #[serde(default)]
+    pub members: Vec<AlbumMember>,
+    #[serde(default)]
+    pub member_log: Vec<MemberChange>,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+impl Album {
+    pub fn role_of(&self, pubkey: &str) -> Option<Role> {
+        if self.owner == pubkey {
+            return Some(Role::Owner);
+        }
+        self.members
+            .iter()
+            .find(|m| m.pubkey == pubkey)
+            .map(|m| m.role)
+    }
+
+    pub fn has_role(&self, pubkey: Option<&str>, role: Role) -> bool {
+        pubkey
+            .and_then(|pubkey| self.role_of(pubkey))
+            .is_some_and(|r| r >= role)
+    }
+
+    /// Private albums are only visible to members; public and unlisted ones to anyone.
+    pub fn visible_to(&self, viewer: Option<&str>) -> bool {
+        self.visibility != Visibility::Private || self.has_role(viewer, Role::Viewer)
+    }
+
+    /// Applies a membership change and appends it to the audit log.
+    pub fn apply_member_change(&mut self, change: MemberChange) {
+        self.members.retain(|m| m.pubkey != change.member);
+        if let Some(role) = change.role.filter(|role| *role != Role::Owner) {
+            self.members.push(AlbumMember {
+                pubkey: change.member.clone(),
+                role,
+                joined_at: change.at,
+            });
+        }
+        self.updated_at = change.at;
+        self.member_log.push(change);
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Memory {
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub album_id: Option<String>,
+    pub author: String,
+    pub image: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image_sha256: Option<String>,
+    pub title: String,
+    pub content: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tx_signature: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slot: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub block_time: Option<i64>,
+    pub created_at: i64,
+    #[serde(default)]
+    pub like_count: u64,
+    /// Comments that are neither hidden nor deleted.
+    #[serde(default)]
+    pub comment_count: u64,
+    /// Set when `image` points at an uploaded video.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub video: Option<VideoInfo>,
+}
+
+impl Memory {
+    pub fn sort_time(&self) -> i64 {
+        self.block_time.unwrap_or(self.created_at)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Comment {
+    pub id: String,
+    pub memory_id: String,
+    /// The comment this one replies to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<String>,
+    pub author: String,
+    pub body: String,
+    /// Hidden by a moderation hook or a moderator of the memory.
+    #[serde(default)]
+    pub hidden: bool,
+    #[serde(default)]
+    pub deleted: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tx_signature: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slot: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub block_time: Option<i64>,
+    pub created_at: i64,
+}
+
+impl Comment {
+    pub fn is_shown(&self) -> bool {
+        !self.hidden && !self.deleted
+    }
+
+    pub fn sort_time(&self) -> i64 {
+        self.block_time.unwrap_or(self.created_at)
+    }
+}
+
+/// An unguessable public link to one memory.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ShareLink {
+    pub token: String,
+    pub memory_id: String,
+    pub created_by: String,
+    pub created_at: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<i64>,
+    #[serde(default)]
+    pub views: u64,
+}
+
+impl ShareLink {
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+/// A user uploaded photo or video, stored once per content hash.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Upload {
+    /// SHA-256 of the stored file.
+    pub id: String,
+    pub file_name: String,
+    pub content_type: String,
+    /// Pixel size of images; videos are not probed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    pub size: u64,
+    pub uploaded_by: String,
+    pub created_at: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub video: Option<VideoInfo>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum VideoStatus {
+    #[default]
+    Processing,
+    Ready,
+    Failed,
+}
+
+/// What ffprobe and ffmpeg made of an uploaded video.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct VideoInfo {
+    pub status: VideoStatus,
+    /// Seconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub codec: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audio_codec: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub poster_url: Option<String>,
+    /// HLS master playlist, when renditions are configured.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hls_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A resumable upload in progress. The bytes received so far live in a
+/// partial file whose length is the upload offset.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ResumableUpload {
+    pub id: String,
+    pub owner: String,
+    /// Total size announced on creation.
+    pub length: u64,
+    /// Decoded `Upload-Metadata` pairs.
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+    pub created_at: i64,
+    /// The upload the finished file was stored as.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_id: Option<String>,
+}
+
+/// A submitted generation task, kept so its author can browse and re-run it.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Task {
+    pub id: String,
+    #[serde(flatten)]
+    pub params: TaskParams,
+    pub status: TaskStatus,
+    /// Set on re-runs and variations of another task.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lineage: Option<TaskLineage>,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum VariationKind {
+    /// Same parameters, new seed.
+    Rerun,
+    /// Same seed, edited prompt.
+    Tweak,
+    /// The parent's image as input.
+    Img2img,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TaskLineage {
+    pub parent_id: String,
+    pub kind: VariationKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub denoise: Option<f64>,
+}
+
+/// A memory image copied into the blob store, keyed by its SHA-256.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MirroredImage {
+    pub uri: String,
+    pub sha256: String,
+    pub content_type: String,
+    pub size: usize,
+}
+
+/// A generated image or upload prepared for a CHRO memo.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PublishedMemory {
+    pub prompt_id: String,
+    pub image_uri: String,
+    pub content_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata_uri: Option<String>,
+    pub title: String,
+    pub content: String,
+    /// The memo text to send with the Memo program.
+    pub memo: String,
+}
+
+/// An NFT mint prepared for a task. The transaction is partially signed by the
+/// server and still needs the owner's signature.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MintRecord {
+    pub prompt_id: String,
+    pub owner: String,
+    pub compressed: bool,
+    /// Mint address, or the predicted asset id for compressed NFTs.
+    pub mint: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tree: Option<String>,
+    pub transaction: String,
+}
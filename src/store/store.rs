@@ -0,0 +1,750 @@
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use log::error;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::{auth::signed_url::local_file_name, conf::config::Config, utils::metrics};
+
+use super::models::{
+    Album, Comment, Invitation, MemberChange, Memory, MintRecord, MirroredImage, PublishedMemory,
+    ResumableUpload, Role, ShareLink, Task, Upload, VideoInfo,
+};
+
+/// Everything that outlives a process restart, kept in memory and snapshotted
+/// to a JSON file after each change.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Store {
+    #[serde(default)]
+    pub albums: HashMap<String, Album>,
+    #[serde(default)]
+    pub memories: HashMap<String, Memory>,
+    #[serde(default)]
+    pub invitations: HashMap<String, Invitation>,
+    #[serde(default)]
+    pub comments: HashMap<String, Comment>,
+    /// Pubkeys that liked a memory, by memory id.
+    #[serde(default)]
+    pub likes: HashMap<String, HashSet<String>>,
+    #[serde(default)]
+    pub share_links: HashMap<String, ShareLink>,
+    /// Pubkeys followed by each pubkey.
+    #[serde(default)]
+    pub follows: HashMap<String, HashSet<String>>,
+    /// User uploads by file id.
+    #[serde(default)]
+    pub uploads: HashMap<String, Upload>,
+    /// Resumable uploads in progress or recently finished, by id.
+    #[serde(default)]
+    pub resumable_uploads: HashMap<String, ResumableUpload>,
+    /// Generation tasks by prompt id.
+    #[serde(default)]
+    pub tasks: HashMap<String, Task>,
+    /// Memory images copied into the blob store, by memo transaction signature.
+    #[serde(default)]
+    pub mirrored_images: HashMap<String, MirroredImage>,
+    /// Tasks and uploads prepared for a memo, by task or file id.
+    #[serde(default)]
+    pub published: HashMap<String, PublishedMemory>,
+    /// NFT mints prepared for published tasks and uploads, by task or file id.
+    #[serde(default)]
+    pub mints: HashMap<String, MintRecord>,
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+static GLOBAL_STORE: Lazy<Arc<Mutex<Store>>> = Lazy::new(|| Arc::new(Mutex::new(Store::default())));
+
+/// Loads the snapshot at `path` (if any) and persists future changes there.
+pub async fn init_store(path: &str) -> anyhow::Result<()> {
+    let path = PathBuf::from(path);
+    let mut store = match tokio::fs::read(&path).await {
+        Ok(data) => serde_json::from_slice::<Store>(&data)?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Store::default(),
+        Err(e) => return Err(e.into()),
+    };
+    store.path = Some(path);
+    *GLOBAL_STORE.lock().await = store;
+    Ok(())
+}
+
+pub async fn read_store<R>(f: impl FnOnce(&Store) -> R) -> R {
+    let store = GLOBAL_STORE.lock().await;
+    f(&store)
+}
+
+/// Applies a change and writes the snapshot. A failed write is logged and
+/// counted in the metrics; the change stays in memory and is written with the
+/// next one.
+pub async fn write_store<R>(f: impl FnOnce(&mut Store) -> R) -> R {
+    let mut store = GLOBAL_STORE.lock().await;
+    let result = f(&mut store);
+    match store.persist().await {
+        Ok(()) => metrics::STORE_UNPERSISTED.set(0),
+        Err(e) => {
+            error!("persist store failed: {}", e);
+            metrics::STORE_PERSIST_FAILURES.add(1);
+            metrics::STORE_UNPERSISTED.set(1);
+        }
+    }
+    result
+}
+
+impl Store {
+    async fn persist(&self) -> anyhow::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let tmp_path = path.with_extension("tmp");
+        tokio::fs::write(&tmp_path, serde_json::to_vec(self)?).await?;
+        tokio::fs::rename(&tmp_path, path).await?;
+        Ok(())
+    }
+
+    /// Whether `viewer` may see a memory: memories outside albums are public,
+    /// the rest follow their album's visibility. Memories of an album that is
+    /// gone are left to their author.
+    pub fn memory_visible_to(&self, memory: &Memory, viewer: Option<&str>) -> bool {
+        match &memory.album_id {
+            Some(album_id) => match self.albums.get(album_id) {
+                Some(album) => album.visible_to(viewer),
+                None => viewer == Some(memory.author.as_str()),
+            },
+            None => true,
+        }
+    }
+
+    pub fn can_edit_memory(&self, memory: &Memory, pubkey: &str) -> bool {
+        memory.author == pubkey
+            || memory
+                .album_id
+                .as_ref()
+                .and_then(|album_id| self.albums.get(album_id))
+                .is_some_and(|album| album.has_role(Some(pubkey), Role::Editor))
+    }
+
+    /// Looks a memory up by id or by the signature of its memo transaction.
+    pub fn find_memory(&self, reference: &str) -> Option<&Memory> {
+        self.memories.get(reference).or_else(|| {
+            self.memories
+                .values()
+                .find(|m| m.tx_signature.as_deref() == Some(reference))
+        })
+    }
+
+    /// Removes a memory together with its comments and likes.
+    pub fn remove_memory(&mut self, memory_id: &str) -> Option<Memory> {
+        let memory = self.memories.remove(memory_id)?;
+        self.comments.retain(|_, c| c.memory_id != memory_id);
+        self.share_links.retain(|_, l| l.memory_id != memory_id);
+        self.likes.remove(memory_id);
+        Some(memory)
+    }
+
+    /// Likes or unlikes a memory and returns its like count.
+    pub fn set_like(&mut self, memory_id: &str, pubkey: &str, liked: bool) -> Option<u64> {
+        let memory = self.memories.get_mut(memory_id)?;
+        let likes = self.likes.entry(memory_id.to_string()).or_default();
+        if liked {
+            likes.insert(pubkey.to_string());
+        } else {
+            likes.remove(pubkey);
+        }
+        memory.like_count = likes.len() as u64;
+        if likes.is_empty() {
+            self.likes.remove(memory_id);
+        }
+        Some(memory.like_count)
+    }
+
+    pub fn liked_by(&self, memory_id: &str, pubkey: &str) -> bool {
+        self.likes
+            .get(memory_id)
+            .is_some_and(|likes| likes.contains(pubkey))
+    }
+
+    pub fn insert_comment(&mut self, comment: Comment) {
+        if comment.is_shown()
+            && let Some(memory) = self.memories.get_mut(&comment.memory_id)
+        {
+            memory.comment_count += 1;
+        }
+        self.comments.insert(comment.id.clone(), comment);
+    }
+
+    /// Changes a comment and keeps the comment count of its memory in step.
+    pub fn update_comment(
+        &mut self,
+        comment_id: &str,
+        f: impl FnOnce(&mut Comment),
+    ) -> Option<Comment> {
+        let comment = self.comments.get_mut(comment_id)?;
+        let was_shown = comment.is_shown();
+        f(comment);
+        let comment = comment.clone();
+        if let Some(memory) = self.memories.get_mut(&comment.memory_id) {
+            match (was_shown, comment.is_shown()) {
+                (false, true) => memory.comment_count += 1,
+                (true, false) => memory.comment_count = memory.comment_count.saturating_sub(1),
+                _ => {}
+            }
+        }
+        Some(comment)
+    }
+
+    /// Follows or unfollows `followee` and returns whether anything changed.
+    pub fn set_follow(&mut self, follower: &str, followee: &str, follow: bool) -> bool {
+        let following = self.follows.entry(follower.to_string()).or_default();
+        let changed = if follow {
+            following.insert(followee.to_string())
+        } else {
+            following.remove(followee)
+        };
+        if following.is_empty() {
+            self.follows.remove(follower);
+        }
+        changed
+    }
+
+    pub fn followers_of(&self, pubkey: &str) -> Vec<String> {
+        let mut followers = self
+            .follows
+            .iter()
+            .filter(|(_, following)| following.contains(pubkey))
+            .map(|(follower, _)| follower.clone())
+            .collect::<Vec<_>>();
+        followers.sort();
+        followers
+    }
+
+    /// Records an upload unless the same content is already stored, and
+    /// returns the stored record with whether it was a duplicate.
+    pub fn insert_upload(&mut self, upload: Upload) -> (Upload, bool) {
+        match self.uploads.get(&upload.id) {
+            Some(existing) => (existing.clone(), true),
+            None => {
+                self.uploads.insert(upload.id.clone(), upload.clone());
+                (upload, false)
+            }
+        }
+    }
+
+    /// The video behind a memory's `image` URL, if it names an uploaded video.
+    pub fn video_for(&self, uri: &str) -> Option<VideoInfo> {
+        let file_name = uri.rsplit('/').next()?;
+        self.uploads
+            .values()
+            .find(|upload| upload.file_name == file_name)
+            .and_then(|upload| upload.video.clone())
+    }
+
+    /// Updates the video info of an upload and of the memories showing it.
+    pub fn set_video(&mut self, file_id: &str, video: VideoInfo) {
+        let Some(upload) = self.uploads.get_mut(file_id) else {
+            return;
+        };
+        upload.video = Some(video.clone());
+        let file_name = upload.file_name.clone();
+        for memory in self.memories.values_mut() {
+            if memory.image.rsplit('/').next() == Some(file_name.as_str()) {
+                memory.video = Some(video.clone());
+            }
+        }
+    }
+
+    /// Who generated a task or uploaded a file, by task or file id.
+    pub fn file_owner(&self, id: &str) -> Option<&str> {
+        self.tasks
+            .get(id)
+            .map(|task| task.params.author.as_str())
+            .or_else(|| {
+                self.uploads
+                    .get(id)
+                    .map(|upload| upload.uploaded_by.as_str())
+            })
+    }
+
+    /// Who owns a served file: the author of the task or upload whose id
+    /// comes before the first `.` or `_` of its name.
+    pub fn file_name_owner(&self, file_name: &str) -> Option<&str> {
+        file_name
+            .split(['.', '_'])
+            .next()
+            .and_then(|id| self.file_owner(id))
+    }
+
+    /// Whether a memory anyone may see shows the file, as image or poster.
+    /// Only URLs of files we serve count, and a file with a known owner only
+    /// counts when shown by one of the owner's memories, so nobody can expose
+    /// someone else's file by posting its URL.
+    pub fn is_public_file(&self, config: &Config, file_name: &str) -> bool {
+        let owner = self.file_name_owner(file_name);
+        let shows = |url: &str| local_file_name(config, url) == Some(file_name);
+        self.memories.values().any(|memory| {
+            owner.is_none_or(|owner| owner == memory.author)
+                && (shows(&memory.image)
+                    || memory
+                        .video
+                        .as_ref()
+                        .and_then(|v| v.poster_url.as_deref())
+                        .is_some_and(shows))
+                && self.memory_visible_to(memory, None)
+        })
+    }
+
+    /// Whether `viewer` may see the mirrored image `sha256`, i.e. one of the
+    /// memories showing it.
+    pub fn blob_visible_to(&self, sha256: &str, viewer: Option<&str>) -> bool {
+        self.memories.values().any(|memory| {
+            memory.image_sha256.as_deref() == Some(sha256) && self.memory_visible_to(memory, viewer)
+        })
+    }
+
+    /// Whether `viewer` may see the task or upload `id`, whose file is
+    /// `file_name`: its owner may, anyone else once it is public.
+    pub fn may_view_file(
+        &self,
+        config: &Config,
+        id: &str,
+        file_name: &str,
+        viewer: Option<&str>,
+    ) -> bool {
+        viewer.is_some_and(|viewer| self.file_owner(id) == Some(viewer))
+            || self.is_public_file(config, file_name)
+    }
+
+    /// Memories of followed authors and of albums shared with `pubkey`, newest
+    /// block time first.
+    pub fn feed(&self, pubkey: &str) -> Vec<Memory> {
+        let following = self.follows.get(pubkey);
+        let mut memories = self
+            .memories
+            .values()
+            .filter(|m| {
+                following.is_some_and(|f| f.contains(&m.author))
+                    || m.album_id
+                        .as_ref()
+                        .and_then(|album_id| self.albums.get(album_id))
+                        .is_some_and(|album| album.has_role(Some(pubkey), Role::Viewer))
+            })
+            .filter(|m| self.memory_visible_to(m, Some(pubkey)))
+            .cloned()
+            .collect::<Vec<_>>();
+        sort_by_block_time(&mut memories, false);
+        memories
+    }
+
+    /// Memories of an album, newest block time first. Memories not yet on chain
+    /// sort by creation time.
+    pub fn album_memories(&self, album_id: &str, ascending: bool) -> Vec<Memory> {
+        let mut memories = self
+            .memories
+            .values()
+            .filter(|m| m.album_id.as_deref() == Some(album_id))
+            .cloned()
+            .collect::<Vec<_>>();
+        sort_by_block_time(&mut memories, ascending);
+        memories
+    }
+}
+
+impl Store {
+    /// Inserts a memory found by the indexer, or refreshes the one already indexed
+    /// for the same transaction. A memory added through the API carries its id
+    /// in the memo and is confirmed instead of duplicated. The memo may only
+    /// place it into an album the author can edit; one naming a deleted album
+    /// stays with its author. A stored memory claiming the transaction for
+    /// another author is replaced.
+    pub fn upsert_indexed_memory(&mut self, mut memory: Memory) -> Memory {
+        if let Some(album_id) = &memory.album_id {
+            let editable = self
+                .albums
+                .get(album_id)
+                .is_none_or(|album| album.has_role(Some(&memory.author), Role::Editor));
+            if !editable {
+                memory.album_id = None;
+            }
+        }
+        memory.video = self.video_for(&memory.image);
+        let existing = self
+            .memories
+            .values_mut()
+            .find(|m| m.tx_signature.is_some() && m.tx_signature == memory.tx_signature);
+        if let Some(existing) = existing {
+            if existing.author != memory.author {
+                let claim = existing.id.clone();
+                self.memories.remove(&claim);
+                self.memories.insert(memory.id.clone(), memory.clone());
+                return memory;
+            }
+            existing.slot = memory.slot;
+            existing.block_time = memory.block_time;
+            if memory.image_sha256.is_some() {
+                existing.image_sha256 = memory.image_sha256;
+            }
+            return existing.clone();
+        }
+        if let Some(existing) = self.memories.get_mut(&memory.id)
+            && existing.tx_signature.is_none()
+            && existing.author == memory.author
+        {
+            existing.tx_signature = memory.tx_signature;
+            existing.slot = memory.slot;
+            existing.block_time = memory.block_time;
+            if memory.image_sha256.is_some() {
+                existing.image_sha256 = memory.image_sha256;
+            }
+            return existing.clone();
+        }
+        if self.memories.contains_key(&memory.id) {
+            memory.id = uuid::Uuid::new_v4().to_string();
+        }
+        self.memories.insert(memory.id.clone(), memory.clone());
+        memory
+    }
+
+    /// Applies a membership change found on chain. Only the album owner may
+    /// change roles; members may only remove themselves, or join through an
+    /// invitation named by its token hash. A change already made through the
+    /// API is confirmed instead of being applied twice.
+    pub fn apply_indexed_member_change(
+        &mut self,
+        change: MemberChange,
+        invitation: Option<&str>,
+    ) -> anyhow::Result<MemberChange> {
+        let album = self
+            .albums
+            .get_mut(&change.album_id)
+            .ok_or_else(|| anyhow::anyhow!("album {} not found", change.album_id))?;
+        let by_self = change.member == change.by;
+        let leaving = change.role.is_none() && by_self;
+        let joining = change.role.is_some() && by_self && invitation.is_some();
+        if change.member == album.owner || !(leaving || joining || album.owner == change.by) {
+            anyhow::bail!("{} may not change members of {}", change.by, album.id);
+        }
+        if let Some(done) = album
+            .member_log
+            .iter()
+            .find(|c| c.tx_signature.is_some() && c.tx_signature == change.tx_signature)
+        {
+            return Ok(done.clone());
+        }
+        let pending = album.member_log.iter_mut().rev().find(|c| {
+            c.tx_signature.is_none()
+                && c.member == change.member
+                && c.role == change.role
+                && c.by == change.by
+        });
+        if let Some(pending) = pending {
+            pending.tx_signature = change.tx_signature;
+            return Ok(pending.clone());
+        }
+        if let Some(token_hash) = invitation.filter(|_| joining) {
+            // not accepted through the API, so the invitation is checked here
+            let invitation = self
+                .invitations
+                .values_mut()
+                .find(|i| {
+                    i.token_hash() == token_hash
+                        && i.album_id == album.id
+                        && Some(i.role) == change.role
+                        && i.is_usable(change.at)
+                })
+                .ok_or_else(|| anyhow::anyhow!("no usable invitation to {}", album.id))?;
+            if !album.has_role(Some(&invitation.created_by), invitation.inviter_role()) {
+                anyhow::bail!(
+                    "{} may no longer invite to {}",
+                    invitation.created_by,
+                    album.id
+                );
+            }
+            if album.has_role(Some(&change.member), invitation.role) {
+                anyhow::bail!("{} already is a member of {}", change.member, album.id);
+            }
+            invitation.uses += 1;
+        }
+        album.apply_member_change(change.clone());
+        Ok(change)
+    }
+
+    /// Merges a comment found on chain. A comment already posted through the API
+    /// carries its id in the memo and is confirmed instead of duplicated.
+    pub fn apply_indexed_comment(&mut self, mut comment: Comment) -> anyhow::Result<Comment> {
+        let memory = self
+            .find_memory(&comment.memory_id)
+            .ok_or_else(|| anyhow::anyhow!("memory {} not found", comment.memory_id))?;
+        if !self.memory_visible_to(memory, Some(&comment.author)) {
+            anyhow::bail!("{} can not see memory {}", comment.author, memory.id);
+        }
+        comment.memory_id = memory.id.clone();
+        if let Some(done) = self
+            .comments
+            .values()
+            .find(|c| c.tx_signature.is_some() && c.tx_signature == comment.tx_signature)
+        {
+            return Ok(done.clone());
+        }
+        if let Some(existing) = self.comments.get_mut(&comment.id)
+            && existing.author == comment.author
+            && existing.memory_id == comment.memory_id
+        {
+            existing.tx_signature = comment.tx_signature;
+            existing.slot = comment.slot;
+            existing.block_time = comment.block_time;
+            return Ok(existing.clone());
+        }
+        if self.comments.contains_key(&comment.id) {
+            comment.id = uuid::Uuid::new_v4().to_string();
+        }
+        if comment
+            .parent_id
+            .as_ref()
+            .and_then(|parent| self.comments.get(parent))
+            .is_none_or(|parent| parent.memory_id != comment.memory_id)
+        {
+            comment.parent_id = None;
+        }
+        self.insert_comment(comment.clone());
+        Ok(comment)
+    }
+}
+
+pub fn sort_by_block_time(memories: &mut [Memory], ascending: bool) {
+    memories.sort_by(|a, b| {
+        let order = a.sort_time().cmp(&b.sort_time()).then(a.id.cmp(&b.id));
+        if ascending { order } else { order.reverse() }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::models::Visibility;
+
+    fn album(id: &str, owner: &str, visibility: Visibility) -> Album {
+        Album {
+            id: id.to_string(),
+            owner: owner.to_string(),
+            title: id.to_string(),
+            cover: None,
+            visibility,
+            members: Vec::new(),
+            member_log: Vec::new(),
+            created_at: 0,
+            updated_at: 0,
+        }
+    }
+
+    fn memory(id: &str, author: &str, album_id: &str) -> Memory {
+        Memory {
+            id: id.to_string(),
+            album_id: Some(album_id.to_string()),
+            author: author.to_string(),
+            image: "https://example.com/a.png".to_string(),
+            image_sha256: None,
+            title: "title".to_string(),
+            content: "content".to_string(),
+            tx_signature: None,
+            slot: None,
+            block_time: None,
+            created_at: 0,
+            like_count: 0,
+            comment_count: 0,
+            video: None,
+        }
+    }
+
+    fn indexed(memory: Memory, signature: &str) -> Memory {
+        Memory {
+            tx_signature: Some(signature.to_string()),
+            slot: Some(7),
+            block_time: Some(100),
+            ..memory
+        }
+    }
+
+    #[test]
+    fn memo_with_memory_id_confirms_the_api_memory() {
+        let mut store = Store::default();
+        store
+            .albums
+            .insert("a".to_string(), album("a", "alice", Visibility::Public));
+        store
+            .memories
+            .insert("m".to_string(), memory("m", "alice", "a"));
+
+        let confirmed = store.upsert_indexed_memory(indexed(memory("m", "alice", "a"), "sig"));
+        assert_eq!(confirmed.id, "m");
+        assert_eq!(store.memories.len(), 1);
+        assert_eq!(store.memories["m"].tx_signature.as_deref(), Some("sig"));
+        assert_eq!(store.memories["m"].block_time, Some(100));
+
+        // indexing the same transaction again changes nothing
+        store.upsert_indexed_memory(indexed(memory("other", "alice", "a"), "sig"));
+        assert_eq!(store.memories.len(), 1);
+    }
+
+    #[test]
+    fn memo_may_not_confirm_another_authors_memory() {
+        let mut store = Store::default();
+        store
+            .albums
+            .insert("a".to_string(), album("a", "alice", Visibility::Public));
+        store
+            .memories
+            .insert("m".to_string(), memory("m", "alice", "a"));
+
+        let indexed = store.upsert_indexed_memory(indexed(memory("m", "bob", "a"), "sig"));
+        assert_ne!(indexed.id, "m");
+        assert!(store.memories["m"].tx_signature.is_none());
+        assert_eq!(store.memories.len(), 2);
+        // bob can not edit alice's album
+        assert!(indexed.album_id.is_none());
+    }
+
+    fn invitation(role: Role, created_by: &str) -> Invitation {
+        Invitation {
+            token: "token".to_string(),
+            album_id: "a".to_string(),
+            role,
+            created_by: created_by.to_string(),
+            expires_at: None,
+            max_uses: Some(1),
+            uses: 0,
+        }
+    }
+
+    fn join(member: &str, role: Role, signature: &str) -> MemberChange {
+        MemberChange {
+            album_id: "a".to_string(),
+            member: member.to_string(),
+            role: Some(role),
+            by: member.to_string(),
+            at: 100,
+            tx_signature: Some(signature.to_string()),
+        }
+    }
+
+    #[test]
+    fn accepted_invitation_is_confirmed_on_chain() {
+        let mut store = Store::default();
+        let mut album = album("a", "alice", Visibility::Private);
+        album.apply_member_change(MemberChange {
+            tx_signature: None,
+            ..join("bob", Role::Viewer, "")
+        });
+        store.albums.insert("a".to_string(), album);
+        let hash = invitation(Role::Viewer, "alice").token_hash();
+
+        let change = store
+            .apply_indexed_member_change(join("bob", Role::Viewer, "sig"), Some(&hash))
+            .unwrap();
+        assert_eq!(change.tx_signature.as_deref(), Some("sig"));
+        let log = &store.albums["a"].member_log;
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0].tx_signature.as_deref(), Some("sig"));
+    }
+
+    #[test]
+    fn members_join_on_chain_only_with_a_usable_invitation() {
+        let mut store = Store::default();
+        store
+            .albums
+            .insert("a".to_string(), album("a", "alice", Visibility::Private));
+        let invitation = invitation(Role::Viewer, "alice");
+        let hash = invitation.token_hash();
+        store
+            .invitations
+            .insert(invitation.token.clone(), invitation);
+
+        // joining needs the invitation, and one for the same role
+        assert!(
+            store
+                .apply_indexed_member_change(join("bob", Role::Viewer, "s1"), None)
+                .is_err()
+        );
+        assert!(
+            store
+                .apply_indexed_member_change(join("bob", Role::Editor, "s2"), Some(&hash))
+                .is_err()
+        );
+        store
+            .apply_indexed_member_change(join("bob", Role::Viewer, "s3"), Some(&hash))
+            .unwrap();
+        assert!(store.albums["a"].has_role(Some("bob"), Role::Viewer));
+        assert_eq!(store.invitations["token"].uses, 1);
+        // used up
+        assert!(
+            store
+                .apply_indexed_member_change(join("carol", Role::Viewer, "s4"), Some(&hash))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn invitations_lapse_when_the_inviter_loses_the_role() {
+        let mut store = Store::default();
+        store
+            .albums
+            .insert("a".to_string(), album("a", "alice", Visibility::Private));
+        let invitation = invitation(Role::Viewer, "dave");
+        let hash = invitation.token_hash();
+        store
+            .invitations
+            .insert(invitation.token.clone(), invitation);
+        assert!(
+            store
+                .apply_indexed_member_change(join("bob", Role::Viewer, "sig"), Some(&hash))
+                .is_err()
+        );
+        assert!(!store.albums["a"].has_role(Some("bob"), Role::Viewer));
+    }
+
+    #[test]
+    fn memories_of_a_missing_album_stay_with_their_author() {
+        let mut store = Store::default();
+        let memory = store.upsert_indexed_memory(indexed(memory("m", "alice", "gone"), "sig"));
+        assert_eq!(memory.album_id.as_deref(), Some("gone"));
+        assert!(store.memory_visible_to(&memory, Some("alice")));
+        assert!(!store.memory_visible_to(&memory, Some("bob")));
+        assert!(!store.memory_visible_to(&memory, None));
+    }
+
+    #[test]
+    fn mirrored_images_follow_the_memories_showing_them() {
+        let mut store = Store::default();
+        for (id, visibility) in [
+            ("private", Visibility::Private),
+            ("public", Visibility::Public),
+        ] {
+            store
+                .albums
+                .insert(id.to_string(), album(id, "alice", visibility));
+        }
+        let mirrored = |id: &str, album_id: &str, sha256: &str| Memory {
+            image_sha256: Some(sha256.to_string()),
+            ..memory(id, "alice", album_id)
+        };
+        store
+            .memories
+            .insert("m1".to_string(), mirrored("m1", "private", "hidden"));
+        store
+            .memories
+            .insert("m2".to_string(), mirrored("m2", "private", "shown"));
+        store
+            .memories
+            .insert("m3".to_string(), mirrored("m3", "public", "shown"));
+
+        assert!(store.blob_visible_to("hidden", Some("alice")));
+        assert!(!store.blob_visible_to("hidden", Some("bob")));
+        assert!(!store.blob_visible_to("hidden", None));
+        assert!(store.blob_visible_to("shown", None));
+        assert!(!store.blob_visible_to("unknown", Some("alice")));
+    }
+}
@@ -0,0 +1,187 @@
+use anyhow::anyhow;
+
+/// EXIF tag pointing at the GPS IFD.
+const GPS_IFD_TAG: u16 = 0x8825;
+
+/// Wipes the GPS IFD from the EXIF block of a JPEG, PNG or WebP file in place,
+/// leaving the rest of the metadata (orientation, camera) untouched. Offsets
+/// don't move, so the file stays valid. Returns whether GPS data was found.
+pub fn strip_gps(data: &mut [u8]) -> anyhow::Result<bool> {
+    if data.starts_with(&[0xFF, 0xD8]) {
+        strip_jpeg(data)
+    } else if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        strip_png(data)
+    } else if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        strip_webp(data)
+    } else {
+        Ok(false)
+    }
+}
+
+fn malformed() -> anyhow::Error {
+    anyhow!("malformed EXIF data")
+}
+
+fn strip_jpeg(data: &mut [u8]) -> anyhow::Result<bool> {
+    let mut found = false;
+    let mut pos = 2;
+    while pos + 4 <= data.len() {
+        if data[pos] != 0xFF {
+            return Err(anyhow!("malformed JPEG segment"));
+        }
+        let marker = data[pos + 1];
+        // fill bytes and markers without a length
+        if marker == 0xFF {
+            pos += 1;
+            continue;
+        }
+        if marker == 0x01 || (0xD0..=0xD7).contains(&marker) {
+            pos += 2;
+            continue;
+        }
+        // the entropy coded image data follows, no metadata after that
+        if marker == 0xDA || marker == 0xD9 {
+            break;
+        }
+        let len = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        let end = pos + 2 + len;
+        if len < 2 || end > data.len() {
+            return Err(anyhow!("malformed JPEG segment"));
+        }
+        let segment = &mut data[pos + 4..end];
+        if marker == 0xE1 && segment.starts_with(b"Exif\0\0") {
+            found |= strip_tiff(&mut segment[6..])?;
+        }
+        pos = end;
+    }
+    Ok(found)
+}
+
+fn strip_png(data: &mut [u8]) -> anyhow::Result<bool> {
+    let mut found = false;
+    let mut pos = 8;
+    while pos + 12 <= data.len() {
+        let len = u32::from_be_bytes(data[pos..pos + 4].try_into()?) as usize;
+        let end = pos + 12 + len;
+        if end > data.len() {
+            return Err(anyhow!("malformed PNG chunk"));
+        }
+        if &data[pos + 4..pos + 8] == b"eXIf" && strip_tiff(&mut data[pos + 8..pos + 8 + len])? {
+            found = true;
+            let crc = crc32fast::hash(&data[pos + 4..pos + 8 + len]);
+            data[pos + 8 + len..end].copy_from_slice(&crc.to_be_bytes());
+        }
+        pos = end;
+    }
+    Ok(found)
+}
+
+fn strip_webp(data: &mut [u8]) -> anyhow::Result<bool> {
+    let mut found = false;
+    let mut pos = 12;
+    while pos + 8 <= data.len() {
+        let len = u32::from_le_bytes(data[pos + 4..pos + 8].try_into()?) as usize;
+        let end = pos + 8 + len;
+        if end > data.len() {
+            return Err(anyhow!("malformed WebP chunk"));
+        }
+        if &data[pos..pos + 4] == b"EXIF" {
+            let chunk = &mut data[pos + 8..end];
+            // some writers keep the JPEG style header
+            let tiff = if chunk.starts_with(b"Exif\0\0") {
+                &mut chunk[6..]
+            } else {
+                chunk
+            };
+            found |= strip_tiff(tiff)?;
+        }
+        pos = end + len % 2;
+    }
+    Ok(found)
+}
+
+/// Reads and writes integers in the byte order of a TIFF header.
+struct Tiff<'a> {
+    data: &'a mut [u8],
+    little_endian: bool,
+}
+
+impl Tiff<'_> {
+    fn u16(&self, at: usize) -> anyhow::Result<u16> {
+        let bytes = self.data.get(at..at + 2).ok_or_else(malformed)?;
+        let bytes = [bytes[0], bytes[1]];
+        Ok(if self.little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        })
+    }
+
+    fn u32(&self, at: usize) -> anyhow::Result<u32> {
+        let bytes = self.data.get(at..at + 4).ok_or_else(malformed)?;
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        Ok(if self.little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    }
+
+    fn zero(&mut self, from: usize, len: usize) -> anyhow::Result<()> {
+        self.data
+            .get_mut(from..from + len)
+            .ok_or_else(malformed)?
+            .fill(0);
+        Ok(())
+    }
+}
+
+/// Bytes per value of a TIFF field type.
+fn type_size(field_type: u16) -> usize {
+    match field_type {
+        3 | 8 => 2,
+        4 | 9 | 11 => 4,
+        5 | 10 | 12 => 8,
+        _ => 1,
+    }
+}
+
+/// Empties the GPS IFD referenced from IFD0: the values its entries point at
+/// and the entries themselves are zeroed and its entry count set to 0.
+fn strip_tiff(data: &mut [u8]) -> anyhow::Result<bool> {
+    let little_endian = match data.get(..2) {
+        Some(b"II") => true,
+        Some(b"MM") => false,
+        _ => return Err(malformed()),
+    };
+    let mut tiff = Tiff {
+        data,
+        little_endian,
+    };
+    if tiff.u16(2)? != 42 {
+        return Err(malformed());
+    }
+    let ifd0 = tiff.u32(4)? as usize;
+    let mut gps_ifd = None;
+    for i in 0..tiff.u16(ifd0)? as usize {
+        let entry = ifd0 + 2 + i * 12;
+        if tiff.u16(entry)? == GPS_IFD_TAG {
+            gps_ifd = Some(tiff.u32(entry + 8)? as usize);
+        }
+    }
+    let Some(gps_ifd) = gps_ifd else {
+        return Ok(false);
+    };
+    let count = tiff.u16(gps_ifd)? as usize;
+    for i in 0..count {
+        let entry = gps_ifd + 2 + i * 12;
+        let size = type_size(tiff.u16(entry + 2)?) * tiff.u32(entry + 4)? as usize;
+        if size > 4 {
+            let offset = tiff.u32(entry + 8)? as usize;
+            tiff.zero(offset, size)?;
+        }
+    }
+    // entries and the next IFD pointer, which becomes "none"
+    tiff.zero(gps_ifd, 2 + count * 12 + 4)?;
+    Ok(true)
+}
@@ -0,0 +1,5 @@
+pub mod exif;
+pub mod resumable;
+#[allow(clippy::module_inception)]
+pub mod upload;
+pub mod video;
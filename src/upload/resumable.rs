@@ -0,0 +1,373 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use futures_util::{Stream, StreamExt};
+use once_cell::sync::Lazy;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::{
+    conf::config::Config,
+    storage::janitor::modified_secs,
+    store::{
+        models::{ResumableUpload, Upload},
+        store::{read_store, write_store},
+    },
+};
+
+use super::{
+    upload::{check_image, sniff_video, store_upload, store_video},
+    video::process_video,
+};
+
+/// Version of the tus protocol the resumable endpoints speak.
+pub const TUS_VERSION: &str = "1.0.0";
+pub const TUS_EXTENSIONS: &str = "creation,checksum,termination";
+pub const TUS_CHECKSUM_ALGORITHMS: &str = "sha256";
+
+/// `Upload-Metadata` key holding the hex SHA-256 of the whole file.
+const CHECKSUM_KEY: &str = "checksum";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ResumableError {
+    #[error("upload not found")]
+    NotFound,
+    #[error("upload belongs to another user")]
+    Forbidden,
+    #[error("upload offset is {expected}, got {received}")]
+    OffsetMismatch { expected: u64, received: u64 },
+    #[error("another request is writing this upload")]
+    Busy,
+    #[error("upload exceeds {0} bytes")]
+    TooLarge(u64),
+    #[error("checksum mismatch")]
+    ChecksumMismatch,
+    #[error("{0}")]
+    Invalid(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Internal(#[from] anyhow::Error),
+}
+
+/// Ids of uploads a PATCH is currently writing to.
+static WRITING: Lazy<std::sync::Mutex<HashSet<String>>> =
+    Lazy::new(|| std::sync::Mutex::new(HashSet::new()));
+
+/// Exclusive right to write one upload, released on drop.
+pub struct WriteLock(String);
+
+impl WriteLock {
+    pub fn acquire(id: &str) -> Result<Self, ResumableError> {
+        let mut writing = WRITING.lock().unwrap();
+        if !writing.insert(id.to_string()) {
+            return Err(ResumableError::Busy);
+        }
+        Ok(WriteLock(id.to_string()))
+    }
+}
+
+impl Drop for WriteLock {
+    fn drop(&mut self) {
+        WRITING.lock().unwrap().remove(&self.0);
+    }
+}
+
+/// Decodes `key base64value,key2 base64value2`; keys may come without a value.
+pub fn parse_metadata(header: &str) -> Result<BTreeMap<String, String>, ResumableError> {
+    let mut metadata = BTreeMap::new();
+    for pair in header
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+    {
+        let (key, value) = pair.split_once(' ').unwrap_or((pair, ""));
+        let value = STANDARD
+            .decode(value.trim())
+            .ok()
+            .and_then(|value| String::from_utf8(value).ok())
+            .ok_or_else(|| {
+                ResumableError::Invalid(format!("invalid metadata value for {}", key))
+            })?;
+        metadata.insert(key.to_string(), value);
+    }
+    Ok(metadata)
+}
+
+/// Decodes an `Upload-Checksum` header such as `sha256 <base64 digest>`.
+pub fn parse_checksum(header: &str) -> Result<Vec<u8>, ResumableError> {
+    let (algorithm, digest) = header
+        .split_once(' ')
+        .ok_or_else(|| ResumableError::Invalid("invalid Upload-Checksum".to_string()))?;
+    if algorithm != "sha256" {
+        return Err(ResumableError::Invalid(format!(
+            "unsupported checksum algorithm: {}",
+            algorithm
+        )));
+    }
+    STANDARD
+        .decode(digest.trim())
+        .map_err(|_| ResumableError::Invalid("invalid Upload-Checksum".to_string()))
+}
+
+fn partial_path(config: &Config, id: &str) -> PathBuf {
+    Path::new(&config.resumable_path).join(format!("{}.part", id))
+}
+
+pub async fn create(
+    config: &Config,
+    owner: &str,
+    length: u64,
+    metadata: BTreeMap<String, String>,
+) -> Result<ResumableUpload, ResumableError> {
+    if length > config.resumable_max_bytes {
+        return Err(ResumableError::TooLarge(config.resumable_max_bytes));
+    }
+    if let Some(checksum) = metadata.get(CHECKSUM_KEY)
+        && (checksum.len() != 64 || !checksum.chars().all(|c| c.is_ascii_hexdigit()))
+    {
+        return Err(ResumableError::Invalid(
+            "checksum must be a hex SHA-256".to_string(),
+        ));
+    }
+    let upload = ResumableUpload {
+        id: uuid::Uuid::new_v4().simple().to_string(),
+        owner: owner.to_string(),
+        length,
+        metadata,
+        created_at: chrono::Utc::now().timestamp(),
+        file_id: None,
+    };
+    tokio::fs::create_dir_all(&config.resumable_path).await?;
+    tokio::fs::File::create(partial_path(config, &upload.id)).await?;
+    write_store(|store| {
+        store
+            .resumable_uploads
+            .insert(upload.id.clone(), upload.clone())
+    })
+    .await;
+    Ok(upload)
+}
+
+/// The upload `id` if it belongs to `owner`.
+pub async fn find(id: &str, owner: &str) -> Result<ResumableUpload, ResumableError> {
+    let upload = read_store(|store| store.resumable_uploads.get(id).cloned())
+        .await
+        .ok_or(ResumableError::NotFound)?;
+    if upload.owner != owner {
+        return Err(ResumableError::Forbidden);
+    }
+    Ok(upload)
+}
+
+/// Bytes received so far; the length of the partial file survives restarts
+/// and dropped connections.
+pub async fn offset(config: &Config, upload: &ResumableUpload) -> Result<u64, ResumableError> {
+    if upload.file_id.is_some() {
+        return Ok(upload.length);
+    }
+    match tokio::fs::metadata(partial_path(config, &upload.id)).await {
+        Ok(metadata) => Ok(metadata.len()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(ResumableError::NotFound),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Appends one PATCH body at `at` and returns the new offset. Bytes received
+/// before a dropped connection are kept so the client can resume, unless the
+/// chunk carries a checksum, in which case it is all or nothing. A body
+/// running past the announced length is rolled back too.
+pub async fn append<S, B, E>(
+    config: &Config,
+    upload: &ResumableUpload,
+    at: u64,
+    mut body: S,
+    checksum: Option<Vec<u8>>,
+) -> Result<u64, ResumableError>
+where
+    S: Stream<Item = Result<B, E>> + Unpin,
+    B: AsRef<[u8]>,
+    E: Display,
+{
+    let expected = offset(config, upload).await?;
+    if at != expected || upload.file_id.is_some() {
+        return Err(ResumableError::OffsetMismatch {
+            expected,
+            received: at,
+        });
+    }
+    let path = partial_path(config, &upload.id);
+    let mut file = tokio::fs::OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .await?;
+    let mut hasher = Sha256::new();
+    let mut written = at;
+    let all_or_nothing = checksum.is_some();
+    let result = async {
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk.map_err(|e| ResumableError::Invalid(e.to_string()))?;
+            let chunk = chunk.as_ref();
+            if written + chunk.len() as u64 > upload.length {
+                return Err(ResumableError::TooLarge(upload.length));
+            }
+            hasher.update(chunk);
+            file.write_all(chunk).await?;
+            written += chunk.len() as u64;
+        }
+        match checksum {
+            Some(checksum) if hasher.finalize().as_slice() != checksum => {
+                Err(ResumableError::ChecksumMismatch)
+            }
+            _ => Ok(()),
+        }
+    }
+    .await;
+    file.flush().await?;
+    match result {
+        Ok(()) => Ok(written),
+        Err(e) => {
+            if all_or_nothing || matches!(e, ResumableError::TooLarge(_)) {
+                file.set_len(at).await?;
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Verifies the whole-file checksum, stores the finished file like a direct
+/// upload and records which upload it became. New videos are processed in
+/// the background.
+pub async fn finish(
+    config: &Config,
+    upload: &ResumableUpload,
+) -> Result<(Upload, bool), ResumableError> {
+    let path = partial_path(config, &upload.id);
+    let (sha256, head) = hash_file(&path).await?;
+    if let Some(expected) = upload.metadata.get(CHECKSUM_KEY)
+        && !expected.eq_ignore_ascii_case(&sha256)
+    {
+        discard(config, &upload.id).await?;
+        return Err(ResumableError::ChecksumMismatch);
+    }
+    let stored = if let Some(kind) = sniff_video(&head) {
+        let stored = store_video(config, &path, sha256, kind, &upload.owner).await?;
+        if !stored.1 {
+            tokio::spawn(process_video(config.clone(), stored.0.clone()));
+        }
+        stored
+    } else {
+        // images are decoded in memory, so they get the limit of direct uploads
+        if upload.length > config.upload_max_bytes as u64 {
+            discard(config, &upload.id).await?;
+            return Err(ResumableError::TooLarge(config.upload_max_bytes as u64));
+        }
+        let data = tokio::fs::read(&path).await?;
+        let max_dimension = config.upload_max_dimension;
+        let image = match tokio::task::spawn_blocking(move || check_image(data, max_dimension))
+            .await
+            .map_err(anyhow::Error::from)?
+        {
+            Ok(image) => image,
+            Err(e) => {
+                discard(config, &upload.id).await?;
+                return Err(ResumableError::Invalid(e.to_string()));
+            }
+        };
+        let stored = store_upload(config, image, &upload.owner).await?;
+        tokio::fs::remove_file(&path).await?;
+        stored
+    };
+    let file_id = stored.0.id.clone();
+    write_store(|store| {
+        if let Some(upload) = store.resumable_uploads.get_mut(&upload.id) {
+            upload.file_id = Some(file_id);
+        }
+    })
+    .await;
+    Ok(stored)
+}
+
+/// SHA-256 of a file and its first bytes for sniffing the type.
+async fn hash_file(path: &Path) -> Result<(String, Vec<u8>), ResumableError> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut head = Vec::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        if head.is_empty() {
+            head = buffer[..read.min(32)].to_vec();
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok((hex::encode(hasher.finalize()), head))
+}
+
+/// Drops an upload and whatever was received of it.
+pub async fn discard(config: &Config, id: &str) -> Result<(), ResumableError> {
+    match tokio::fs::remove_file(partial_path(config, id)).await {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    write_store(|store| store.resumable_uploads.remove(id)).await;
+    Ok(())
+}
+
+/// Drops uploads untouched for `RESUMABLE_TTL_SECS`, finished or not, and
+/// partial files no upload refers to. Returns how many were dropped.
+pub async fn expire(config: &Config) -> Result<u64, ResumableError> {
+    if config.resumable_ttl_secs == 0 {
+        return Ok(0);
+    }
+    let now = chrono::Utc::now().timestamp();
+    let ttl = config.resumable_ttl_secs as i64;
+    let uploads = read_store(|store| {
+        store
+            .resumable_uploads
+            .values()
+            .map(|upload| (upload.id.clone(), upload.created_at))
+            .collect::<Vec<_>>()
+    })
+    .await;
+    let mut expired = 0;
+    let mut known = HashSet::new();
+    for (id, created_at) in uploads {
+        // every PATCH touches the partial file
+        let last_used = match tokio::fs::metadata(partial_path(config, &id)).await {
+            Ok(metadata) => modified_secs(&metadata).max(created_at),
+            Err(_) => created_at,
+        };
+        if now - last_used <= ttl || WRITING.lock().unwrap().contains(&id) {
+            known.insert(id);
+            continue;
+        }
+        discard(config, &id).await?;
+        expired += 1;
+    }
+
+    let mut dir = match tokio::fs::read_dir(&config.resumable_path).await {
+        Ok(dir) => dir,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(expired),
+        Err(e) => return Err(e.into()),
+    };
+    while let Some(entry) = dir.next_entry().await? {
+        let name = entry.file_name();
+        let Some(id) = name.to_str().and_then(|name| name.strip_suffix(".part")) else {
+            continue;
+        };
+        if known.contains(id) || now - modified_secs(&entry.metadata().await?) <= ttl {
+            continue;
+        }
+        tokio::fs::remove_file(entry.path()).await?;
+        expired += 1;
+    }
+    Ok(expired)
+}
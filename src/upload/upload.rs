@@ -0,0 +1,193 @@
+use std::io::Cursor;
+use std::path::Path;
+
+use image::{DynamicImage, ImageFormat, ImageReader, Limits};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    conf::config::Config,
+    storage::storage::output_storage_from_config,
+    store::{
+        models::{Upload, VideoInfo},
+        store::{read_store, write_store},
+    },
+};
+
+use super::exif;
+
+/// Image types accepted for upload, recognised by their magic bytes.
+const ALLOWED_FORMATS: [ImageFormat; 3] = [ImageFormat::Png, ImageFormat::Jpeg, ImageFormat::WebP];
+
+/// An uploaded image that passed validation.
+pub struct CheckedImage {
+    pub data: Vec<u8>,
+    pub format: ImageFormat,
+    pub width: u32,
+    pub height: u32,
+    /// SHA-256 of `data` after GPS stripping.
+    pub sha256: String,
+}
+
+/// Checks the type by magic bytes and the dimensions from the header, decodes
+/// the image to make sure it is complete, then wipes EXIF GPS coordinates.
+pub fn check_image(mut data: Vec<u8>, max_dimension: u32) -> anyhow::Result<CheckedImage> {
+    let format = image::guess_format(&data)
+        .ok()
+        .filter(|format| ALLOWED_FORMATS.contains(format))
+        .ok_or_else(|| anyhow::anyhow!("unsupported image type, expected PNG, JPEG or WebP"))?;
+    let (width, height) = ImageReader::with_format(Cursor::new(&data), format).into_dimensions()?;
+    if width > max_dimension || height > max_dimension {
+        anyhow::bail!(
+            "image is {}x{}, the limit is {}px per side",
+            width,
+            height,
+            max_dimension
+        );
+    }
+    ImageReader::with_format(Cursor::new(&data), format).decode()?;
+    exif::strip_gps(&mut data)?;
+    let sha256 = hex::encode(Sha256::digest(&data));
+    Ok(CheckedImage {
+        data,
+        format,
+        width,
+        height,
+        sha256,
+    })
+}
+
+/// Decodes an image of any readable type, refusing one whose header says it
+/// is larger than `max_dimension` per side before anything is allocated for
+/// its pixels.
+pub fn decode_image(data: &[u8], max_dimension: u32) -> anyhow::Result<DynamicImage> {
+    let (width, height) = ImageReader::new(Cursor::new(data))
+        .with_guessed_format()?
+        .into_dimensions()?;
+    if width > max_dimension || height > max_dimension {
+        anyhow::bail!(
+            "image is {}x{}, the limit is {}px per side",
+            width,
+            height,
+            max_dimension
+        );
+    }
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(max_dimension);
+    limits.max_image_height = Some(max_dimension);
+    // An 8-bit RGBA image at the size limit.
+    limits.max_alloc = Some(u64::from(max_dimension).pow(2) * 4);
+    let mut reader = ImageReader::new(Cursor::new(data)).with_guessed_format()?;
+    reader.limits(limits);
+    Ok(reader.decode()?)
+}
+
+/// Puts the image into the output storage as `{sha256}.{ext}` and records it,
+/// returning the existing record when the same content was uploaded before.
+pub async fn store_upload(
+    config: &Config,
+    image: CheckedImage,
+    uploaded_by: &str,
+) -> anyhow::Result<(Upload, bool)> {
+    let extension = image.format.extensions_str()[0];
+    let file_name = format!("{}.{}", image.sha256, extension);
+    let storage = output_storage_from_config(config)?;
+    let size = image.data.len() as u64;
+    let content_type = image.format.to_mime_type();
+    if storage.stat(&file_name).await?.is_none() {
+        storage.put(&file_name, image.data, content_type).await?;
+    }
+    let upload = Upload {
+        id: image.sha256,
+        file_name,
+        content_type: content_type.to_string(),
+        width: Some(image.width),
+        height: Some(image.height),
+        size,
+        uploaded_by: uploaded_by.to_string(),
+        created_at: chrono::Utc::now().timestamp(),
+        video: None,
+    };
+    Ok(write_store(|store| store.insert_upload(upload)).await)
+}
+
+/// HEIF brands share the MP4 container but hold still images.
+const HEIF_BRANDS: [&[u8]; 6] = [b"avif", b"avis", b"heic", b"heix", b"mif1", b"msf1"];
+
+/// Recognises MP4, QuickTime and WebM by their magic bytes and returns the
+/// file extension and content type.
+pub fn sniff_video(head: &[u8]) -> Option<(&'static str, &'static str)> {
+    if head.get(4..8) == Some(b"ftyp") {
+        let brand = head.get(8..12)?;
+        return match brand {
+            b"qt  " => Some(("mov", "video/quicktime")),
+            _ if HEIF_BRANDS.contains(&brand) => None,
+            _ => Some(("mp4", "video/mp4")),
+        };
+    }
+    head.starts_with(&[0x1A, 0x45, 0xDF, 0xA3])
+        .then_some(("webm", "video/webm"))
+}
+
+/// Stores a finished video in the output storage as `{sha256}.{ext}` and
+/// records it like [`store_upload`] does. The file is moved into
+/// `img_tmp_path` first, where ffmpeg reads it.
+pub async fn store_video(
+    config: &Config,
+    path: &Path,
+    sha256: String,
+    (extension, content_type): (&str, &str),
+    uploaded_by: &str,
+) -> anyhow::Result<(Upload, bool)> {
+    let file_name = format!("{}.{}", sha256, extension);
+    let target = Path::new(&config.img_tmp_path).join(&file_name);
+    let size = tokio::fs::metadata(path).await?.len();
+    if tokio::fs::try_exists(&target).await? {
+        tokio::fs::remove_file(path).await?;
+    } else if tokio::fs::rename(path, &target).await.is_err() {
+        // the partial files may live on another filesystem
+        tokio::fs::copy(path, &target).await?;
+        tokio::fs::remove_file(path).await?;
+    }
+    // with the local backend the move above already stored it
+    let storage = output_storage_from_config(config)?;
+    if storage.stat(&file_name).await?.is_none() {
+        let data = tokio::fs::read(&target).await?;
+        storage.put(&file_name, data, content_type).await?;
+    }
+    let upload = Upload {
+        id: sha256,
+        file_name,
+        content_type: content_type.to_string(),
+        width: None,
+        height: None,
+        size,
+        uploaded_by: uploaded_by.to_string(),
+        created_at: chrono::Utc::now().timestamp(),
+        video: Some(VideoInfo::default()),
+    };
+    Ok(write_store(|store| store.insert_upload(upload)).await)
+}
+
+/// Resolves a task id or an upload file id to the name and content type of
+/// its file.
+pub async fn image_file(config: &Config, id: &str) -> Option<(String, String)> {
+    if id.is_empty()
+        || !id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return None;
+    }
+    let task_file = format!("{}.png", id);
+    let storage = output_storage_from_config(config).ok()?;
+    if storage.stat(&task_file).await.ok().flatten().is_some() {
+        return Some((task_file, "image/png".to_string()));
+    }
+    read_store(|store| {
+        store
+            .uploads
+            .get(id)
+            .map(|upload| (upload.file_name.clone(), upload.content_type.clone()))
+    })
+    .await
+}
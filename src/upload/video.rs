@@ -0,0 +1,337 @@
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use anyhow::anyhow;
+use log::{error, info, warn};
+use serde::Deserialize;
+use tokio::process::Command;
+
+use crate::{
+    conf::config::Config,
+    storage::storage::{Storage, local_output, output_storage_from_config},
+    store::{
+        models::{Upload, VideoInfo, VideoStatus},
+        store::write_store,
+    },
+};
+
+/// Directory under `img_tmp_path`, and key prefix in the output storage,
+/// holding one HLS folder per video.
+pub const HLS_DIR: &str = "hls";
+/// Directory under `img_tmp_path` for ffmpeg's output in progress.
+const WORK_DIR: &str = ".video";
+pub const HLS_MASTER_PLAYLIST: &str = "master.m3u8";
+const HLS_SEGMENT_SECONDS: u32 = 6;
+
+#[derive(Debug, Deserialize)]
+struct Probe {
+    format: ProbeFormat,
+    #[serde(default)]
+    streams: Vec<ProbeStream>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProbeFormat {
+    duration: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProbeStream {
+    codec_type: Option<String>,
+    codec_name: Option<String>,
+    width: Option<u32>,
+    height: Option<u32>,
+}
+
+/// The parts of an ffprobe report we keep.
+#[derive(Debug, Default)]
+struct Probed {
+    duration: Option<f64>,
+    codec: Option<String>,
+    audio_codec: Option<String>,
+    width: u32,
+    height: u32,
+}
+
+/// Runs a tool to completion and returns its stdout, or the last line it
+/// wrote to stderr as the error.
+async fn run(program: &str, args: &[&str]) -> anyhow::Result<Vec<u8>> {
+    let output = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .map_err(|e| anyhow!("run {} failed: {}", program, e))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow!(
+            "{} exited with {}: {}",
+            program,
+            output.status,
+            stderr.lines().last().unwrap_or_default()
+        ));
+    }
+    Ok(output.stdout)
+}
+
+async fn probe(config: &Config, path: &str) -> anyhow::Result<Probed> {
+    let stdout = run(
+        &config.ffprobe_path,
+        &[
+            "-v",
+            "error",
+            "-print_format",
+            "json",
+            "-show_format",
+            "-show_streams",
+            path,
+        ],
+    )
+    .await?;
+    let probe: Probe = serde_json::from_slice(&stdout)?;
+    let stream = |kind: &str| {
+        probe
+            .streams
+            .iter()
+            .find(|s| s.codec_type.as_deref() == Some(kind))
+    };
+    let video = stream("video").ok_or_else(|| anyhow!("no video stream"))?;
+    Ok(Probed {
+        duration: probe.format.duration.and_then(|d| d.parse().ok()),
+        codec: video.codec_name.clone(),
+        audio_codec: stream("audio").and_then(|s| s.codec_name.clone()),
+        width: video.width.unwrap_or_default(),
+        height: video.height.unwrap_or_default(),
+    })
+}
+
+/// Grabs a frame one second in, or halfway through shorter clips.
+async fn extract_poster(
+    config: &Config,
+    path: &str,
+    duration: Option<f64>,
+    target: &Path,
+) -> anyhow::Result<()> {
+    let seek = duration.map_or(0.0, |d| (d / 2.0).min(1.0)).to_string();
+    run(
+        &config.ffmpeg_path,
+        &[
+            "-y",
+            "-v",
+            "error",
+            "-ss",
+            &seek,
+            "-i",
+            path,
+            "-frames:v",
+            "1",
+            "-q:v",
+            "3",
+            &target.to_string_lossy(),
+        ],
+    )
+    .await?;
+    Ok(())
+}
+
+/// Encodes one H.264 rendition per configured height not above the source,
+/// then writes a master playlist pointing at them.
+async fn transcode_hls(
+    config: &Config,
+    path: &str,
+    dir: &Path,
+    probed: &Probed,
+) -> anyhow::Result<()> {
+    if probed.width == 0 || probed.height == 0 {
+        return Err(anyhow!("unknown video size"));
+    }
+    let mut heights = config
+        .hls_renditions
+        .iter()
+        .copied()
+        .filter(|h| *h <= probed.height)
+        .collect::<Vec<_>>();
+    if heights.is_empty() {
+        heights.push(probed.height & !1);
+    }
+    tokio::fs::create_dir_all(dir).await?;
+    let mut master = String::from("#EXTM3U\n#EXT-X-VERSION:3\n");
+    for height in heights {
+        let name = format!("{}p", height);
+        let segments = dir.join(format!("{}_%04d.ts", name));
+        let playlist = dir.join(format!("{}.m3u8", name));
+        run(
+            &config.ffmpeg_path,
+            &[
+                "-y",
+                "-v",
+                "error",
+                "-i",
+                path,
+                "-vf",
+                &format!("scale=-2:{}", height),
+                "-c:v",
+                "libx264",
+                "-preset",
+                "veryfast",
+                "-crf",
+                "23",
+                "-c:a",
+                "aac",
+                "-b:a",
+                "128k",
+                "-f",
+                "hls",
+                "-hls_time",
+                &HLS_SEGMENT_SECONDS.to_string(),
+                "-hls_playlist_type",
+                "vod",
+                "-hls_segment_filename",
+                &segments.to_string_lossy(),
+                &playlist.to_string_lossy(),
+            ],
+        )
+        .await?;
+        let width = (probed.width as u64 * height as u64 / probed.height as u64) as u32 & !1;
+        let bandwidth = rendition_bandwidth(dir, &name, probed.duration).await?;
+        master.push_str(&format!(
+            "#EXT-X-STREAM-INF:BANDWIDTH={},RESOLUTION={}x{}\n{}.m3u8\n",
+            bandwidth, width, height, name
+        ));
+    }
+    tokio::fs::write(dir.join(HLS_MASTER_PLAYLIST), master).await?;
+    Ok(())
+}
+
+/// Average bits per second of a rendition's segments.
+async fn rendition_bandwidth(dir: &Path, name: &str, duration: Option<f64>) -> anyhow::Result<u64> {
+    let prefix = format!("{}_", name);
+    let mut bytes = 0;
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        if entry.file_name().to_string_lossy().starts_with(&prefix) {
+            bytes += entry.metadata().await?.len();
+        }
+    }
+    let seconds = duration.filter(|d| *d > 0.0).unwrap_or(1.0);
+    Ok((bytes as f64 * 8.0 / seconds) as u64)
+}
+
+/// Output storage key of a playlist or segment.
+pub fn hls_key(file_id: &str, name: &str) -> String {
+    format!("{}/{}/{}", HLS_DIR, file_id, name)
+}
+
+/// Scratch directory ffmpeg writes a video's poster and HLS output to before
+/// they are moved to the output storage. The janitor leaves it alone.
+fn work_dir(config: &Config, file_id: &str) -> PathBuf {
+    Path::new(&config.img_tmp_path).join(WORK_DIR).join(file_id)
+}
+
+/// Stores the playlists and segments ffmpeg wrote to `dir`, segments first
+/// and the master playlist last, so a listed file is always there.
+async fn store_hls(storage: &dyn Storage, dir: &Path, file_id: &str) -> anyhow::Result<()> {
+    let mut names = Vec::new();
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        names.push(entry.file_name().to_string_lossy().to_string());
+    }
+    names.sort_by_key(|name| (name == HLS_MASTER_PLAYLIST, name.ends_with(".m3u8")));
+    for name in names {
+        let content_type = if name.ends_with(".m3u8") {
+            "application/vnd.apple.mpegurl"
+        } else {
+            "video/mp2t"
+        };
+        let data = tokio::fs::read(dir.join(&name)).await?;
+        storage
+            .put(&hls_key(file_id, &name), data, content_type)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Extracts the poster and, when renditions are configured, the HLS output
+/// of a video into the output storage.
+async fn store_outputs(
+    config: &Config,
+    upload: &Upload,
+    path: &str,
+    probed: &Probed,
+    work_dir: &Path,
+) -> anyhow::Result<String> {
+    let storage = output_storage_from_config(config)?;
+    tokio::fs::create_dir_all(work_dir).await?;
+    let poster = format!("{}_poster.jpg", upload.id);
+    let poster_path = work_dir.join(&poster);
+    extract_poster(config, path, probed.duration, &poster_path).await?;
+    storage
+        .put(&poster, tokio::fs::read(&poster_path).await?, "image/jpeg")
+        .await?;
+    if !config.hls_renditions.is_empty() {
+        let hls_dir = work_dir.join(HLS_DIR);
+        transcode_hls(config, path, &hls_dir, probed).await?;
+        store_hls(storage.as_ref(), &hls_dir, &upload.id).await?;
+    }
+    Ok(poster)
+}
+
+/// Probes an uploaded video, extracts its poster frame and, when renditions
+/// are configured, transcodes it to HLS. The outcome lands on the upload and
+/// on every memory showing it.
+pub async fn process_video(config: Config, upload: Upload) {
+    let result = async {
+        let path = local_output(&config, &upload.file_name)
+            .await?
+            .ok_or_else(|| anyhow!("video {} not found", upload.file_name))?;
+        let path = path.to_string_lossy().to_string();
+        let probed = probe(&config, &path).await?;
+        let work_dir = work_dir(&config, &upload.id);
+        let stored = store_outputs(&config, &upload, &path, &probed, &work_dir).await;
+        if let Err(e) = tokio::fs::remove_dir_all(&work_dir).await {
+            warn!("remove {} failed: {}", work_dir.display(), e);
+        }
+        let poster = stored?;
+        let hls_url = if config.hls_renditions.is_empty() {
+            None
+        } else {
+            Some(format!(
+                "{}/{}/{}/{}",
+                config.public_base_url, HLS_DIR, upload.id, HLS_MASTER_PLAYLIST
+            ))
+        };
+        let video = VideoInfo {
+            status: VideoStatus::Ready,
+            duration: probed.duration,
+            codec: probed.codec.clone(),
+            audio_codec: probed.audio_codec.clone(),
+            poster_url: Some(format!("{}/{}", config.img_tmp_point, poster)),
+            hls_url,
+            error: None,
+        };
+        anyhow::Ok((video, probed))
+    }
+    .await;
+    match result {
+        Ok((video, probed)) => {
+            info!("video {} processed", upload.id);
+            write_store(|store| {
+                if let Some(upload) = store.uploads.get_mut(&upload.id) {
+                    upload.width = Some(probed.width);
+                    upload.height = Some(probed.height);
+                }
+                store.set_video(&upload.id, video);
+            })
+            .await;
+        }
+        Err(e) => {
+            error!("process video {} failed: {}", upload.id, e);
+            let video = VideoInfo {
+                status: VideoStatus::Failed,
+                error: Some(e.to_string()),
+                ..Default::default()
+            };
+            write_store(|store| store.set_video(&upload.id, video)).await;
+        }
+    }
+}
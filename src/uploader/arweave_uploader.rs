@@ -0,0 +1,133 @@
+use async_trait::async_trait;
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use reqwest::Client;
+use rsa::{
+    BigUint, RsaPrivateKey,
+    pss::SigningKey,
+    rand_core::OsRng,
+    signature::{RandomizedSigner, SignatureEncoding},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use crate::conf::config::Config;
+
+use super::uploader::PermanentUploader;
+
+/// Posts format 1 transactions (data inline) to an Arweave node or arlocal.
+pub struct ArweaveUploader {
+    client: Client,
+    node: String,
+    key: RsaPrivateKey,
+    owner: Vec<u8>,
+}
+
+/// An Arweave wallet in JWK form, all fields base64url.
+#[derive(Debug, Deserialize)]
+struct Jwk {
+    n: String,
+    e: String,
+    d: String,
+    p: String,
+    q: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Tag {
+    name: String,
+    value: String,
+}
+
+impl ArweaveUploader {
+    pub fn from_config(config: &Config) -> anyhow::Result<Self> {
+        let wallet_path = config
+            .arweave_wallet_path
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("ARWEAVE_WALLET_PATH is required for arweave"))?;
+        let jwk: Jwk = serde_json::from_str(&std::fs::read_to_string(wallet_path)?)?;
+        let component = |value: &str| -> anyhow::Result<BigUint> {
+            Ok(BigUint::from_bytes_be(&URL_SAFE_NO_PAD.decode(value)?))
+        };
+        let key = RsaPrivateKey::from_components(
+            component(&jwk.n)?,
+            component(&jwk.e)?,
+            component(&jwk.d)?,
+            vec![component(&jwk.p)?, component(&jwk.q)?],
+        )?;
+        Ok(ArweaveUploader {
+            client: Client::new(),
+            node: config.arweave_node.trim_end_matches('/').to_string(),
+            key,
+            owner: URL_SAFE_NO_PAD.decode(&jwk.n)?,
+        })
+    }
+}
+
+#[async_trait]
+impl PermanentUploader for ArweaveUploader {
+    async fn upload(&self, data: Vec<u8>, content_type: &str) -> anyhow::Result<String> {
+        let last_tx = self
+            .client
+            .get(format!("{}/tx_anchor", self.node))
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        let reward = self
+            .client
+            .get(format!("{}/price/{}", self.node, data.len()))
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        let tags = [("Content-Type", content_type), ("App-Name", "Chroniq")];
+        let quantity = "0";
+
+        // format 1 signature data: owner, target, data, quantity, reward, last_tx, tags
+        let mut signature_data = Vec::new();
+        signature_data.extend_from_slice(&self.owner);
+        signature_data.extend_from_slice(&data);
+        signature_data.extend_from_slice(quantity.as_bytes());
+        signature_data.extend_from_slice(reward.as_bytes());
+        signature_data.extend_from_slice(&URL_SAFE_NO_PAD.decode(&last_tx)?);
+        for (name, value) in tags {
+            signature_data.extend_from_slice(name.as_bytes());
+            signature_data.extend_from_slice(value.as_bytes());
+        }
+        let signature = SigningKey::<Sha256>::new(self.key.clone())
+            .sign_with_rng(&mut OsRng, &signature_data)
+            .to_bytes();
+        let id = URL_SAFE_NO_PAD.encode(Sha256::digest(&signature));
+
+        let transaction = json!({
+            "format": 1,
+            "id": id,
+            "last_tx": last_tx,
+            "owner": URL_SAFE_NO_PAD.encode(&self.owner),
+            "tags": tags
+                .iter()
+                .map(|(name, value)| Tag {
+                    name: URL_SAFE_NO_PAD.encode(name),
+                    value: URL_SAFE_NO_PAD.encode(value),
+                })
+                .collect::<Vec<_>>(),
+            "target": "",
+            "quantity": quantity,
+            "data": URL_SAFE_NO_PAD.encode(&data),
+            "data_size": data.len().to_string(),
+            "data_root": "",
+            "reward": reward,
+            "signature": URL_SAFE_NO_PAD.encode(&signature),
+        });
+        self.client
+            .post(format!("{}/tx", self.node))
+            .json(&transaction)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(format!("ar://{}", id))
+    }
+}
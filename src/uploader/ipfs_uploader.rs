@@ -0,0 +1,46 @@
+use async_trait::async_trait;
+use reqwest::{Client, multipart};
+use serde::Deserialize;
+
+use super::uploader::PermanentUploader;
+
+/// Adds and pins content through the HTTP RPC API of a Kubo node.
+pub struct IpfsUploader {
+    client: Client,
+    api: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct AddResponse {
+    #[serde(rename = "Hash")]
+    hash: String,
+}
+
+impl IpfsUploader {
+    pub fn new(api: &str) -> Self {
+        IpfsUploader {
+            client: Client::new(),
+            api: api.trim_end_matches('/').to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl PermanentUploader for IpfsUploader {
+    async fn upload(&self, data: Vec<u8>, content_type: &str) -> anyhow::Result<String> {
+        let part = multipart::Part::bytes(data)
+            .file_name("file")
+            .mime_str(content_type)?;
+        let form = multipart::Form::new().part("file", part);
+        let url = format!("{}/api/v0/add?pin=true&cid-version=1", self.api);
+        let res = self
+            .client
+            .post(&url)
+            .multipart(form)
+            .send()
+            .await?
+            .error_for_status()?;
+        let added: AddResponse = res.json().await?;
+        Ok(format!("ipfs://{}", added.hash))
+    }
+}
@@ -0,0 +1,4 @@
+pub mod arweave_uploader;
+pub mod ipfs_uploader;
+#[allow(clippy::module_inception)]
+pub mod uploader;
\ No newline at end of file
@@ -0,0 +1,36 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use crate::conf::config::Config;
+
+use super::{arweave_uploader::ArweaveUploader, ipfs_uploader::IpfsUploader};
+
+/// Pushes content to permanent, content-addressed storage.
+#[async_trait]
+pub trait PermanentUploader: Send + Sync {
+    /// Uploads `data` and returns its content URI (`ipfs://...` or `ar://...`).
+    async fn upload(&self, data: Vec<u8>, content_type: &str) -> anyhow::Result<String>;
+}
+
+/// Builds the uploader selected by `UPLOADER`, or `None` when generated images
+/// should keep pointing at `img_tmp_point`.
+pub fn uploader_from_config(config: &Config) -> anyhow::Result<Option<Arc<dyn PermanentUploader>>> {
+    match config.uploader.as_str() {
+        "none" => Ok(None),
+        "ipfs" => Ok(Some(Arc::new(IpfsUploader::new(&config.ipfs_api)))),
+        "arweave" => Ok(Some(Arc::new(ArweaveUploader::from_config(config)?))),
+        other => Err(anyhow::anyhow!("unknown uploader: {}", other)),
+    }
+}
+
+/// Turns a content URI into an HTTP URL through the configured gateways.
+pub fn resolve_content_uri(uri: &str, config: &Config) -> String {
+    if let Some(cid) = uri.strip_prefix("ipfs://") {
+        format!("{}/ipfs/{}", config.ipfs_gateway.trim_end_matches('/'), cid)
+    } else if let Some(tx_id) = uri.strip_prefix("ar://") {
+        format!("{}/{}", config.arweave_gateway.trim_end_matches('/'), tx_id)
+    } else {
+        uri.to_string()
+    }
+}
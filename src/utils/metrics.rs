@@ -0,0 +1,104 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+enum Kind {
+    Counter,
+    Gauge,
+}
+
+/// A process-wide counter or gauge exposed on `/metrics`.
+pub struct Metric {
+    name: &'static str,
+    help: &'static str,
+    kind: Kind,
+    value: AtomicU64,
+}
+
+impl Metric {
+    const fn counter(name: &'static str, help: &'static str) -> Self {
+        Metric {
+            name,
+            help,
+            kind: Kind::Counter,
+            value: AtomicU64::new(0),
+        }
+    }
+
+    const fn gauge(name: &'static str, help: &'static str) -> Self {
+        Metric {
+            name,
+            help,
+            kind: Kind::Gauge,
+            value: AtomicU64::new(0),
+        }
+    }
+
+    pub fn add(&self, n: u64) {
+        self.value.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn set(&self, value: u64) {
+        self.value.store(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.value.load(Ordering::Relaxed)
+    }
+}
+
+pub static JANITOR_RUNS: Metric = Metric::counter(
+    "chroniq_janitor_runs_total",
+    "Janitor passes over the temp image directory.",
+);
+pub static JANITOR_FILES_DELETED: Metric = Metric::counter(
+    "chroniq_janitor_files_deleted_total",
+    "Temp files and HLS folders deleted by the janitor.",
+);
+pub static JANITOR_BYTES_RECLAIMED: Metric = Metric::counter(
+    "chroniq_janitor_bytes_reclaimed_total",
+    "Bytes freed by the janitor.",
+);
+pub static TEMP_DIR_BYTES: Metric = Metric::gauge(
+    "chroniq_temp_dir_bytes",
+    "Size of the temp image directory after the last janitor pass.",
+);
+pub static TEMP_DIR_PINNED_BYTES: Metric = Metric::gauge(
+    "chroniq_temp_dir_pinned_bytes",
+    "Bytes of published or minted files the janitor keeps.",
+);
+pub static STORE_PERSIST_FAILURES: Metric = Metric::counter(
+    "chroniq_store_persist_failures_total",
+    "Failed writes of the store snapshot.",
+);
+pub static STORE_UNPERSISTED: Metric = Metric::gauge(
+    "chroniq_store_unpersisted",
+    "1 while the last store write failed and changes only live in memory.",
+);
+
+const METRICS: [&Metric; 7] = [
+    &JANITOR_RUNS,
+    &JANITOR_FILES_DELETED,
+    &JANITOR_BYTES_RECLAIMED,
+    &TEMP_DIR_BYTES,
+    &TEMP_DIR_PINNED_BYTES,
+    &STORE_PERSIST_FAILURES,
+    &STORE_UNPERSISTED,
+];
+
+/// All metrics in the Prometheus text exposition format.
+pub fn render() -> String {
+    let mut text = String::new();
+    for metric in METRICS {
+        let kind = match metric.kind {
+            Kind::Counter => "counter",
+            Kind::Gauge => "gauge",
+        };
+        text.push_str(&format!(
+            "# HELP {name} {}\n# TYPE {name} {}\n{name} {}\n",
+            metric.help,
+            kind,
+            metric.value.load(Ordering::Relaxed),
+            name = metric.name,
+        ));
+    }
+    text
+}
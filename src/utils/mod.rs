@@ -1 +1,3 @@
+pub mod metrics;
+pub mod page;
 pub mod result;
\ No newline at end of file
@@ -0,0 +1,101 @@
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_PAGE_SIZE: usize = 20;
+const MAX_PAGE_SIZE: usize = 100;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PageQuery {
+    pub page: Option<usize>,
+    pub page_size: Option<usize>,
+    /// `asc` or `desc` (default).
+    pub order: Option<String>,
+}
+
+impl PageQuery {
+    pub fn ascending(&self) -> bool {
+        self.order.as_deref() == Some("asc")
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: usize,
+    pub page: usize,
+    pub page_size: usize,
+}
+
+impl<T> Page<T> {
+    /// Cuts one page out of already ordered items; pages start at 1.
+    pub fn paginate(items: Vec<T>, query: &PageQuery) -> Self {
+        let page = query.page.unwrap_or(1).max(1);
+        let page_size = query
+            .page_size
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE);
+        let total = items.len();
+        let items = items
+            .into_iter()
+            .skip((page - 1) * page_size)
+            .take(page_size)
+            .collect();
+        Page {
+            items,
+            total,
+            page,
+            page_size,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CursorQuery {
+    /// `next_cursor` of the previous page.
+    pub cursor: Option<String>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CursorPage<T> {
+    pub items: Vec<T>,
+    /// Absent on the last page.
+    pub next_cursor: Option<String>,
+}
+
+impl<T> CursorPage<T> {
+    /// Takes the items after the cursor from items ordered newest first by
+    /// `key` (time, id). Unlike page numbers, cursors stay stable while new
+    /// items arrive at the top.
+    pub fn paginate(items: Vec<T>, query: &CursorQuery, key: impl Fn(&T) -> (i64, String)) -> Self {
+        let limit = query
+            .limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE);
+        let after = query.cursor.as_deref().and_then(|cursor| {
+            let (time, id) = cursor.split_once('.')?;
+            Some((time.parse::<i64>().ok()?, id.to_string()))
+        });
+        let mut items = items
+            .into_iter()
+            .filter(|item| after.as_ref().is_none_or(|after| &key(item) < after))
+            .take(limit + 1)
+            .collect::<Vec<_>>();
+        let next_cursor = if items.len() > limit {
+            items.truncate(limit);
+            items.last().map(|item| {
+                let (time, id) = key(item);
+                format!("{}.{}", time, id)
+            })
+        } else {
+            None
+        };
+        CursorPage { items, next_cursor }
+    }
+
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> CursorPage<U> {
+        CursorPage {
+            items: self.items.into_iter().map(f).collect(),
+            next_cursor: self.next_cursor,
+        }
+    }
+}
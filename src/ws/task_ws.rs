@@ -6,10 +6,12 @@ use futures_util::StreamExt;
 use log::{error, info};
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, broadcast};
 use tokio_tungstenite::connect_async;
 
 use crate::conf::config;
+use crate::store::models::{Task, TaskLineage};
+use crate::store::store::{read_store, write_store};
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub enum TaskStatus {
@@ -27,20 +29,113 @@ pub enum TaskStatus {
 static GLOBAL_TASK_STATUS: Lazy<Arc<Mutex<HashMap<String, TaskStatus>>>> =
     Lazy::new(|| Arc::new(Mutex::new(HashMap::new())));
 
+/// The live status, or the last one stored for tasks of an earlier run.
 pub async fn get_task_status(task_id: &str) -> Option<TaskStatus> {
-    let map = GLOBAL_TASK_STATUS.lock().await;
-    map.get(task_id).cloned()
+    let status = GLOBAL_TASK_STATUS.lock().await.get(task_id).cloned();
+    match status {
+        Some(status) => Some(status),
+        None => read_store(|store| store.tasks.get(task_id).map(|t| t.status.clone())).await,
+    }
 }
 
 pub async fn update_task_status(task_id: &str, new_status: TaskStatus) -> bool {
-    let mut map = GLOBAL_TASK_STATUS.lock().await;
-    if let Some(status) = map.get_mut(task_id) {
-        *status = new_status;
-        true
-    } else {
-        map.insert(task_id.to_string(), new_status);
-        true
+    let changed = {
+        let mut map = GLOBAL_TASK_STATUS.lock().await;
+        map.insert(task_id.to_string(), new_status.clone()) != Some(new_status.clone())
+    };
+    if changed && read_store(|store| store.tasks.contains_key(task_id)).await {
+        let now = chrono::Utc::now().timestamp();
+        write_store(|store| {
+            if let Some(task) = store.tasks.get_mut(task_id) {
+                task.status = new_status.clone();
+                task.updated_at = now;
+            }
+        })
+        .await;
+    }
+    if changed {
+        publish_task_event(TaskEvent {
+            task_id: task_id.to_string(),
+            kind: TaskKind::Imagine,
+            status: new_status,
+            data: None,
+            author: get_task_params(task_id).await.map(|p| p.author),
+        });
     }
+    true
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskKind {
+    Imagine,
+    Export,
+}
+
+/// A status change of a generation or export task.
+#[derive(Debug, Serialize, Clone)]
+pub struct TaskEvent {
+    pub task_id: String,
+    pub kind: TaskKind,
+    pub status: TaskStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<serde_json::Value>,
+    /// Only the author is sent the event.
+    #[serde(skip)]
+    pub author: Option<String>,
+}
+
+static TASK_EVENTS: Lazy<broadcast::Sender<TaskEvent>> = Lazy::new(|| broadcast::channel(256).0);
+
+pub fn publish_task_event(event: TaskEvent) {
+    // no subscribers is not an error
+    let _ = TASK_EVENTS.send(event);
+}
+
+pub fn subscribe_task_events() -> broadcast::Receiver<TaskEvent> {
+    TASK_EVENTS.subscribe()
+}
+
+/// Generation parameters of a submitted task.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TaskParams {
+    pub prompt: String,
+    pub style: String,
+    pub steps: i32,
+    pub seed: u32,
+    pub author: String,
+    pub model: String,
+}
+
+pub async fn get_task_params(task_id: &str) -> Option<TaskParams> {
+    read_store(|store| store.tasks.get(task_id).map(|t| t.params.clone())).await
+}
+
+/// Stores a newly submitted task.
+pub async fn record_task(task_id: &str, params: TaskParams, lineage: Option<TaskLineage>) {
+    let now = chrono::Utc::now().timestamp();
+    let task = Task {
+        id: task_id.to_string(),
+        params,
+        status: TaskStatus::Submited,
+        lineage,
+        created_at: now,
+        updated_at: now,
+    };
+    write_store(|store| store.tasks.insert(task_id.to_string(), task)).await;
+}
+
+/// Ids and parameters of the tasks an author submitted.
+pub async fn tasks_by_author(author: &str) -> Vec<(String, TaskParams)> {
+    read_store(|store| {
+        store
+            .tasks
+            .values()
+            .filter(|t| t.params.author == author)
+            .map(|t| (t.id.clone(), t.params.clone()))
+            .collect()
+    })
+    .await
 }
 
 pub async fn ws_connect(config: Arc<config::Config>) -> anyhow::Result<()> {